@@ -1,6 +1,8 @@
 #!/usr/bin/env python3
 import json
 import os
+import platform
+import re
 import sys
 import threading
 import time
@@ -8,9 +10,124 @@ import requests
 from typing import Any, Dict, Optional, List
 
 
+_send_lock = threading.Lock()
+
+
 def _send(obj: Dict[str, Any]) -> None:
-    sys.stdout.write(json.dumps(obj, ensure_ascii=False) + "\n")
-    sys.stdout.flush()
+    line = json.dumps(obj, ensure_ascii=False) + "\n"
+    with _send_lock:
+        sys.stdout.write(line)
+        sys.stdout.flush()
+
+def _total_ram_bytes() -> Optional[int]:
+    try:
+        if sys.platform == "win32":
+            import ctypes
+
+            class _MemoryStatus(ctypes.Structure):
+                _fields_ = [
+                    ("dwLength", ctypes.c_ulong),
+                    ("dwMemoryLoad", ctypes.c_ulong),
+                    ("ullTotalPhys", ctypes.c_ulonglong),
+                    ("ullAvailPhys", ctypes.c_ulonglong),
+                    ("ullTotalPageFile", ctypes.c_ulonglong),
+                    ("ullAvailPageFile", ctypes.c_ulonglong),
+                    ("ullTotalVirtual", ctypes.c_ulonglong),
+                    ("ullAvailVirtual", ctypes.c_ulonglong),
+                    ("ullAvailExtendedVirtual", ctypes.c_ulonglong),
+                ]
+
+            status = _MemoryStatus()
+            status.dwLength = ctypes.sizeof(_MemoryStatus)
+            if not ctypes.windll.kernel32.GlobalMemoryStatusEx(ctypes.byref(status)):
+                return None
+            return int(status.ullTotalPhys)
+        return int(os.sysconf("SC_PAGE_SIZE") * os.sysconf("SC_PHYS_PAGES"))
+    except Exception:
+        return None
+
+
+def _collect_capabilities() -> Dict[str, Any]:
+    """Versions/devices reported to the Rust side for compatibility debugging."""
+
+    from importlib import metadata
+
+    versions: Dict[str, str] = {"python": platform.python_version()}
+    for dist in ("torch", "transformers", "huggingface_hub", "requests"):
+        try:
+            versions[dist] = metadata.version(dist)
+        except Exception:
+            pass
+
+    warnings: List[str] = []
+    devices: List[str] = ["cpu"]
+    memory: Dict[str, Optional[int]] = {"ram_bytes": _total_ram_bytes(), "vram_bytes": None}
+
+    if "transformers" not in versions:
+        warnings.append("transformers is not installed; generation will fail")
+
+    try:
+        import torch
+
+        if torch.cuda.is_available():
+            devices.append("cuda")
+            memory["vram_bytes"] = int(torch.cuda.get_device_properties(0).total_memory)
+        if torch.backends.mps.is_available():
+            devices.append("mps")
+
+        if torch.version.cuda is None and sys.platform != "darwin":
+            warnings.append("torch was built without CUDA support; generation will run on CPU")
+        elif torch.version.cuda is not None and not torch.cuda.is_available():
+            warnings.append(f"torch was built with CUDA {torch.version.cuda} but no CUDA device is available")
+    except Exception as e:
+        warnings.append(f"torch could not be imported: {e}")
+
+    return {
+        "versions": versions,
+        "devices": devices,
+        "warnings": warnings,
+        "features": _runner_features(),
+        "memory": memory,
+        "architectures": _supported_architectures(),
+    }
+
+
+def _runner_features() -> List[str]:
+    """Optional protocol features. Found without importing anything, so `ready`
+    can carry them before the first request is read."""
+    from importlib.util import find_spec
+
+    features: List[str] = ["prefill", "validate", "count_tokens", "unload", "stop", "messages"]
+    if find_spec("lmformatenforcer") is not None:
+        features.append("response_format")
+    if find_spec("PIL") is not None:
+        features.append("images")
+    return features
+
+
+def _supported_architectures() -> List[str]:
+    """Model classes (as named in config.json `architectures`) that
+    AutoModelForCausalLM can load with the installed transformers."""
+    try:
+        from transformers.models.auto.modeling_auto import MODEL_FOR_CAUSAL_LM_MAPPING_NAMES
+    except Exception:
+        return []
+
+    names = set()
+    for value in MODEL_FOR_CAUSAL_LM_MAPPING_NAMES.values():
+        if isinstance(value, str):
+            names.add(value)
+        else:
+            names.update(v for v in value if isinstance(v, str))
+    return sorted(names)
+
+
+def _send_capabilities() -> None:
+    try:
+        _send({"type": "capabilities", **_collect_capabilities()})
+    except Exception as e:
+        print(f"Failed to collect capabilities: {e}", file=sys.stderr)
+
 
 def _import_hf_hub():
     try:
@@ -46,6 +163,292 @@ def _ensure_parent_dir(path: str) -> None:
         os.makedirs(parent, exist_ok=True)
 
 
+def _free_memory() -> None:
+    try:
+        import gc
+        import torch
+
+        gc.collect()
+        if torch.cuda.is_available():
+            torch.cuda.empty_cache()
+    except Exception:
+        pass
+
+
+def _load_image(spec: Dict[str, Any]):
+    """Decode one image as sent by Cerebro: `{"path": ...}` or `{"data": <base64>}`."""
+    import base64
+    import io
+
+    from PIL import Image
+
+    if isinstance(spec.get("path"), str):
+        image = Image.open(spec["path"])
+    else:
+        image = Image.open(io.BytesIO(base64.b64decode(spec.get("data") or "")))
+    return image.convert("RGB")
+
+
+def _user_turn(prompt: str, images: Optional[List[Any]] = None) -> Dict[str, Any]:
+    content: List[Dict[str, Any]] = [{"type": "image", "image": image} for image in images or []]
+    content.append({"type": "text", "text": prompt})
+    return {"role": "user", "content": content}
+
+
+def _caller_turns(turns: List[Dict[str, Any]], system: Dict[str, Any]) -> List[Dict[str, Any]]:
+    """A conversation sent by the caller, as content-part messages behind the
+    default `system` turn unless it brings its own."""
+    messages = [{"role": t["role"], "content": [{"type": "text", "text": t["content"]}]} for t in turns]
+    if not any(t["role"] == "system" for t in turns):
+        messages.insert(0, system)
+    return messages
+
+
+def _conversation(
+    history: List[Dict[str, Any]],
+    turns: Optional[List[Dict[str, Any]]],
+    prompt: str,
+    images: Optional[List[Any]] = None,
+) -> List[Dict[str, Any]]:
+    """The messages to feed the model: `turns` when the caller sent them, else
+    `history`, with `prompt` as the final user turn. Alongside `turns` an empty
+    prompt adds no turn and the images join the last user turn instead."""
+    if turns is None:
+        return history + [_user_turn(prompt, images)]
+    messages = _caller_turns(turns, history[0])
+    if prompt:
+        return messages + [_user_turn(prompt, images)]
+    if images:
+        for msg in reversed(messages):
+            if msg["role"] == "user":
+                msg["content"] = [{"type": "image", "image": image} for image in images] + msg["content"]
+                break
+    return messages
+
+
+def _parse_turns(raw: Any) -> Optional[List[Dict[str, Any]]]:
+    """The well-formed `messages` of a request, or None when there are none."""
+    if not isinstance(raw, list):
+        return None
+    turns = [
+        {"role": t["role"], "content": t["content"]}
+        for t in raw
+        if isinstance(t, dict) and t.get("role") in ("system", "user", "assistant") and isinstance(t.get("content"), str)
+    ]
+    return turns or None
+
+
+def _chat_inputs(processor, model_id_norm: str, messages: List[Dict[str, Any]], prefill: Optional[str]):
+    """Apply the chat template to `messages`, opening an assistant turn that
+    holds `prefill` when given."""
+    # Adapt messages if model is Qwen, which do not accept list on content
+    updated_messages = []
+    
+    if "qwen" in model_id_norm.lower():
+        for msg in messages:
+            if isinstance(msg["content"], list):
+                combined_text = " ".join([part["text"] for part in msg["content"] if part.get("type") == "text"])
+                updated_messages.append({
+                    "role": msg["role"],
+                    "content": combined_text
+                })
+            else:
+                updated_messages.append(msg)
+        updated_messages
+    else:
+        updated_messages = messages
+
+    # Aplica o chat template
+    if prefill:
+        # Open an assistant turn holding the prefill and let the model
+        # continue it; skip_prompt keeps the prefill out of the stream.
+        if "qwen" in model_id_norm.lower():
+            prefill_msg = {"role": "assistant", "content": prefill}
+        else:
+            prefill_msg = {"role": "assistant", "content": [{"type": "text", "text": prefill}]}
+        inputs = processor.apply_chat_template(
+            updated_messages + [prefill_msg],
+            continue_final_message=True,
+            tokenize=True,
+            return_dict=True,
+            return_tensors="pt",
+        )
+    else:
+        inputs = processor.apply_chat_template(
+            updated_messages,
+            add_generation_prompt=True,
+            tokenize=True,
+            return_dict=True,
+            return_tensors="pt",
+        )
+    return inputs
+
+
+class _StopMatcher:
+    """Finds stop sequences in streamed text, holding back any tail that could
+    still turn into one so it is never streamed."""
+
+    def __init__(self, stops: List[str]) -> None:
+        self.stops = stops
+        self.pending = ""
+        self.hit: Optional[str] = None
+
+    def feed(self, text: str) -> str:
+        """Add `text` and return what is now safe to emit."""
+        self.pending += text
+        matches = [(self.pending.find(s), s) for s in self.stops if s in self.pending]
+        if matches:
+            index, self.hit = min(matches)
+            out, self.pending = self.pending[:index], ""
+            return out
+        keep = max(
+            (k for s in self.stops for k in range(1, len(s)) if self.pending.endswith(s[:k])),
+            default=0,
+        )
+        cut = len(self.pending) - keep
+        out, self.pending = self.pending[:cut], self.pending[cut:]
+        return out
+
+    def flush(self) -> str:
+        out, self.pending = self.pending, ""
+        return out
+
+
+_SIZE_UNITS = {"B": 1, "KB": 1000, "MB": 1000**2, "GB": 1000**3, "TB": 1000**4,
+               "KIB": 1024, "MIB": 1024**2, "GIB": 1024**3, "TIB": 1024**4}
+
+
+def _parse_size(value: str, unit: str) -> int:
+    return int(float(value) * _SIZE_UNITS.get(unit.upper(), 1))
+
+
+def _oom_details(e: BaseException) -> Optional[Dict[str, Any]]:
+    """Classify `e` as an out-of-memory error. Returns the requested and
+    available byte counts the backend reported (possibly neither), or None
+    when `e` is some other error."""
+    text = str(e)
+    if not (
+        isinstance(e, MemoryError)
+        or type(e).__name__ == "OutOfMemoryError"
+        or "out of memory" in text.lower()
+    ):
+        return None
+
+    details: Dict[str, Any] = {}
+    size = r"([\d.]+) ?([KMGT]i?B|B)"
+    requested = re.search(r"Tried to allocate " + size, text)
+    if requested:
+        details["requested_bytes"] = _parse_size(*requested.groups())
+    # CUDA: "... 1.50 GiB free"; MPS only reports its ceiling: "max allowed: 18.13 GB".
+    available = re.search(size + r" (?:is )?free", text) or re.search(r"max allowed: " + size, text)
+    if available:
+        details["available_bytes"] = _parse_size(*available.groups())
+    return details
+
+
+class LoadCancelled(Exception):
+    """Raised when a generation is cancelled before its model finished loading."""
+
+
+class DownloadPaused(Exception):
+    """Raised inside a download when the host asked to pause it."""
+
+
+class DownloadCancelled(Exception):
+    """Raised inside a download when the host cancelled it."""
+
+
+# Written into a download's local_dir when it stops early, listing the files that
+# finished so a later download of the same repo can skip them.
+DOWNLOAD_STATE_FILE = ".cerebro_download.json"
+
+
+def _read_download_state(local_dir: str, repo_id: str) -> List[str]:
+    try:
+        with open(os.path.join(local_dir, DOWNLOAD_STATE_FILE), "r", encoding="utf-8") as f:
+            state = json.load(f)
+    except Exception:
+        return []
+    if not isinstance(state, dict) or state.get("repo_id") != repo_id:
+        return []
+    completed = state.get("completed")
+    return [c for c in completed if isinstance(c, str)] if isinstance(completed, list) else []
+
+
+def _write_download_state(
+    local_dir: str, repo_id: str, completed: List[str], partial: Optional[str]
+) -> None:
+    try:
+        with open(os.path.join(local_dir, DOWNLOAD_STATE_FILE), "w", encoding="utf-8") as f:
+            json.dump({"repo_id": repo_id, "completed": completed, "partial": partial}, f)
+    except Exception as e:
+        print(f"Failed to write download state in {local_dir}: {e}", file=sys.stderr)
+
+
+def _clear_download_state(local_dir: str) -> None:
+    try:
+        os.remove(os.path.join(local_dir, DOWNLOAD_STATE_FILE))
+    except FileNotFoundError:
+        pass
+    except Exception as e:
+        print(f"Failed to remove download state in {local_dir}: {e}", file=sys.stderr)
+
+
+def _present_bytes(local_dir: str, files: List[str]) -> int:
+    total = 0
+    for filename in files:
+        try:
+            total += os.path.getsize(os.path.join(local_dir, filename))
+        except OSError:
+            pass
+    return total
+
+
+def _discard_files(local_dir: str, files: List[str]) -> None:
+    """Deletes this repo's files from local_dir, then any directories left empty."""
+    for filename in files:
+        try:
+            os.remove(os.path.join(local_dir, filename))
+        except FileNotFoundError:
+            pass
+        except Exception as e:
+            print(f"Failed to discard {filename}: {e}", file=sys.stderr)
+    _clear_download_state(local_dir)
+    for root, _dirs, _files in os.walk(local_dir, topdown=False):
+        try:
+            os.rmdir(root)
+        except OSError:
+            pass
+
+
+class DownloadHTTPError(RuntimeError):
+    """An HTTP error status while fetching a file, with the response kept for
+    classifying it."""
+
+    def __init__(self, message: str, response) -> None:
+        super().__init__(message)
+        self.response = response
+
+
+def _auth_error_details(e: Exception, repo_id: str, token: Optional[str], endpoint: Optional[str]) -> Dict[str, Any]:
+    """Extra `download_error` fields for a 401/403: `reason` is `gated` when the
+    repo's license hasn't been accepted, `invalid_token` when the token was
+    refused, and `unauthorized` when none was sent."""
+    response = getattr(e, "response", None)
+    status = getattr(response, "status_code", None)
+    if status not in (401, 403):
+        return {}
+    try:
+        code = response.headers.get("X-Error-Code") or ""
+        text = (response.text or "")[:500].lower()
+    except Exception:
+        code, text = "", ""
+    if code == "GatedRepo" or "gated" in text or "restricted" in text:
+        base = (endpoint or "https://huggingface.co").rstrip("/")
+        return {"reason": "gated", "status": status, "license_url": f"{base}/{repo_id}"}
+    return {"reason": "invalid_token" if token else "unauthorized", "status": status}
+
+
 def _download_streaming(
     *,
     url: str,
@@ -55,6 +458,8 @@ def _download_streaming(
     cancel_event: threading.Event,
     on_progress,
     overall_base: int,
+    extra_headers: Optional[Dict[str, str]] = None,
+    pause_event: Optional[threading.Event] = None,
 ) -> int:
     """Download a single file with streaming + resume.
 
@@ -83,6 +488,7 @@ def _download_streaming(
             existing = 0
 
     headers = {
+        **(extra_headers or {}),
         # Avoid transparent decompression changing byte counts
         "Accept-Encoding": "identity",
         "User-Agent": "cerebro/0.1 (tauri; python)",
@@ -126,12 +532,17 @@ def _download_streaming(
                 msg = f"HTTP {resp.status_code}: {resp.text[:200]}"
             except Exception:
                 pass
-            raise RuntimeError(f"HTTP error downloading file: {msg}")
+            raise DownloadHTTPError(f"HTTP error downloading file: {msg}", resp)
 
         with open(dest_path, mode) as f:
             for chunk in resp.iter_content(chunk_size=chunk_size):
                 if cancel_event.is_set():
-                    raise RuntimeError("Download cancelled")
+                    resp.close()
+                    raise DownloadCancelled()
+                if pause_event is not None and pause_event.is_set():
+                    resp.close()
+                    on_progress(overall_base + written)
+                    raise DownloadPaused()
                 if not chunk:
                     continue
                 f.write(chunk)
@@ -150,6 +561,21 @@ def _download_streaming(
         raise RuntimeError(f"Network error downloading file: {e}") from e
 
 
+def _model_id_norm(model_name: str) -> str:
+    return model_name.split("/")[-1].lower().replace("-", "_").replace(" ", "_")
+
+
+def _select_device() -> str:
+    import torch
+
+    if torch.cuda.is_available():
+        return "cuda"
+    elif torch.backends.mps.is_available():
+        return "mps"
+    else:
+        return "cpu"
+
+
 class Runner:
     def __init__(self) -> None:
         self._lock = threading.Lock()
@@ -157,6 +583,15 @@ class Runner:
         self._messages: Dict[str, List[Any]] = {}
         self._cancel: Dict[str, threading.Event] = {}
         self._download_cancel: Dict[str, threading.Event] = {}
+        self._download_pause: Dict[str, threading.Event] = {}
+        # Whether a cancelled download should delete what it fetched.
+        self._download_discard: Dict[str, bool] = {}
+
+    def register_generation(self, generation_id: str) -> None:
+        """Create the cancel event up front so a cancel that arrives before the
+        generation thread starts is not lost."""
+        with self._lock:
+            self._cancel.setdefault(generation_id, threading.Event())
 
     def cancel(self, generation_id: str) -> None:
         with self._lock:
@@ -164,12 +599,148 @@ class Runner:
         if ev is not None:
             ev.set()
 
-    def cancel_download(self, download_id: str) -> None:
+    def cancel_download(self, download_id: str, discard: bool = False) -> None:
         with self._lock:
             ev = self._download_cancel.get(download_id)
+            if ev is not None:
+                self._download_discard[download_id] = discard
+        if ev is not None:
+            ev.set()
+
+    def pause_download(self, download_id: str) -> None:
+        with self._lock:
+            ev = self._download_pause.get(download_id)
         if ev is not None:
             ev.set()
 
+    def is_loaded(self, model_name: str) -> bool:
+        with self._lock:
+            return _model_id_norm(model_name) in self._loaded
+
+    def unload_all(self) -> None:
+        with self._lock:
+            self._loaded = {}
+        _free_memory()
+
+    def _ensure_loaded(self, model_name: str, repo_id: Optional[str], on_stage=None) -> Dict[str, Any]:
+        """Load `model_name` (a local dir), unloading anything else first."""
+
+        model_id_norm = _model_id_norm(model_name)
+        with self._lock:
+            cached = self._loaded.get(model_id_norm)
+        if cached is not None:
+            return cached
+
+        def stage(name: str) -> None:
+            if on_stage is not None:
+                on_stage(name)
+
+        # Unload other models to free up memory
+        stage("unloading")
+        self.unload_all()
+
+        loaded = self._load_fresh(model_name, stage)
+        with self._lock:
+            self._loaded[model_id_norm] = loaded
+
+        _send({"type": "model_loaded", "model": model_name, "repo_id": repo_id})
+        return loaded
+
+    def _load_fresh(self, model_name: str, stage=lambda name: None) -> Dict[str, Any]:
+        """Load the processor and weights of `model_name` without caching them."""
+
+        import torch
+        from transformers import AutoProcessor, AutoModelForCausalLM, AutoTokenizer
+
+        model_id_norm = _model_id_norm(model_name)
+
+        # Quantization
+        # quant_config = HqqConfig(nbits=8, group_size=64) -> Use quant just for NVIDIA GPU
+
+        stage("processor")
+        try:
+            if "qwen" in model_id_norm:
+                raise RuntimeError("Qwen models do not support AutoProcessor")
+
+            processor = AutoProcessor.from_pretrained(model_name, device_map=_select_device(), local_files_only=True)
+            as_processor_tokenizer = True
+        except Exception:
+            # processor as tokenizer
+            processor = AutoTokenizer.from_pretrained(model_name, local_files_only=True)
+            as_processor_tokenizer = False
+
+        stage("weights")
+        load_kwargs = {
+            "local_files_only": True,
+            "dtype": torch.bfloat16,
+            "device_map": _select_device(),
+            # "quantization_config": quant_config,
+        }
+        try:
+            model = AutoModelForCausalLM.from_pretrained(model_name, **load_kwargs)
+        except ValueError:
+            # Many vision-language checkpoints are only registered for image-text-to-text.
+            import transformers
+
+            auto_vlm = getattr(transformers, "AutoModelForImageTextToText", None)
+            if auto_vlm is None:
+                raise
+            model = auto_vlm.from_pretrained(model_name, **load_kwargs)
+
+        print(f"Using device: {_select_device()}", file=sys.stderr)
+
+        return {
+            "processor": processor,
+            "model": model,
+            "as_processor_tokenizer": as_processor_tokenizer,
+        }
+
+    def validate_model(self, validate_id: str, model_name: str) -> None:
+        """Load `model_name` on the side and drop it again, so a broken download
+        is caught without disturbing the model that is currently loaded."""
+        try:
+            if not self.is_loaded(model_name):
+                loaded = self._load_fresh(model_name)
+                del loaded
+            _send({"type": "validate_result", "validate_id": validate_id, "ok": True})
+        except Exception as e:
+            print(f"Model validation failed: {validate_id}: {e}", file=sys.stderr)
+            _send({"type": "validate_result", "validate_id": validate_id, "ok": False, "message": str(e)})
+        finally:
+            _free_memory()
+
+    def count_tokens(
+        self,
+        count_id: str,
+        model_name: str,
+        repo_id: Optional[str],
+        prompt: str,
+        prefill: Optional[str] = None,
+        turns: Optional[List[Dict[str, Any]]] = None,
+    ) -> None:
+        """Count the prompt tokens `generate` would feed the model for `prompt`,
+        conversation history and chat template included."""
+        try:
+            loaded = self._ensure_loaded(model_name, repo_id)
+            messages = _conversation(self._history(), turns, prompt)
+            inputs = _chat_inputs(loaded["processor"], _model_id_norm(model_name), messages, prefill)
+            tokens = int(inputs["input_ids"].shape[-1])
+            _send({"type": "token_count", "count_id": count_id, "ok": True, "tokens": tokens})
+        except Exception as e:
+            print(f"Token count failed: {count_id}: {e}", file=sys.stderr)
+            _send({"type": "token_count", "count_id": count_id, "ok": False, "message": str(e)})
+
+    def load_model(self, switch_id: str, model_name: str, repo_id: Optional[str]) -> None:
+        def on_stage(name: str) -> None:
+            _send({"type": "model_load_progress", "switch_id": switch_id, "repo_id": repo_id, "stage": name})
+
+        try:
+            self._ensure_loaded(model_name, repo_id, on_stage=on_stage)
+            _send({"type": "model_load_done", "switch_id": switch_id, "repo_id": repo_id, "model": model_name})
+        except Exception as e:
+            print(f"Model load error: {switch_id}: {e}", file=sys.stderr)
+            _send({"type": "model_load_error", "switch_id": switch_id, "repo_id": repo_id, "message": str(e)})
+
     def download_model(
         self,
         download_id: str,
@@ -177,13 +748,24 @@ class Runner:
         revision: Optional[str],
         local_dir: Optional[str],
         token: Optional[str],
+        endpoint: Optional[str] = None,
+        extra_headers: Optional[Dict[str, str]] = None,
+        resume: bool = False,
     ) -> None:
-        
+        """`resume` is set when `local_dir` already holds files. They are reused
+        either way; the flag only labels `download_started` for the UI."""
+
         print(f"Starting download: {download_id} for repo: {repo_id}", file=sys.stderr)
         
         cancel_event = threading.Event()
+        pause_event = threading.Event()
         with self._lock:
             self._download_cancel[download_id] = cancel_event
+            self._download_pause[download_id] = pause_event
+        last_n = 0
+        files: List[str] = []
+        completed: List[str] = []
+        current: Optional[str] = None
 
         try:
             HfApi, hf_hub_download, hf_hub_url = _import_hf_hub()
@@ -193,10 +775,11 @@ class Runner:
                     "type": "download_started",
                     "download_id": download_id,
                     "repo_id": repo_id,
+                    "resumed": resume,
                 }
             )
 
-            api = HfApi()
+            api = HfApi(endpoint=endpoint, headers=extra_headers or None)
 
             # Byte-based progress: fetch file metadata (sizes) once.
             info = api.model_info(repo_id, revision=revision, files_metadata=True, token=token)
@@ -205,6 +788,7 @@ class Runner:
             files = [f for f in files if isinstance(f, str) and f]
             if not files:
                 raise RuntimeError("No files found in repo")
+            finished_before = set(_read_download_state(local_dir, repo_id)) if local_dir else set()
 
             # Precompute total bytes.
             per_file_size: Dict[str, Optional[int]] = {}
@@ -236,6 +820,8 @@ class Runner:
             downloaded_bytes = 0
 
             def emit_progress(n_bytes: int, desc: Optional[str] = None) -> None:
+                nonlocal last_n
+                last_n = int(n_bytes)
                 payload = {
                     "type": "download_progress",
                     "download_id": download_id,
@@ -248,7 +834,9 @@ class Runner:
 
             for filename in files:
                 if cancel_event.is_set():
-                    raise RuntimeError("Download cancelled")
+                    raise DownloadCancelled()
+                if pause_event.is_set():
+                    raise DownloadPaused()
 
                 emit_progress(downloaded_bytes, filename)
 
@@ -259,9 +847,23 @@ class Runner:
 
                 expected_size = per_file_size.get(filename)
 
+                # Recorded as finished by an earlier, stopped download. Matters for
+                # files of unknown size, where a Range request can't tell us.
+                if filename in finished_before and os.path.exists(dest_path):
+                    downloaded_bytes += os.path.getsize(dest_path)
+                    completed.append(filename)
+                    continue
+                current = filename
+
                 # Build URL and stream download. This yields frequent progress updates even
                 # for a single multi-GB safetensors shard.
-                url = hf_hub_url(repo_id=repo_id, filename=filename, revision=revision, repo_type="model")
+                url = hf_hub_url(
+                    repo_id=repo_id,
+                    filename=filename,
+                    revision=revision,
+                    repo_type="model",
+                    endpoint=endpoint,
+                )
 
                 before = downloaded_bytes
 
@@ -278,6 +880,8 @@ class Runner:
                     cancel_event=cancel_event,
                     on_progress=on_progress,
                     overall_base=before,
+                    extra_headers=extra_headers,
+                    pause_event=pause_event,
                 )
 
                 # Advance overall counter by the file's expected size if known, else by written.
@@ -286,9 +890,12 @@ class Runner:
                 else:
                     downloaded_bytes = before + int(written)
 
+                completed.append(filename)
+                current = None
                 emit_progress(downloaded_bytes, filename)
 
             path = local_dir or ""
+            _clear_download_state(path)
             
             print(f"Download done: {download_id} for repo: {repo_id}", file=sys.stderr)
 
@@ -300,19 +907,94 @@ class Runner:
                     "path": path,
                 }
             )
+        except DownloadPaused:
+            print(f"Download paused: {download_id} for repo: {repo_id}", file=sys.stderr)
+            if local_dir:
+                _write_download_state(local_dir, repo_id, completed, current)
+            _send(
+                {
+                    "type": "download_paused",
+                    "download_id": download_id,
+                    "repo_id": repo_id,
+                    "n": last_n,
+                }
+            )
+        except DownloadCancelled:
+            with self._lock:
+                discard = self._download_discard.get(download_id, False)
+            kept_bytes = 0
+            if local_dir and discard:
+                _discard_files(local_dir, files)
+            elif local_dir:
+                _write_download_state(local_dir, repo_id, completed, current)
+                kept_bytes = _present_bytes(local_dir, files)
+            print(
+                f"Download cancelled: {download_id} for repo: {repo_id} (discard={discard})",
+                file=sys.stderr,
+            )
+            _send(
+                {
+                    "type": "download_cancelled",
+                    "download_id": download_id,
+                    "repo_id": repo_id,
+                    "discarded": discard,
+                    "kept_bytes": kept_bytes,
+                    "completed_files": completed,
+                }
+            )
         except Exception as e:
             print(f"Download error: {download_id} for repo: {repo_id}: {e}", file=sys.stderr)
+            auth = _auth_error_details(e, repo_id, token, endpoint)
+            message = str(e)
+            if auth.get("reason") == "gated":
+                message = f"{repo_id} is gated; accept its license at {auth['license_url']} and try again"
             _send(
                 {
                     "type": "download_error",
                     "download_id": download_id,
                     "repo_id": repo_id,
-                    "message": str(e),
+                    "message": message,
+                    **auth,
                 }
             )
         finally:
             with self._lock:
                 self._download_cancel.pop(download_id, None)
+                self._download_pause.pop(download_id, None)
+                self._download_discard.pop(download_id, None)
+
+    def _history(self) -> List[Dict[str, Any]]:
+        """The running conversation, or a fresh one holding just the system prompt."""
+        messages = self._messages.get("messages")
+        if messages is not None:
+            return messages
+        return [
+            {
+                "role": "system",
+                "content": [
+                    {
+                    "type": "text",
+                    "text": "You are a helpful, concise, and direct assistant."
+                    },
+                    {
+                    "type": "text",
+                    "text": "Always respond exclusively in the same language used by the user. Do not translate, explain, or repeat the answer in any other language. Do not add translations in parentheses."
+                    },
+                    {
+                    "type": "text",
+                    "text": "If you are unsure about an answer, clearly say that you do not know instead of guessing."
+                    },
+                    {
+                    "type": "text",
+                    "text": "You were developed by Lucas Mengarda as an open-source local AI project. Never claim a different origin."
+                    },
+                    {
+                    "type": "text",
+                    "text": "You are running locally on the user's machine."
+                    }
+                ]
+                }
+        ]
 
     def generate(
         self,
@@ -321,146 +1003,70 @@ class Runner:
         prompt: str,
         max_new_tokens: int,
         temperature: float,
+        repo_id: Optional[str] = None,
+        seed: Optional[int] = None,
+        prefill: Optional[str] = None,
+        response_format: Optional[Dict[str, Any]] = None,
+        images: Optional[List[Dict[str, Any]]] = None,
+        sampling: Optional[Dict[str, Any]] = None,
+        stop: Optional[List[str]] = None,
+        turns: Optional[List[Dict[str, Any]]] = None,
     ) -> None:
         try: 
             
             print(f"Starting generation: {generation_id} with model: {model_name}", file=sys.stderr)
             
-            cancel_event = threading.Event()
             with self._lock:
-                self._cancel[generation_id] = cancel_event
+                cancel_event = self._cancel.setdefault(generation_id, threading.Event())
         
             
             import torch
             from transformers import (
-                AutoProcessor,
-                AutoModelForCausalLM,
                 TextIteratorStreamer,
-                AutoTokenizer,
                 StoppingCriteria,
                 StoppingCriteriaList,
+                set_seed,
             )
 
-            processor = None
-            model = None
             messages = None
-            as_processor_tokenizer = False
-            
-            model_id_norm = model_name.split("/")[-1].lower().replace("-", "_").replace(" ", "_")
             
-            def select_device():
-                if torch.cuda.is_available():
-                    return "cuda"
-                elif torch.backends.mps.is_available():
-                    return "mps"
-                else:
-                    return "cpu"
+            model_id_norm = _model_id_norm(model_name)
 
-            if self._loaded.get(model_id_norm) == None:
-                
-                # Unload other models to free up memory
-                with self._lock:
-                    self._loaded = {}
+            if not self.is_loaded(model_name):
+                _send({"type": "phase", "generation_id": generation_id, "phase": "loading"})
 
-                # Quantization
-                # quant_config = HqqConfig(nbits=8, group_size=64) -> Use quant just for NVIDIA GPU
-                
-                try:
-                    if "qwen" in model_id_norm.lower():
-                        raise RuntimeError("Qwen models do not support AutoProcessor")
-                    
-                    processor = AutoProcessor.from_pretrained(model_name, device_map=select_device(), local_files_only=True)
-                    as_processor_tokenizer = True
-                except Exception as e:
-                    # processor as tokenizer
-                    processor = AutoTokenizer.from_pretrained(model_name, local_files_only=True)
-                    as_processor_tokenizer = False
-                    
-                    
-                model = AutoModelForCausalLM.from_pretrained(
-                    model_name,
-                    local_files_only=True,
-                    dtype=torch.bfloat16,
-                    device_map=select_device(), 
-                    # quantization_config=quant_config,
-                )
-                
-                print(f"Using device: {select_device()}", file=sys.stderr)
-                
-                self._loaded[model_id_norm] = {
-                    "processor": processor,
-                    "model": model
-                }
-                
-            else:
-                processor = self._loaded[model_id_norm]["processor"]
-                model = self._loaded[model_id_norm]["model"]
+            # from_pretrained can't be interrupted, so a cancel during a cold load
+            # is honored at the next stage boundary (or right after the load).
+            def check_cancelled(_stage: str = "") -> None:
+                if cancel_event.is_set():
+                    raise LoadCancelled()
 
-            if self._messages.get("messages") == None:
-                messages = [
+            check_cancelled()
+            loaded = self._ensure_loaded(model_name, repo_id, on_stage=check_cancelled)
+            check_cancelled()
+            processor = loaded["processor"]
+            model = loaded["model"]
+            as_processor_tokenizer = loaded["as_processor_tokenizer"]
+
+            if images and getattr(processor, "image_processor", None) is None:
+                _send(
                     {
-                        "role": "system",
-                        "content": [
-                            {
-                            "type": "text",
-                            "text": "You are a helpful, concise, and direct assistant."
-                            },
-                            {
-                            "type": "text",
-                            "text": "Always respond exclusively in the same language used by the user. Do not translate, explain, or repeat the answer in any other language. Do not add translations in parentheses."
-                            },
-                            {
-                            "type": "text",
-                            "text": "If you are unsure about an answer, clearly say that you do not know instead of guessing."
-                            },
-                            {
-                            "type": "text",
-                            "text": "You were developed by Lucas Mengarda as an open-source local AI project. Never claim a different origin."
-                            },
-                            {
-                            "type": "text",
-                            "text": "You are running locally on the user's machine."
-                            }
-                        ]
-                        }
-                ]
+                        "type": "error",
+                        "generation_id": generation_id,
+                        "message": f"{repo_id or model_name} does not accept image inputs",
+                        "reason": "not_multimodal",
+                    }
+                )
+                return
+            decoded_images = [_load_image(spec) for spec in images or []]
+
+            # A caller-sent conversation is used as-is and kept out of the history.
+            messages = _conversation(self._history(), turns, prompt, decoded_images)
+            if turns is None:
                 self._messages["messages"] = messages
-            else:
-                messages = self._messages["messages"]
-            
-            # Add user prompt to messages
-            messages.append({
-                "role": "user",
-                "content": [{"type": "text", "text": prompt}],
-            })
-            
-            
-            # Adapt messages if model is Qwen, which do not accept list on content
-            updated_messages = []
-            
-            if "qwen" in model_id_norm.lower():
-                for msg in messages:
-                    if isinstance(msg["content"], list):
-                        combined_text = " ".join([part["text"] for part in msg["content"] if part.get("type") == "text"])
-                        updated_messages.append({
-                            "role": msg["role"],
-                            "content": combined_text
-                        })
-                    else:
-                        updated_messages.append(msg)
-                updated_messages
-            else:
-                updated_messages = messages
-
-            # Aplica o chat template
-            inputs = processor.apply_chat_template(
-                updated_messages,
-                add_generation_prompt=True,
-                tokenize=True,
-                return_dict=True,
-                return_tensors="pt",
-            )
-            
+
+            inputs = _chat_inputs(processor, model_id_norm, messages, prefill)
+
             if as_processor_tokenizer:
                 inputs = inputs.to(model.device, dtype=torch.bfloat16)
             else:
@@ -474,7 +1080,7 @@ class Runner:
             # Rust expects runner messages: {type: "token", generation_id, token}
             # and a final {type: "done", generation_id}.
 
-            do_sample = float(temperature) > 0.0 and not select_device() == "mps"
+            do_sample = float(temperature) > 0.0 and not _select_device() == "mps"
             
             tokenizer = processor.tokenizer if as_processor_tokenizer else processor
             
@@ -488,24 +1094,74 @@ class Runner:
                 "use_cache": True,
                 "streamer": streamer,
             }
-
-            # This exists to allow stopping criteria to access the cancel_event
+            # Unset knobs fall back to the model's generation_config. top_p/top_k
+            # only mean something when sampling.
+            sampling = sampling or {}
+            if sampling.get("repetition_penalty") is not None:
+                generation_kwargs["repetition_penalty"] = float(sampling["repetition_penalty"])
+            if do_sample and sampling.get("top_p") is not None:
+                generation_kwargs["top_p"] = float(sampling["top_p"])
+            if do_sample and sampling.get("top_k") is not None:
+                generation_kwargs["top_k"] = int(sampling["top_k"])
+
+            # This exists to allow stopping criteria to access the cancel_event.
+            # It also records whether the cancel is what ended generation, so a
+            # cancel arriving after the model already finished doesn't mask the
+            # real finish reason.
+            stopped = {"by_cancel": False}
+            matcher = _StopMatcher([s for s in stop or [] if s])
+            stop_event = threading.Event()
 
             class _CancelStop(StoppingCriteria):
                 def __call__(self, *args, **kwargs):
-                    return cancel_event.is_set()
+                    if cancel_event.is_set():
+                        stopped["by_cancel"] = True
+                        return True
+                    return stop_event.is_set()
 
             generation_kwargs["stopping_criteria"] = StoppingCriteriaList([_CancelStop()])
 
+            if response_format is not None:
+                from lmformatenforcer import JsonSchemaParser
+                from lmformatenforcer.integrations.transformers import (
+                    build_transformers_prefix_allowed_tokens_fn,
+                )
+
+                # A None schema accepts any JSON object.
+                schema = response_format.get("schema") if response_format.get("type") == "json_schema" else None
+                generation_kwargs["prefix_allowed_tokens_fn"] = build_transformers_prefix_allowed_tokens_fn(
+                    tokenizer, JsonSchemaParser(schema)
+                )
+
+            if seed is not None:
+                set_seed(seed)
+
+            _send({"type": "phase", "generation_id": generation_id, "phase": "prefill"})
+
+            output: Dict[str, Any] = {}
+
+            def _generate() -> None:
+                output["ids"] = model.generate(**generation_kwargs)
+
             # Gera em thread separada
-            thread = threading.Thread(target=model.generate, kwargs=generation_kwargs)
+            thread = threading.Thread(target=_generate)
             thread.start()
 
             full_response = ""
+
+            def emit(text: str) -> None:
+                nonlocal full_response
+                if text:
+                    full_response += text
+                    _send({"type": "chat_token", "generation_id": generation_id, "token": text})
+
             for new_text in streamer:
-                
-                full_response += new_text
-                _send({"type": "chat_token", "generation_id": generation_id, "token": new_text})
+                # Past a stop sequence, just drain until generate() notices.
+                if matcher.hit is not None:
+                    continue
+                emit(matcher.feed(new_text))
+                if matcher.hit is not None:
+                    stop_event.set()
                 
                 # Condição customizada para parar
                 # if len(full_response) > 500:
@@ -517,18 +1173,46 @@ class Runner:
                     break
 
             thread.join()
-            _send({"type": "done", "generation_id": generation_id})
-            
-            messages.append({
-                "role": "assistant",
-                "content": [{"type": "text", "text": full_response}],
-            })
+            if matcher.hit is None and not cancel_event.is_set():
+                emit(matcher.flush())
+            if stopped["by_cancel"] or (cancel_event.is_set() and "ids" not in output):
+                _send({"type": "cancelled", "generation_id": generation_id, "seed": seed})
+            elif "ids" not in output:
+                raise RuntimeError("Generation ended without output")
+            else:
+                new_tokens = output["ids"].shape[-1] - inputs["input_ids"].shape[-1]
+                done = {"type": "done", "generation_id": generation_id, "seed": seed}
+                if matcher.hit is not None:
+                    done.update(finish_reason="stop_sequence", stop_sequence=matcher.hit)
+                else:
+                    done["finish_reason"] = "length" if new_tokens >= max_new_tokens else "stop"
+                _send(done)
             
-            self._messages["messages"] = messages
+            if turns is None:
+                messages.append({
+                    "role": "assistant",
+                    "content": [{"type": "text", "text": (prefill or "") + full_response}],
+                })
+                self._messages["messages"] = messages
             
+        except LoadCancelled:
+            print(f"Generation cancelled during load: {generation_id}", file=sys.stderr)
+            _send(
+                {
+                    "type": "error",
+                    "generation_id": generation_id,
+                    "message": "Cancelled while loading the model",
+                    "reason": "cancelled_during_load",
+                }
+            )
         except Exception as e:
             print(f"Erro na geração: {e}", file=sys.stderr)
-            _send({"type": "error", "generation_id": generation_id, "message": str(e)})
+            oom = _oom_details(e)
+            if oom is not None:
+                _free_memory()
+                _send({"type": "error", "generation_id": generation_id, "message": str(e), "reason": "out_of_memory", **oom})
+            else:
+                _send({"type": "error", "generation_id": generation_id, "message": str(e)})
         finally:
             with self._lock:
                 self._cancel.pop(generation_id, None)
@@ -537,7 +1221,10 @@ class Runner:
 
 def main() -> None:
     runner = Runner()
-    _send({"type": "ready"})
+    _send({"type": "ready", "features": _runner_features()})
+
+    # Importing torch can take a few seconds; don't hold up the first request.
+    threading.Thread(target=_send_capabilities, daemon=True).start()
 
     for line in sys.stdin:
         line = line.strip()
@@ -561,10 +1248,21 @@ def main() -> None:
                 runner.cancel(generation_id)
             continue
 
+        if msg_type == "unload":
+            runner.unload_all()
+            _send({"type": "model_unloaded"})
+            continue
+
         if msg_type == "download_cancel":
             download_id = msg.get("download_id")
             if isinstance(download_id, str):
-                runner.cancel_download(download_id)
+                runner.cancel_download(download_id, bool(msg.get("discard", False)))
+            continue
+
+        if msg_type == "download_pause":
+            download_id = msg.get("download_id")
+            if isinstance(download_id, str):
+                runner.pause_download(download_id)
             continue
 
         if msg_type == "download":
@@ -573,6 +1271,9 @@ def main() -> None:
             revision = msg.get("revision")
             local_dir = msg.get("local_dir")
             token = msg.get("token")
+            endpoint = msg.get("endpoint")
+            extra_headers = msg.get("headers")
+            resume = msg.get("resume") is True
 
             if not isinstance(download_id, str) or not download_id:
                 _send(
@@ -600,18 +1301,33 @@ def main() -> None:
                 local_dir = None
             if token is not None and not isinstance(token, str):
                 token = None
+            if not isinstance(endpoint, str) or not endpoint:
+                endpoint = None
+            if isinstance(extra_headers, dict):
+                extra_headers = {str(k): str(v) for k, v in extra_headers.items()}
+            else:
+                extra_headers = None
 
             threading.Thread(
                 target=runner.download_model,
-                args=(download_id, repo_id, revision, local_dir, token),
+                args=(
+                    download_id,
+                    repo_id,
+                    revision,
+                    local_dir,
+                    token,
+                    endpoint,
+                    extra_headers,
+                    resume,
+                ),
                 daemon=True,
             ).start()
             continue
         
         if msg_type == "generate":
             
+            # Not the message itself: prompts and images don't belong in logs.
             print("Processing generate request...", file=sys.stderr)
-            print(f"Message: {msg}", file=sys.stderr)
 
             generation_id = msg.get("generation_id")
             model_name = msg.get("model")
@@ -632,9 +1348,114 @@ def main() -> None:
                 _send({"type": "error", "generation_id": generation_id, "message": "Missing prompt"})
                 continue
 
+            repo_id = msg.get("repo_id")
+            if not isinstance(repo_id, str):
+                repo_id = None
+            seed = msg.get("seed")
+            if not isinstance(seed, int) or isinstance(seed, bool) or seed < 0:
+                seed = None
+            prefill = msg.get("prefill")
+            if not isinstance(prefill, str) or not prefill:
+                prefill = None
+            response_format = msg.get("response_format")
+            if not isinstance(response_format, dict) or response_format.get("type") not in ("json_object", "json_schema"):
+                response_format = None
+            images = msg.get("images")
+            if not isinstance(images, list) or not images:
+                images = None
+            turns = _parse_turns(msg.get("messages"))
+            stop = msg.get("stop")
+            if not isinstance(stop, list):
+                stop = None
+            else:
+                stop = [s for s in stop if isinstance(s, str) and s] or None
+            sampling = {
+                key: msg[key]
+                for key in ("top_p", "top_k", "repetition_penalty")
+                if isinstance(msg.get(key), (int, float)) and not isinstance(msg.get(key), bool)
+            }
+
+            runner.register_generation(generation_id)
             threading.Thread(
                 target=runner.generate,
-                args=(generation_id, model_name, prompt, int(max_new_tokens), float(temperature)),
+                args=(
+                    generation_id,
+                    model_name,
+                    prompt,
+                    int(max_new_tokens),
+                    float(temperature),
+                    repo_id,
+                    seed,
+                    prefill,
+                    response_format,
+                    images,
+                    sampling,
+                    stop,
+                    turns,
+                ),
+                daemon=True,
+            ).start()
+            continue
+
+        if msg_type == "load":
+            switch_id = msg.get("switch_id")
+            model_name = msg.get("model")
+            repo_id = msg.get("repo_id")
+            if not isinstance(switch_id, str) or not switch_id:
+                _send({"type": "model_load_error", "switch_id": None, "repo_id": None, "message": "Missing switch_id"})
+                continue
+            if not isinstance(model_name, str) or not model_name:
+                _send({"type": "model_load_error", "switch_id": switch_id, "repo_id": None, "message": "Missing model"})
+                continue
+            if not isinstance(repo_id, str):
+                repo_id = None
+
+            threading.Thread(
+                target=runner.load_model,
+                args=(switch_id, model_name, repo_id),
+                daemon=True,
+            ).start()
+            continue
+
+        if msg_type == "validate":
+            validate_id = msg.get("validate_id")
+            model_name = msg.get("model")
+            if not isinstance(validate_id, str) or not validate_id:
+                continue
+            if not isinstance(model_name, str) or not model_name:
+                _send({"type": "validate_result", "validate_id": validate_id, "ok": False, "message": "Missing model"})
+                continue
+
+            threading.Thread(
+                target=runner.validate_model,
+                args=(validate_id, model_name),
+                daemon=True,
+            ).start()
+            continue
+
+        if msg_type == "count_tokens":
+            count_id = msg.get("count_id")
+            model_name = msg.get("model")
+            prompt = msg.get("prompt")
+            if not isinstance(count_id, str) or not count_id:
+                continue
+            if not isinstance(model_name, str) or not model_name:
+                _send({"type": "token_count", "count_id": count_id, "ok": False, "message": "Missing model"})
+                continue
+            if not isinstance(prompt, str):
+                _send({"type": "token_count", "count_id": count_id, "ok": False, "message": "Missing prompt"})
+                continue
+            repo_id = msg.get("repo_id")
+            if not isinstance(repo_id, str):
+                repo_id = None
+            prefill = msg.get("prefill")
+            if not isinstance(prefill, str) or not prefill:
+                prefill = None
+            turns = _parse_turns(msg.get("messages"))
+
+            threading.Thread(
+                target=runner.count_tokens,
+                args=(count_id, model_name, repo_id, prompt, prefill, turns),
                 daemon=True,
             ).start()
             continue
@@ -0,0 +1,166 @@
+// Markdown-safe chunking for `chat_token`. Holds streamed text back until it
+// ends on a boundary an incremental markdown renderer won't reflow at: after a
+// complete word, and after the closing line of a code fence.
+
+/// Inside a fence, complete lines start flowing anyway once this much is held,
+/// so a long code block doesn't stall the stream.
+const MAX_FENCE_HOLD: usize = 4096;
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+/// An unfinished line that is, or may still grow into, a fence marker.
+fn could_be_fence(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    is_fence(line)
+        || (!trimmed.is_empty()
+            && trimmed.len() < 3
+            && trimmed.chars().all(|c| c == '`' || c == '~'))
+}
+
+#[derive(Default)]
+pub(crate) struct MarkdownChunker {
+    /// Whether the text emitted so far ends inside an open fence.
+    in_fence: bool,
+    /// The unfinished last line of the text emitted so far.
+    line: String,
+}
+
+impl MarkdownChunker {
+    /// The full line that `pending[start..end]` belongs to.
+    fn line_of(&self, pending: &str, start: usize, end: usize) -> String {
+        let prefix = if start == 0 { self.line.as_str() } else { "" };
+        format!("{prefix}{}", &pending[start..end])
+    }
+
+    /// Length of the longest prefix of `pending` that is safe to emit now.
+    pub(crate) fn safe_len(&self, pending: &str) -> usize {
+        let mut in_fence = self.in_fence;
+        let mut safe = 0;
+        let mut line_start = 0;
+
+        for (i, _) in pending.match_indices('\n') {
+            if is_fence(&self.line_of(pending, line_start, i)) {
+                in_fence = !in_fence;
+            }
+            line_start = i + 1;
+            if !in_fence {
+                safe = line_start;
+            }
+        }
+
+        if in_fence {
+            return if pending.len() > MAX_FENCE_HOLD {
+                line_start
+            } else {
+                safe
+            };
+        }
+        if could_be_fence(&self.line_of(pending, line_start, pending.len())) {
+            return safe;
+        }
+        // Up to and including the last whitespace, so a word is never split.
+        let partial = &pending[line_start..];
+        partial
+            .char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map_or(safe, |(i, c)| line_start + i + c.len_utf8())
+    }
+
+    /// Records that `emitted` went out, tracking whether a fence is open.
+    pub(crate) fn advance(&mut self, emitted: &str) {
+        let mut parts = emitted.split('\n');
+        self.line.push_str(parts.next().unwrap_or_default());
+        for part in parts {
+            if is_fence(&self.line) {
+                self.in_fence = !self.in_fence;
+            }
+            self.line.clear();
+            self.line.push_str(part);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Streams `tokens` through a chunker the way `accept_token` does and
+    /// returns the chunks released along the way, plus what is still held.
+    fn stream(tokens: &[&str]) -> (Vec<String>, String) {
+        let mut chunker = MarkdownChunker::default();
+        let (mut chunks, mut pending) = (Vec::new(), String::new());
+        for token in tokens {
+            pending.push_str(token);
+            let safe = chunker.safe_len(&pending);
+            if safe > 0 {
+                let rest = pending.split_off(safe);
+                let chunk = std::mem::replace(&mut pending, rest);
+                chunker.advance(&chunk);
+                chunks.push(chunk);
+            }
+        }
+        (chunks, pending)
+    }
+
+    #[test]
+    fn words_are_never_split() {
+        let (chunks, held) = stream(&["Hel", "lo wo", "rld and", " more"]);
+        assert_eq!(chunks, ["Hello ", "world ", "and "]);
+        assert_eq!(held, "more");
+    }
+
+    #[test]
+    fn a_fence_split_across_tokens_is_held_until_it_closes() {
+        let (chunks, held) = stream(&["Code:\n`", "``ru", "st\nfn main() {}\n", "`", "``\nDone "]);
+        assert_eq!(chunks, ["Code:\n", "```rust\nfn main() {}\n```\nDone "]);
+        assert_eq!(held, "");
+    }
+
+    #[test]
+    fn possible_fence_prefixes_are_held() {
+        let chunker = MarkdownChunker::default();
+        for line in ["`", "``", "~", "  ~~", "```", "~~~py"] {
+            assert_eq!(chunker.safe_len(&format!("text\n{line}")), 5, "{line:?}");
+        }
+        // Backticks that can no longer open a fence don't hold anything back.
+        assert_eq!(chunker.safe_len("text\n`code` "), 12);
+        assert_eq!(chunker.safe_len("a `b"), 2);
+    }
+
+    #[test]
+    fn an_open_fence_is_tracked_across_advances() {
+        let mut chunker = MarkdownChunker::default();
+        chunker.advance("```py\n");
+        assert_eq!(chunker.safe_len("x = 1\ny = 2"), 0);
+        assert_eq!(chunker.safe_len("x = 1\n```\nok "), 13);
+        chunker.advance("x = 1\n```\n");
+        assert_eq!(chunker.safe_len("plain words"), 6);
+    }
+
+    #[test]
+    fn a_long_fence_releases_complete_lines() {
+        let mut chunker = MarkdownChunker::default();
+        chunker.advance("```\n");
+        let line = "let value = 42;\n";
+        let mut body = line.repeat(MAX_FENCE_HOLD / line.len() + 1);
+        assert!(body.len() > MAX_FENCE_HOLD);
+        let complete = body.len();
+        body.push_str("let partial");
+        assert_eq!(chunker.safe_len(&body), complete);
+        assert_eq!(chunker.safe_len(&line.repeat(3)), 0);
+    }
+
+    #[test]
+    fn multibyte_whitespace_ends_a_word_on_a_char_boundary() {
+        let chunker = MarkdownChunker::default();
+        let text = "héllo\u{3000}wörld";
+        let safe = chunker.safe_len(text);
+        assert_eq!(&text[..safe], "héllo\u{3000}");
+        let text = "naïve\u{a0}café";
+        assert_eq!(&text[..chunker.safe_len(text)], "naïve\u{a0}");
+        assert_eq!(chunker.safe_len("ünïcödé"), 0);
+    }
+}
@@ -0,0 +1,153 @@
+// Bookkeeping for model downloads handled by the runner, persisted to
+// app_data_dir/downloads.json so paused downloads survive a restart.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tauri::Manager;
+
+use crate::model_lock::ModelDirLock;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct ActiveDownload {
+    pub repo_id: String,
+    pub revision: Option<String>,
+    pub local_dir: String,
+    pub paused: bool,
+    /// Waiting for a free slot under `max_concurrent_downloads`. Missing from
+    /// downloads.json files written before queueing existed.
+    #[serde(default)]
+    pub queued: bool,
+    /// Kept in memory only so a resume can re-authenticate; never written to disk.
+    #[serde(skip)]
+    pub token: Option<String>,
+    /// Latest `download_progress` seen from the runner.
+    #[serde(skip)]
+    pub progress: Option<DownloadProgress>,
+    #[serde(skip)]
+    pub clock: Option<ProgressClock>,
+    /// Keeps the model folder locked until the download is forgotten. `None`
+    /// for downloads restored from disk until they are resumed.
+    #[serde(skip)]
+    pub lock: Option<Arc<ModelDirLock>>,
+}
+
+/// Weight of the newest sample in the throughput average.
+const RATE_SMOOTHING: f64 = 0.3;
+/// Estimates stop growing this long after the last real event, so a stalled
+/// download doesn't look like it is still moving.
+const MAX_EXTRAPOLATION: Duration = Duration::from_secs(30);
+
+/// Measured throughput of a download, for filling the gaps between the
+/// runner's progress events, which may only come at file boundaries.
+#[derive(Clone)]
+pub(crate) struct ProgressClock {
+    at: Instant,
+    n: u64,
+    bytes_per_sec: Option<f64>,
+}
+
+impl ProgressClock {
+    pub(crate) fn new(n: u64) -> Self {
+        Self {
+            at: Instant::now(),
+            n,
+            bytes_per_sec: None,
+        }
+    }
+
+    /// Records a real progress value from the runner.
+    pub(crate) fn observe(&mut self, n: u64) {
+        let secs = self.at.elapsed().as_secs_f64();
+        if n > self.n && secs > 0.0 {
+            let sample = (n - self.n) as f64 / secs;
+            self.bytes_per_sec = Some(
+                self.bytes_per_sec
+                    .map_or(sample, |rate| rate + RATE_SMOOTHING * (sample - rate)),
+            );
+        }
+        self.at = Instant::now();
+        self.n = n;
+    }
+
+    pub(crate) fn since_last(&self) -> Duration {
+        self.at.elapsed()
+    }
+
+    /// Bytes likely done by now, never past `total`. `None` until a
+    /// throughput has been measured.
+    pub(crate) fn estimate(&self, total: Option<u64>) -> Option<u64> {
+        let rate = self.bytes_per_sec?;
+        let secs = self.at.elapsed().min(MAX_EXTRAPOLATION).as_secs_f64();
+        let n = self.n + (rate * secs) as u64;
+        Some(total.map_or(n, |total| n.min(total)))
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct DownloadProgress {
+    pub n: u64,
+    pub total: Option<u64>,
+    pub desc: Option<String>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct DownloadInfo {
+    pub download_id: String,
+    pub repo_id: String,
+    pub paused: bool,
+    pub queued: bool,
+    pub progress: Option<DownloadProgress>,
+}
+
+fn downloads_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let base = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+    Ok(base.join("downloads.json"))
+}
+
+/// Downloads left over from a previous run. The runner is gone, so every one
+/// of them comes back paused.
+pub(crate) fn load_persisted(app: &tauri::AppHandle) -> HashMap<String, ActiveDownload> {
+    let mut downloads: HashMap<String, ActiveDownload> = downloads_path(app)
+        .ok()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default();
+    for download in downloads.values_mut() {
+        download.paused = true;
+        download.queued = false;
+    }
+    downloads
+}
+
+pub(crate) fn persist(
+    app: &tauri::AppHandle,
+    downloads: &HashMap<String, ActiveDownload>,
+) -> Result<(), String> {
+    let path = downloads_path(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create app data dir: {e}"))?;
+    }
+    let raw =
+        serde_json::to_string_pretty(downloads).map_err(|e| format!("Serialize error: {e}"))?;
+    fs::write(&path, raw).map_err(|e| format!("Failed to write downloads state: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downloads_saved_before_queueing_still_load() {
+        let raw = r#"{"repo_id":"org/model","local_dir":"/m/org_model","paused":true}"#;
+        let download: ActiveDownload = serde_json::from_str(raw).unwrap();
+        assert!(download.paused);
+        assert!(!download.queued);
+        assert!(download.revision.is_none());
+    }
+}
@@ -0,0 +1,178 @@
+// Errors returned by Tauri commands.
+//
+// Plain messages serialize as a bare string, which is what the UI has always
+// received. Typed variants serialize as `{ "kind": ..., "message": ..., ... }`
+// so the frontend can branch on `kind` (e.g. show an install dialog).
+use std::fmt;
+
+#[derive(Debug)]
+pub(crate) enum CommandError {
+    Message(String),
+    PythonNotFound { tried: Vec<String> },
+    BlockedByPolicy { url: String, address: String },
+    RuntimeNotRunning,
+    /// `reason` is `offline`, `proxy` or `timeout`.
+    NetworkUnavailable { reason: &'static str, detail: String },
+    NoChatTemplate { repo_id: String },
+    RateLimited { command: String, retry_after_ms: u64 },
+    /// Network access is switched off through `go_offline`.
+    Offline,
+    /// The prompt is empty or whitespace and there is no prefill to continue.
+    EmptyPrompt,
+    /// The prompt plus `max_new_tokens` doesn't fit the model's context window.
+    ContextOverflow {
+        prompt_tokens: u64,
+        max_new_tokens: u32,
+        context_length: u64,
+    },
+    /// Another download, import or delete holds the model's folder lock.
+    ModelBusy { repo_id: String },
+    /// Images were sent to a model without an image processor.
+    NotMultimodal { repo_id: String },
+    /// The models volume can't fit what is left of a download.
+    InsufficientDiskSpace {
+        repo_id: String,
+        required_bytes: u64,
+        free_bytes: u64,
+    },
+}
+
+impl CommandError {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Message(_) => "Message",
+            Self::PythonNotFound { .. } => "PythonNotFound",
+            Self::BlockedByPolicy { .. } => "BlockedByPolicy",
+            Self::RuntimeNotRunning => "RuntimeNotRunning",
+            Self::NetworkUnavailable { .. } => "NetworkUnavailable",
+            Self::NoChatTemplate { .. } => "NoChatTemplate",
+            Self::RateLimited { .. } => "RateLimited",
+            Self::Offline => "Offline",
+            Self::EmptyPrompt => "EmptyPrompt",
+            Self::ContextOverflow { .. } => "ContextOverflow",
+            Self::ModelBusy { .. } => "ModelBusy",
+            Self::NotMultimodal { .. } => "NotMultimodal",
+            Self::InsufficientDiskSpace { .. } => "InsufficientDiskSpace",
+        }
+    }
+
+    fn details(&self) -> serde_json::Value {
+        match self {
+            Self::Message(_) | Self::RuntimeNotRunning | Self::Offline | Self::EmptyPrompt => {
+                serde_json::json!({})
+            }
+            Self::PythonNotFound { tried } => serde_json::json!({ "tried": tried }),
+            Self::BlockedByPolicy { url, address } => {
+                serde_json::json!({ "url": url, "address": address })
+            }
+            Self::NetworkUnavailable { reason, .. } => serde_json::json!({ "reason": reason }),
+            Self::NoChatTemplate { repo_id }
+            | Self::ModelBusy { repo_id }
+            | Self::NotMultimodal { repo_id } => {
+                serde_json::json!({ "repo_id": repo_id })
+            }
+            Self::RateLimited {
+                command,
+                retry_after_ms,
+            } => serde_json::json!({ "command": command, "retry_after_ms": retry_after_ms }),
+            Self::InsufficientDiskSpace {
+                repo_id,
+                required_bytes,
+                free_bytes,
+            } => serde_json::json!({
+                "repo_id": repo_id,
+                "required_bytes": required_bytes,
+                "free_bytes": free_bytes,
+            }),
+            Self::ContextOverflow {
+                prompt_tokens,
+                max_new_tokens,
+                context_length,
+            } => serde_json::json!({
+                "prompt_tokens": prompt_tokens,
+                "max_new_tokens": max_new_tokens,
+                "context_length": context_length,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message(m) => f.write_str(m),
+            Self::PythonNotFound { tried } => write!(
+                f,
+                "Python was not found (tried: {}). Install Python 3 and make sure it is on your PATH.",
+                tried.join(", ")
+            ),
+            Self::BlockedByPolicy { url, address } => write!(
+                f,
+                "Request to {url} was blocked: {address} is a private or local address. Add the host to the HTTP allowlist to permit it."
+            ),
+            Self::RuntimeNotRunning => f.write_str("Python runtime is not running"),
+            Self::NetworkUnavailable { reason, detail } => {
+                write!(f, "Network unavailable ({reason}): {detail}")
+            }
+            Self::NoChatTemplate { repo_id } => {
+                write!(f, "{repo_id} does not ship a chat template")
+            }
+            Self::RateLimited {
+                command,
+                retry_after_ms,
+            } => write!(
+                f,
+                "Too many {command} calls; try again in {:.1}s",
+                *retry_after_ms as f64 / 1000.0
+            ),
+            Self::Offline => f.write_str("Cerebro is in offline mode"),
+            Self::EmptyPrompt => f.write_str("The prompt is empty"),
+            Self::ContextOverflow {
+                prompt_tokens,
+                max_new_tokens,
+                context_length,
+            } => write!(
+                f,
+                "The prompt ({prompt_tokens} tokens) plus max_new_tokens ({max_new_tokens}) exceeds the model's context of {context_length} tokens"
+            ),
+            Self::ModelBusy { repo_id } => write!(
+                f,
+                "{repo_id} is being changed by another operation; try again once it finishes"
+            ),
+            Self::NotMultimodal { repo_id } => {
+                write!(f, "{repo_id} does not accept image inputs")
+            }
+            Self::InsufficientDiskSpace {
+                repo_id,
+                required_bytes,
+                free_bytes,
+            } => write!(
+                f,
+                "Not enough disk space for {repo_id}: needs {:.1} GB, {:.1} GB free",
+                *required_bytes as f64 / 1e9,
+                *free_bytes as f64 / 1e9
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        Self::Message(message)
+    }
+}
+
+impl serde::Serialize for CommandError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Self::Message(m) = self {
+            return serializer.serialize_str(m);
+        }
+
+        let mut value = self.details();
+        value["kind"] = self.kind().into();
+        value["message"] = self.to_string().into();
+        serde::Serialize::serialize(&value, serializer)
+    }
+}
@@ -0,0 +1,48 @@
+// System-wide shortcut that toggles the main window from any app, bound to
+// `Settings::global_shortcut`.
+use tauri::plugin::TauriPlugin;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+pub(crate) fn plugin() -> TauriPlugin<tauri::Wry> {
+    tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(|app, _shortcut, event| {
+            if event.state() == ShortcutState::Pressed {
+                crate::toggle_main_window(app);
+            }
+        })
+        .build()
+}
+
+/// Accepts the usual accelerator syntax, e.g. `CommandOrControl+Shift+Space`.
+pub(crate) fn parse(raw: &str) -> Result<Shortcut, String> {
+    raw.trim()
+        .parse()
+        .map_err(|e| format!("Invalid shortcut {raw}: {e}"))
+}
+
+/// Replaces `old` with `new`. The new chord is registered first, so one that
+/// is taken leaves the old binding working.
+pub(crate) fn rebind(
+    app: &tauri::AppHandle,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<(), String> {
+    let old = old.and_then(|raw| parse(raw).ok());
+    let new = new.map(parse).transpose()?;
+    if old == new {
+        return Ok(());
+    }
+
+    let shortcuts = app.global_shortcut();
+    if let Some(new) = new {
+        shortcuts
+            .register(new)
+            .map_err(|e| format!("{new} is not available, it may be in use by another app: {e}"))?;
+    }
+    if let Some(old) = old {
+        if let Err(e) = shortcuts.unregister(old) {
+            eprintln!("Failed to unregister {old}: {e}");
+        }
+    }
+    Ok(())
+}
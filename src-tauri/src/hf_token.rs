@@ -0,0 +1,76 @@
+// The Hugging Face token, kept in the OS credential store (Keychain, Windows
+// Credential Manager, Secret Service) so the frontend doesn't have to hold it
+// and send it with every download. The custom request headers, which often
+// carry credentials too, are kept there as well.
+use std::collections::HashMap;
+
+use keyring::Entry;
+
+const SERVICE: &str = "com.genoalabs.cerebro";
+const ACCOUNT: &str = "huggingface-token";
+const HEADERS_ACCOUNT: &str = "huggingface-headers";
+
+fn entry() -> Result<Entry, String> {
+    entry_for(ACCOUNT)
+}
+
+fn entry_for(account: &str) -> Result<Entry, String> {
+    Entry::new(SERVICE, account).map_err(|e| format!("Failed to open the credential store: {e}"))
+}
+
+/// The stored token, or `None` when there is none or the store is unavailable.
+pub(crate) fn load() -> Option<String> {
+    let entry = entry().map_err(|e| eprintln!("{e}")).ok()?;
+    match entry.get_password() {
+        Ok(token) => Some(token),
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            eprintln!("Failed to read the Hugging Face token: {e}");
+            None
+        }
+    }
+}
+
+pub(crate) fn store(token: &str) -> Result<(), String> {
+    entry()?
+        .set_password(token)
+        .map_err(|e| format!("Failed to store the Hugging Face token: {e}"))
+}
+
+/// Removes the stored token. Clearing when none is stored succeeds.
+pub(crate) fn clear() -> Result<(), String> {
+    match entry()?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Failed to remove the Hugging Face token: {e}")),
+    }
+}
+
+/// The stored `hf_headers`, empty when there are none or the store is unavailable.
+pub(crate) fn load_headers() -> HashMap<String, String> {
+    let Ok(entry) = entry_for(HEADERS_ACCOUNT).map_err(|e| eprintln!("{e}")) else {
+        return HashMap::new();
+    };
+    match entry.get_password() {
+        Ok(raw) => serde_json::from_str(&raw).unwrap_or_default(),
+        Err(keyring::Error::NoEntry) => HashMap::new(),
+        Err(e) => {
+            eprintln!("Failed to read the Hugging Face headers: {e}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Replaces the stored headers; an empty map removes them.
+pub(crate) fn store_headers(headers: &HashMap<String, String>) -> Result<(), String> {
+    let entry = entry_for(HEADERS_ACCOUNT)?;
+    if headers.is_empty() {
+        return match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to remove the Hugging Face headers: {e}")),
+        };
+    }
+    let raw = serde_json::to_string(headers).map_err(|e| format!("Serialize error: {e}"))?;
+    entry
+        .set_password(&raw)
+        .map_err(|e| format!("Failed to store the Hugging Face headers: {e}"))
+}
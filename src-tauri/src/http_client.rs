@@ -0,0 +1,99 @@
+// One reqwest client shared by commands that talk to the network, so
+// connections and TLS sessions are reused instead of rebuilt per request.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tauri::Manager;
+
+use crate::http_guard::{self, GuardedResolver};
+use crate::settings::SettingsState;
+
+/// `http_request` calls that don't pass `timeout_ms` get this limit.
+pub(crate) const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest the proxy client waits for the next bytes. There is no overall
+/// limit on the client, so `http_download` can take as long as it needs.
+const HTTP_READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Redirects followed unless the request says otherwise, as reqwest does by default.
+pub(crate) const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+pub(crate) struct HttpClientState {
+    pub client: reqwest::Client,
+    /// Clients for `http_request`, whose targets come from the UI or remote
+    /// content and go through `http_guard`, by redirect limit. reqwest only
+    /// sets redirect policy per client. Built on first use, since the
+    /// resolver reads the settings through the app handle.
+    proxy: Mutex<HashMap<usize, reqwest::Client>>,
+    /// Last `measure_download_speed` result, reused for `SPEED_CACHE_TTL`.
+    pub last_speed: Mutex<Option<(Instant, DownloadSpeed)>>,
+}
+
+pub(crate) const SPEED_CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct DownloadSpeed {
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+    /// Time until the first body byte arrived.
+    pub latency_ms: u64,
+    pub megabytes_per_sec: f64,
+}
+
+impl Default for HttpClientState {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .user_agent("cerebro/0.1 (tauri; rust)")
+                .connect_timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            proxy: Mutex::new(HashMap::new()),
+            last_speed: Mutex::new(None),
+        }
+    }
+}
+
+impl HttpClientState {
+    /// The guarded client that follows up to `max_redirects` redirects; with
+    /// 0, 3xx responses come back as they are.
+    pub(crate) fn proxy_client(
+        &self,
+        app: &tauri::AppHandle,
+        max_redirects: usize,
+    ) -> Result<reqwest::Client, String> {
+        let mut clients = self
+            .proxy
+            .lock()
+            .map_err(|_| "HTTP client mutex poisoned".to_string())?;
+        if let Some(client) = clients.get(&max_redirects) {
+            return Ok(client.clone());
+        }
+
+        let redirect = if max_redirects == 0 {
+            reqwest::redirect::Policy::none()
+        } else {
+            let handle = app.clone();
+            // Named hosts are checked by the resolver; IP literals skip
+            // DNS, so redirects to one are checked here.
+            reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= max_redirects {
+                    return attempt.error("too many redirects");
+                }
+                let settings = handle.state::<SettingsState>().get();
+                match http_guard::check_url(attempt.url(), &settings) {
+                    Ok(()) => attempt.follow(),
+                    Err(e) => attempt.error(e),
+                }
+            })
+        };
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(GuardedResolver { app: app.clone() }))
+            .redirect(redirect)
+            .connect_timeout(Duration::from_secs(10))
+            .read_timeout(HTTP_READ_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Failed to build HTTP client: {e}"))?;
+        clients.insert(max_redirects, client.clone());
+        Ok(client)
+    }
+}
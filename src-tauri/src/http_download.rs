@@ -0,0 +1,204 @@
+// General-purpose file downloads for `http_download`, streamed to disk through
+// the guarded `http_request` client. Model downloads go through the runner
+// instead; this is for everything else the UI needs to fetch to a file. Files
+// only ever land in app_data_dir/downloads, since the content is untrusted.
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tauri::{Emitter, Manager};
+
+use crate::error::CommandError;
+
+/// Minimum gap between `cerebro:http_download_progress` events.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Cancel flags of the downloads in flight, by download id.
+#[derive(Default)]
+pub(crate) struct HttpDownloadState {
+    active: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl HttpDownloadState {
+    pub(crate) fn register(&self, download_id: &str) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        if let Ok(mut active) = self.active.lock() {
+            active.insert(download_id.to_string(), flag.clone());
+        }
+        flag
+    }
+
+    /// Asks `download_id` to stop. False when no such download is running.
+    pub(crate) fn cancel(&self, download_id: &str) -> bool {
+        let active = self.active.lock().ok();
+        let Some(flag) = active.as_ref().and_then(|a| a.get(download_id)) else {
+            return false;
+        };
+        flag.store(true, Ordering::Relaxed);
+        true
+    }
+
+    pub(crate) fn finish(&self, download_id: &str) {
+        if let Ok(mut active) = self.active.lock() {
+            active.remove(download_id);
+        }
+    }
+}
+
+/// `relative` joined onto `root`, provided it names a file below it: no `..`,
+/// root or drive prefix, and not `root` itself.
+pub(crate) fn dest_in(root: &Path, relative: &str) -> Result<PathBuf, String> {
+    let path = Path::new(relative);
+    let plain = path.components().all(|c| matches!(c, Component::Normal(_)));
+    if relative.trim().is_empty() || !plain {
+        return Err(format!(
+            "dest_path must be a relative path inside the downloads folder: {relative}"
+        ));
+    }
+    Ok(root.join(path))
+}
+
+/// Resolves `relative` under app_data_dir/downloads, creating its folder. Fails
+/// when a file is already there and `overwrite` isn't set, or when a symlinked
+/// folder would lead the write outside the downloads folder.
+pub(crate) fn prepare_dest(
+    app: &tauri::AppHandle,
+    relative: &str,
+    overwrite: bool,
+) -> Result<PathBuf, String> {
+    let root = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?
+        .join("downloads");
+    let dest = dest_in(&root, relative)?;
+    let parent = dest.parent().unwrap_or(&root);
+    fs::create_dir_all(parent)
+        .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+    let inside = match (root.canonicalize(), parent.canonicalize()) {
+        (Ok(root), Ok(parent)) => parent.starts_with(root),
+        _ => false,
+    };
+    if !inside {
+        return Err(format!("{relative} is outside the downloads folder"));
+    }
+    if dest.is_dir() {
+        return Err(format!("{relative} is a directory"));
+    }
+    if dest.symlink_metadata().is_ok() && !overwrite {
+        return Err(format!(
+            "{relative} already exists; pass overwrite to replace it"
+        ));
+    }
+    Ok(dest)
+}
+
+/// Where the body is written until it is complete, so `dest` never holds half a file.
+pub(crate) fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+/// Streams `res` into `dest`, reporting progress as it goes. Returns the bytes
+/// written, or `None` when `cancel` was set first; either way no partial file
+/// is left behind. Without `overwrite`, a file that appeared at `dest` in the
+/// meantime is left alone and the download fails.
+pub(crate) async fn save_to_file(
+    app: &tauri::AppHandle,
+    download_id: &str,
+    mut res: reqwest::Response,
+    dest: &Path,
+    overwrite: bool,
+    cancel: &AtomicBool,
+) -> Result<Option<u64>, CommandError> {
+    let part = partial_path(dest);
+    let result = write_body(app, download_id, &mut res, &part, cancel).await;
+    match result {
+        Ok(Some(_)) if !overwrite && dest.symlink_metadata().is_ok() => {
+            let _ = fs::remove_file(&part);
+            Err(format!("{} already exists", dest.display()).into())
+        }
+        Ok(Some(n)) => {
+            fs::rename(&part, dest)
+                .map_err(|e| format!("Failed to move download to {}: {e}", dest.display()))?;
+            Ok(Some(n))
+        }
+        other => {
+            let _ = fs::remove_file(&part);
+            other
+        }
+    }
+}
+
+async fn write_body(
+    app: &tauri::AppHandle,
+    download_id: &str,
+    res: &mut reqwest::Response,
+    part: &Path,
+    cancel: &AtomicBool,
+) -> Result<Option<u64>, CommandError> {
+    let total = res.content_length();
+    let mut file =
+        fs::File::create(part).map_err(|e| format!("Failed to create {}: {e}", part.display()))?;
+    let mut n: u64 = 0;
+    let mut reported_at: Option<Instant> = None;
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let chunk = res
+            .chunk()
+            .await
+            .map_err(|e| format!("Download interrupted: {e}"))?;
+        let Some(chunk) = chunk else {
+            break;
+        };
+        file.write_all(&chunk)
+            .map_err(|e| format!("Failed to write {}: {e}", part.display()))?;
+        n += chunk.len() as u64;
+
+        if reported_at.is_none_or(|at| at.elapsed() >= PROGRESS_INTERVAL) {
+            reported_at = Some(Instant::now());
+            let _ = app.emit(
+                "cerebro:http_download_progress",
+                serde_json::json!({ "download_id": download_id, "n": n, "total": total }),
+            );
+        }
+    }
+
+    file.flush()
+        .map_err(|e| format!("Failed to write {}: {e}", part.display()))?;
+    let _ = app.emit(
+        "cerebro:http_download_progress",
+        serde_json::json!({ "download_id": download_id, "n": n, "total": total }),
+    );
+    Ok(Some(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dest_in_keeps_files_below_the_downloads_folder() {
+        let root = Path::new("/data/cerebro/downloads");
+        assert_eq!(dest_in(root, "a.zip").unwrap(), root.join("a.zip"));
+        assert_eq!(dest_in(root, "sub/a.zip").unwrap(), root.join("sub/a.zip"));
+        for bad in [
+            "",
+            " ",
+            ".",
+            "../a.zip",
+            "sub/../../a.zip",
+            "/home/me/.bashrc",
+        ] {
+            assert!(dest_in(root, bad).is_err(), "{bad:?} should be rejected");
+        }
+    }
+}
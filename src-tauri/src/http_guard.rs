@@ -0,0 +1,263 @@
+// Outbound request policy for `http_request`: blocks private, loopback and
+// link-local destinations so remote content can't steer the app at internal
+// services.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+
+use reqwest::dns::{Name, Resolve, Resolving};
+use reqwest::Url;
+use tauri::Manager;
+
+use crate::error::CommandError;
+use crate::settings::{Settings, SettingsState};
+
+/// The IPv4 address carried by an IPv4-mapped (`::ffff:a.b.c.d`), IPv4-compatible
+/// (`::a.b.c.d`), NAT64 (`64:ff9b::/96`) or 6to4 (`2002::/16`) address, which
+/// can all end up reaching that IPv4 host.
+fn embedded_ipv4(v6: Ipv6Addr) -> Option<Ipv4Addr> {
+    if let Some(v4) = v6.to_ipv4_mapped() {
+        return Some(v4);
+    }
+    let s = v6.segments();
+    let low = |hi: u16, lo: u16| Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo));
+    match s {
+        [0, 0, 0, 0, 0, 0, ..] | [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(low(s[6], s[7])),
+        [0x2002, ..] => Some(low(s[1], s[2])),
+        _ => None,
+    }
+}
+
+fn is_loopback(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback(),
+        IpAddr::V6(v6) => v6.is_loopback() || embedded_ipv4(v6).is_some_and(|v4| v4.is_loopback()),
+    }
+}
+
+/// True for loopback, RFC 1918, CGNAT, link-local, benchmarking, multicast,
+/// reserved, unique-local, site-local and unspecified addresses, and for IPv6
+/// addresses embedding any of the IPv4 ones.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (b & 0xfe) == 18)
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            embedded_ipv4(v6).is_some_and(|v4| is_internal(IpAddr::V4(v4)))
+                || v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first & 0xffc0) == 0xfec0
+        }
+    }
+}
+
+fn is_allowlisted(host: &str, ip: Option<IpAddr>, settings: &Settings) -> bool {
+    settings.http_allowlist.iter().any(|entry| {
+        let entry = entry.trim();
+        entry.eq_ignore_ascii_case(host)
+            || ip.is_some_and(|ip| entry.parse::<IpAddr>().is_ok_and(|e| e == ip))
+    })
+}
+
+/// `target` is what the error reports as blocked: the URL, or just the host
+/// when the check happens during a DNS lookup.
+fn check_addr(
+    target: &str,
+    host: &str,
+    ip: IpAddr,
+    settings: &Settings,
+) -> Result<(), CommandError> {
+    if !is_internal(ip)
+        || (settings.http_allow_localhost && is_loopback(ip))
+        || is_allowlisted(host, Some(ip), settings)
+    {
+        return Ok(());
+    }
+    Err(CommandError::BlockedByPolicy {
+        url: target.to_string(),
+        address: ip.to_string(),
+    })
+}
+
+/// Rejects `url` when its host is an internal IP literal. Such hosts never
+/// reach `GuardedResolver`, which checks every named host.
+pub(crate) fn check_url(url: &Url, settings: &Settings) -> Result<(), CommandError> {
+    if !settings.http_block_private {
+        return Ok(());
+    }
+    let Some(host) = url.host_str() else {
+        return Err(format!("URL has no host: {url}").into());
+    };
+    // IPv6 literals come back bracketed.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) => check_addr(url.as_str(), host, ip, settings),
+        Err(_) => Ok(()),
+    }
+}
+
+/// DNS for the shared `http_request` client. Every lookup, for redirect
+/// targets and new pooled connections too, is checked against the current
+/// settings, so the address checked is the address connected to.
+pub(crate) struct GuardedResolver {
+    pub app: tauri::AppHandle,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let settings = self.app.state::<SettingsState>().get();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let lookup = host.clone();
+            let addrs: Vec<SocketAddr> = tauri::async_runtime::spawn_blocking(move || {
+                (lookup.as_str(), 0).to_socket_addrs()
+            })
+            .await??
+            .collect();
+            if settings.http_block_private && !is_allowlisted(&host, None, &settings) {
+                for addr in &addrs {
+                    check_addr(&host, &host, addr.ip(), &settings)?;
+                }
+            }
+            Ok(Box::new(addrs.into_iter()) as Box<dyn Iterator<Item = SocketAddr> + Send>)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocking() -> Settings {
+        Settings {
+            http_block_private: true,
+            http_allow_localhost: false,
+            http_allowlist: Vec::new(),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn internal_ranges_are_blocked() {
+        let internal = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "0.1.2.3",
+            "255.255.255.255",
+            "224.0.0.1",
+            "239.255.255.250",
+            "240.0.0.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "192.0.0.8",
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "fec0::1",
+            "ff02::1",
+            "ff0e::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::10.0.0.1",
+            "::169.254.169.254",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::7f00:1",
+            "2002:c0a8:0101::1",
+            "2002:7f00:1::",
+        ];
+        for ip in internal {
+            assert!(is_internal(ip.parse().unwrap()), "{ip} should be internal");
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        let public = [
+            "8.8.8.8",
+            "1.1.1.1",
+            "100.128.0.1",
+            "172.32.0.1",
+            "192.0.1.1",
+            "198.20.0.1",
+            "223.255.255.255",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ];
+        for ip in public {
+            assert!(!is_internal(ip.parse().unwrap()), "{ip} should be public");
+        }
+    }
+
+    #[test]
+    fn allow_localhost_only_opens_loopback() {
+        let settings = Settings {
+            http_allow_localhost: true,
+            ..blocking()
+        };
+        for ip in ["127.0.0.1", "::1", "::ffff:127.0.0.1", "64:ff9b::7f00:1"] {
+            assert!(
+                check_addr(ip, ip, ip.parse().unwrap(), &settings).is_ok(),
+                "{ip}"
+            );
+        }
+        for ip in ["10.0.0.1", "fe80::1", "::ffff:10.0.0.1"] {
+            assert!(
+                check_addr(ip, ip, ip.parse().unwrap(), &settings).is_err(),
+                "{ip}"
+            );
+        }
+        assert!(check_addr("::1", "::1", "::1".parse().unwrap(), &blocking()).is_err());
+    }
+
+    #[test]
+    fn allowlist_matches_by_host_and_by_ip() {
+        let settings = Settings {
+            http_allowlist: vec![" NAS.local ".into(), "10.0.0.5".into()],
+            ..blocking()
+        };
+        let nas: IpAddr = "192.168.1.20".parse().unwrap();
+        assert!(check_addr("nas.local", "nas.local", nas, &settings).is_ok());
+        assert!(check_addr("other.local", "other.local", nas, &settings).is_err());
+        let listed: IpAddr = "10.0.0.5".parse().unwrap();
+        assert!(check_addr("any", "any", listed, &settings).is_ok());
+        assert!(check_addr("any", "any", "10.0.0.6".parse().unwrap(), &settings).is_err());
+    }
+
+    #[test]
+    fn ip_literal_urls_are_checked() {
+        let check = |url: &str, settings: &Settings| check_url(&Url::parse(url).unwrap(), settings);
+        assert!(check("http://127.0.0.1:8080/", &blocking()).is_err());
+        assert!(check("http://[::ffff:10.0.0.1]/", &blocking()).is_err());
+        assert!(check("http://[fec0::1]/", &blocking()).is_err());
+        assert!(check("https://8.8.8.8/", &blocking()).is_ok());
+        // Named hosts are left to the resolver.
+        assert!(check("https://example.com/", &blocking()).is_ok());
+        let off = Settings {
+            http_block_private: false,
+            ..blocking()
+        };
+        assert!(check("http://127.0.0.1/", &off).is_ok());
+    }
+}
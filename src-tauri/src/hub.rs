@@ -0,0 +1,84 @@
+// Hugging Face repo metadata (`/api/models/<repo_id>`), kept in memory for a
+// while so a model gallery can be reopened without refetching every card.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::CommandError;
+use crate::settings::Settings;
+
+pub(crate) const MODEL_INFO_TTL: Duration = Duration::from_secs(10 * 60);
+/// Requests `prefetch_model_info` keeps in flight at once.
+pub(crate) const PREFETCH_CONCURRENCY: usize = 4;
+
+#[derive(Default)]
+pub(crate) struct ModelInfoCache {
+    entries: Mutex<HashMap<String, (Instant, serde_json::Value)>>,
+}
+
+impl ModelInfoCache {
+    /// The cached metadata for `repo_id`, unless it is older than `MODEL_INFO_TTL`.
+    pub(crate) fn get(&self, repo_id: &str) -> Option<serde_json::Value> {
+        let entries = self.entries.lock().ok()?;
+        let (fetched_at, info) = entries.get(repo_id)?;
+        (fetched_at.elapsed() < MODEL_INFO_TTL).then(|| info.clone())
+    }
+
+    pub(crate) fn insert(&self, repo_id: &str, info: serde_json::Value) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|_, (fetched_at, _)| fetched_at.elapsed() < MODEL_INFO_TTL);
+            entries.insert(repo_id.to_string(), (Instant::now(), info));
+        }
+    }
+}
+
+/// The model classes listed under `config.architectures` in Hub metadata.
+pub(crate) fn architectures(info: &serde_json::Value) -> Vec<String> {
+    info.pointer("/config/architectures")
+        .and_then(|x| x.as_array())
+        .map(|list| {
+            list.iter()
+                .filter_map(|x| x.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Fetches the Hub's metadata for `repo_id` from the configured endpoint.
+pub(crate) async fn fetch_model_info(
+    client: reqwest::Client,
+    settings: Settings,
+    repo_id: String,
+) -> Result<serde_json::Value, CommandError> {
+    let endpoint = settings
+        .hf_endpoint
+        .as_deref()
+        .unwrap_or("https://huggingface.co")
+        .trim_end_matches('/');
+    let mut request = client.get(format!("{endpoint}/api/models/{repo_id}"));
+    for (name, value) in &settings.hf_headers {
+        request = request.header(name, value);
+    }
+
+    let res = request.send().await.map_err(|e| {
+        if e.is_connect() || e.is_timeout() {
+            CommandError::NetworkUnavailable {
+                reason: if e.is_timeout() { "timeout" } else { "offline" },
+                detail: e.to_string(),
+            }
+        } else {
+            format!("Failed to fetch {repo_id}: {e}").into()
+        }
+    })?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(format!("{repo_id} was not found on the Hub").into());
+    }
+    if !res.status().is_success() {
+        return Err(format!("Failed to fetch {repo_id}: HTTP {}", res.status()).into());
+    }
+    let body = res
+        .text()
+        .await
+        .map_err(|e| format!("Failed reading metadata for {repo_id}: {e}"))?;
+    serde_json::from_str(&body).map_err(|e| format!("Invalid metadata for {repo_id}: {e}").into())
+}
@@ -0,0 +1,104 @@
+// Image inputs for vision-language models. Each one is size-checked and
+// sniffed here so obvious mistakes fail before anything reaches the runner,
+// which does the actual decoding with Pillow.
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use base64::Engine;
+
+/// Largest image accepted, in decoded bytes.
+pub(crate) const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ImageInput {
+    /// Base64-encoded image bytes, optionally as a `data:` URL.
+    Base64 { data: String },
+    /// An image file on this machine.
+    Path { path: String },
+}
+
+/// The image format, recognized from the leading bytes.
+fn sniff_format(head: &[u8]) -> Option<&'static str> {
+    match head {
+        [0x89, b'P', b'N', b'G', ..] => Some("png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        [b'B', b'M', ..] => Some("bmp"),
+        _ => None,
+    }
+}
+
+fn too_large(len: u64) -> String {
+    format!(
+        "{:.1} MiB is over the {} MiB limit",
+        len as f64 / (1024.0 * 1024.0),
+        MAX_IMAGE_BYTES / (1024 * 1024)
+    )
+}
+
+fn prepare_one(image: &ImageInput) -> Result<serde_json::Value, String> {
+    match image {
+        ImageInput::Base64 { data } => {
+            let encoded = data
+                .split_once(";base64,")
+                .map_or(data.as_str(), |(_, encoded)| encoded)
+                .trim();
+            // Rejected before decoding, so an oversized blob isn't copied again.
+            let approx_len = encoded.len() as u64 / 4 * 3;
+            if approx_len > MAX_IMAGE_BYTES {
+                return Err(too_large(approx_len));
+            }
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("invalid base64: {e}"))?;
+            if sniff_format(&bytes).is_none() {
+                return Err("not a PNG, JPEG, GIF, WebP or BMP image".to_string());
+            }
+            Ok(serde_json::json!({ "data": encoded }))
+        }
+        ImageInput::Path { path } => {
+            let path = Path::new(path);
+            let meta = fs::metadata(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            if !meta.is_file() {
+                return Err(format!("{} is not a file", path.display()));
+            }
+            if meta.len() > MAX_IMAGE_BYTES {
+                return Err(too_large(meta.len()));
+            }
+            let mut head = Vec::with_capacity(12);
+            File::open(path)
+                .and_then(|f| f.take(12).read_to_end(&mut head))
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            if sniff_format(&head).is_none() {
+                return Err(format!(
+                    "{} is not a PNG, JPEG, GIF, WebP or BMP image",
+                    path.display()
+                ));
+            }
+            // The runner may run with a different working directory.
+            let path = fs::canonicalize(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            Ok(serde_json::json!({ "path": path.to_string_lossy() }))
+        }
+    }
+}
+
+/// Checks every image and converts it to what the runner expects:
+/// `{ "path": ... }` for files and `{ "data": ... }` (plain base64) for blobs.
+pub(crate) fn prepare(images: &[ImageInput]) -> Result<Vec<serde_json::Value>, String> {
+    images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| prepare_one(image).map_err(|e| format!("Image {}: {e}", i + 1)))
+        .collect()
+}
+
+/// Whether the model in `dir` ships an image preprocessor, which is how
+/// vision-language checkpoints announce that they take images.
+pub(crate) fn accepts_images(dir: &Path) -> bool {
+    ["preprocessor_config.json", "processor_config.json"]
+        .iter()
+        .any(|name| dir.join(name).is_file())
+}
@@ -0,0 +1,100 @@
+// Per-model time-to-first-token and decode speed over recent generations,
+// for a benchmarking view. Fed from the same numbers as the usage log.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::usage::UsageEntry;
+
+/// Most recent generations kept per model.
+const MAX_SAMPLES: usize = 1000;
+
+#[derive(Clone, Copy)]
+struct Sample {
+    ttft_ms: Option<u64>,
+    tokens_per_sec: Option<f64>,
+}
+
+impl Sample {
+    fn from_entry(entry: &UsageEntry) -> Self {
+        // Measured from the first token, so load and prefill time don't count.
+        let tokens_per_sec = entry.ttft_ms.and_then(|ttft| {
+            let decode_ms = entry.duration_ms.saturating_sub(ttft);
+            (entry.completion_tokens > 1 && decode_ms > 0)
+                .then(|| (entry.completion_tokens - 1) as f64 * 1000.0 / decode_ms as f64)
+        });
+        Self {
+            ttft_ms: entry.ttft_ms,
+            tokens_per_sec,
+        }
+    }
+}
+
+#[derive(Default, serde::Serialize)]
+pub(crate) struct LatencyStats {
+    pub p50_ttft_ms: Option<u64>,
+    pub p95_ttft_ms: Option<u64>,
+    pub p50_tps: Option<f64>,
+    pub p95_tps: Option<f64>,
+    /// Generations the percentiles are taken over.
+    pub samples: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct LatencyState {
+    per_model: Mutex<HashMap<String, VecDeque<Sample>>>,
+}
+
+impl LatencyState {
+    pub(crate) fn record(&self, entry: &UsageEntry) {
+        if let Ok(mut per_model) = self.per_model.lock() {
+            push(&mut per_model, entry);
+        }
+    }
+
+    /// Replaces every sample with `entries`, e.g. those in the usage log.
+    pub(crate) fn reset_from(&self, entries: &[UsageEntry]) {
+        if let Ok(mut per_model) = self.per_model.lock() {
+            per_model.clear();
+            for entry in entries {
+                push(&mut per_model, entry);
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self, model: &str) -> LatencyStats {
+        let Some(samples) = self
+            .per_model
+            .lock()
+            .ok()
+            .and_then(|per_model| per_model.get(model).cloned())
+        else {
+            return LatencyStats::default();
+        };
+
+        let mut ttft: Vec<u64> = samples.iter().filter_map(|s| s.ttft_ms).collect();
+        ttft.sort_unstable();
+        let mut tps: Vec<f64> = samples.iter().filter_map(|s| s.tokens_per_sec).collect();
+        tps.sort_by(f64::total_cmp);
+        LatencyStats {
+            p50_ttft_ms: percentile(&ttft, 50.0),
+            p95_ttft_ms: percentile(&ttft, 95.0),
+            p50_tps: percentile(&tps, 50.0),
+            p95_tps: percentile(&tps, 95.0),
+            samples: samples.len(),
+        }
+    }
+}
+
+fn push(per_model: &mut HashMap<String, VecDeque<Sample>>, entry: &UsageEntry) {
+    let samples = per_model.entry(entry.model.clone()).or_default();
+    if samples.len() >= MAX_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(Sample::from_entry(entry));
+}
+
+/// Nearest-rank percentile of an ascending slice.
+fn percentile<T: Copy>(sorted: &[T], p: f64) -> Option<T> {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
@@ -0,0 +1,251 @@
+// Generation requests that arrive from outside the UI: `cerebro://generate?...`
+// deep links, which wait for the user to confirm them, and `--prompt`
+// command-line flags.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use reqwest::Url;
+
+/// Unconfirmed deep-link requests kept at once.
+const MAX_PENDING_REQUESTS: usize = 8;
+
+/// Deep-link requests shown to the user but not confirmed yet, oldest first.
+/// Any web page can open a `cerebro://` link, so those never start a
+/// generation on their own.
+#[derive(Default)]
+pub(crate) struct PendingLaunchState {
+    pending: Mutex<VecDeque<(String, LaunchGenerate)>>,
+}
+
+impl PendingLaunchState {
+    /// Holds `request` under `id`, dropping the oldest one past
+    /// `MAX_PENDING_REQUESTS` so a flood of links can't pile up.
+    pub(crate) fn insert(&self, id: String, request: LaunchGenerate) {
+        if let Ok(mut pending) = self.pending.lock() {
+            if pending.len() >= MAX_PENDING_REQUESTS {
+                pending.pop_front();
+            }
+            pending.push_back((id, request));
+        }
+    }
+
+    /// Removes and returns the request held under `id`.
+    pub(crate) fn take(&self, id: &str) -> Option<LaunchGenerate> {
+        let mut pending = self.pending.lock().ok()?;
+        let index = pending.iter().position(|(held, _)| held == id)?;
+        pending.remove(index).map(|(_, request)| request)
+    }
+}
+
+/// A validated request to start a generation on open.
+pub(crate) struct LaunchGenerate {
+    /// Falls back to the configured default model when absent.
+    pub model: Option<String>,
+    pub prompt: String,
+    pub max_new_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub seed: Option<u64>,
+}
+
+impl LaunchGenerate {
+    fn empty() -> Self {
+        Self {
+            model: None,
+            prompt: String::new(),
+            max_new_tokens: None,
+            temperature: None,
+            seed: None,
+        }
+    }
+
+    /// Applies one `key=value` parameter. Keys use snake_case in URLs and
+    /// kebab-case on the command line; both are accepted everywhere.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = |what: &str| format!("Invalid {what}: {value}");
+        match key.replace('-', "_").as_str() {
+            "model" => self.model = Some(value.trim().to_string()).filter(|m| !m.is_empty()),
+            "prompt" => self.prompt = value.to_string(),
+            "max_new_tokens" => {
+                self.max_new_tokens = Some(value.parse().map_err(|_| invalid("max_new_tokens"))?)
+            }
+            "temperature" => {
+                self.temperature = Some(value.parse().map_err(|_| invalid("temperature"))?)
+            }
+            "seed" => self.seed = Some(value.parse().map_err(|_| invalid("seed"))?),
+            other => return Err(format!("Unknown generate parameter: {other}")),
+        }
+        Ok(())
+    }
+
+    fn validated(self) -> Result<Self, String> {
+        if self.prompt.trim().is_empty() {
+            return Err("A prompt is required".to_string());
+        }
+        Ok(self)
+    }
+}
+
+/// Parses `cerebro://generate?model=...&prompt=...`. Returns `Ok(None)` for
+/// URLs of other schemes or actions, which aren't ours to handle.
+pub(crate) fn parse_deep_link(url: &Url) -> Result<Option<LaunchGenerate>, String> {
+    if url.scheme() != "cerebro" {
+        return Ok(None);
+    }
+    // `cerebro://generate` puts the action in the host, `cerebro:generate` in the path.
+    let action = url
+        .host_str()
+        .unwrap_or_else(|| url.path())
+        .trim_matches('/');
+    if action != "generate" {
+        return Err(format!("Unsupported cerebro:// action: {action}"));
+    }
+    let mut request = LaunchGenerate::empty();
+    for (key, value) in url.query_pairs() {
+        request.set(&key, &value)?;
+    }
+    request.validated().map(Some)
+}
+
+/// Parses `--prompt <text>` (plus `--model`, `--max-new-tokens`,
+/// `--temperature`, `--seed`, also as `--flag=value`) from launch arguments,
+/// skipping the executable path. Returns `Ok(None)` without `--prompt`;
+/// unrelated arguments the OS may add are ignored.
+pub(crate) fn parse_args(args: &[String]) -> Result<Option<LaunchGenerate>, String> {
+    const FLAGS: &[&str] = &["prompt", "model", "max-new-tokens", "temperature", "seed"];
+
+    let mut request = LaunchGenerate::empty();
+    let mut found_prompt = false;
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            continue;
+        };
+        let (name, inline) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (flag, None),
+        };
+        if !FLAGS.contains(&name) {
+            continue;
+        }
+        let value = match inline {
+            Some(value) => value,
+            None => rest
+                .next()
+                .cloned()
+                .ok_or_else(|| format!("--{name} needs a value"))?,
+        };
+        found_prompt |= name == "prompt";
+        request.set(name, &value)?;
+    }
+    if !found_prompt {
+        return Ok(None);
+    }
+    request.validated().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(url: &str) -> Result<Option<LaunchGenerate>, String> {
+        parse_deep_link(&Url::parse(url).unwrap())
+    }
+
+    fn args(list: &[&str]) -> Result<Option<LaunchGenerate>, String> {
+        let list: Vec<String> = std::iter::once("cerebro")
+            .chain(list.iter().copied())
+            .map(String::from)
+            .collect();
+        parse_args(&list)
+    }
+
+    #[test]
+    fn deep_links_accept_both_url_forms() {
+        for url in [
+            "cerebro://generate?prompt=hi%20there&model=org/m&seed=7",
+            "cerebro:generate?prompt=hi%20there&model=org/m&seed=7",
+            "cerebro://generate/?prompt=hi%20there&model=org/m&seed=7",
+        ] {
+            let request = link(url).unwrap().expect("a generate request");
+            assert_eq!(request.prompt, "hi there", "{url}");
+            assert_eq!(request.model.as_deref(), Some("org/m"), "{url}");
+            assert_eq!(request.seed, Some(7), "{url}");
+        }
+    }
+
+    #[test]
+    fn deep_links_reject_unknown_actions_keys_and_values() {
+        assert!(link("https://generate?prompt=hi").unwrap().is_none());
+        assert!(link("cerebro://delete?prompt=hi").is_err());
+        assert!(link("cerebro://generate?prompt=hi&voice=loud").is_err());
+        assert!(link("cerebro://generate?prompt=hi&temperature=warm").is_err());
+        assert!(link("cerebro://generate?prompt=%20%20").is_err());
+        assert!(link("cerebro://generate").is_err());
+    }
+
+    #[test]
+    fn deep_links_accept_kebab_case_keys_and_drop_a_blank_model() {
+        let request = link("cerebro://generate?prompt=hi&max-new-tokens=32&model=%20")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.max_new_tokens, Some(32));
+        assert!(request.model.is_none());
+    }
+
+    #[test]
+    fn args_accept_inline_and_separate_values() {
+        let inline = args(&["--prompt=hello", "--temperature=0.5"])
+            .unwrap()
+            .unwrap();
+        let separate = args(&["--prompt", "hello", "--temperature", "0.5"])
+            .unwrap()
+            .unwrap();
+        for request in [inline, separate] {
+            assert_eq!(request.prompt, "hello");
+            assert_eq!(request.temperature, Some(0.5));
+        }
+        let request = args(&["--max-new-tokens", "12", "--prompt", "x=y"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (request.max_new_tokens, request.prompt.as_str()),
+            (Some(12), "x=y")
+        );
+    }
+
+    #[test]
+    fn args_without_a_prompt_are_not_a_request() {
+        assert!(args(&[]).unwrap().is_none());
+        assert!(args(&["--model", "org/m"]).unwrap().is_none());
+        // Unknown flags and stray values the OS adds are skipped.
+        assert!(args(&["-psn_0_123", "--verbose", "file.txt"])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn args_reject_a_missing_value_or_a_blank_prompt() {
+        assert!(args(&["--prompt"]).is_err());
+        assert!(args(&["--prompt", "hi", "--seed"]).is_err());
+        assert!(args(&["--prompt", "   "]).is_err());
+        assert!(args(&["--prompt="]).is_err());
+        assert!(args(&["--prompt", "hi", "--seed", "-1"]).is_err());
+    }
+
+    #[test]
+    fn pending_requests_are_taken_once_and_capped() {
+        let state = PendingLaunchState::default();
+        for i in 0..=MAX_PENDING_REQUESTS {
+            let mut request = LaunchGenerate::empty();
+            request.prompt = format!("p{i}");
+            state.insert(format!("r{i}"), request);
+        }
+        assert!(state.take("r0").is_none());
+        let last = format!("r{MAX_PENDING_REQUESTS}");
+        assert_eq!(
+            state.take(&last).unwrap().prompt,
+            format!("p{MAX_PENDING_REQUESTS}")
+        );
+        assert!(state.take(&last).is_none());
+    }
+}
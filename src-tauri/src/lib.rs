@@ -1,18 +1,68 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use std::collections::HashMap;
+mod chunking;
+mod downloads;
+mod error;
+#[cfg(desktop)]
+mod global_shortcut;
+mod hf_token;
+mod http_client;
+mod http_download;
+mod http_guard;
+mod hub;
+mod images;
+mod launch;
+mod latency;
+mod mock;
+mod network;
+mod model_lock;
+mod models;
+mod partial_json;
+mod rate_limit;
+mod resource_limits;
+mod response_cache;
+mod settings;
+mod storage;
+mod usage;
+mod window_geometry;
+mod ws_server;
+
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{BufRead, BufReader, Write};
 use std::process::{Child, ChildStdin, Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{fs, path::PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, path::Path, path::PathBuf};
 
 use tauri::{
-    menu::{Menu, MenuItem},
+    menu::{Menu, MenuItem, PredefinedMenuItem},
     tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent},
     Emitter,
     Manager,
     State,
 };
+use base64::Engine;
+use tauri_plugin_deep_link::DeepLinkExt;
+
+use downloads::{ActiveDownload, DownloadInfo, DownloadProgress, ProgressClock};
+use error::CommandError;
+use http_client::{DownloadSpeed, HttpClientState, DEFAULT_MAX_REDIRECTS, HTTP_REQUEST_TIMEOUT};
+use http_download::HttpDownloadState;
+use hub::ModelInfoCache;
+use latency::{LatencyState, LatencyStats};
+use images::ImageInput;
+use network::NetworkState;
+use models::{CachedFormat, ModelFilter, ModelFormat, ModelIndexState, ModelInfo, ModelSort};
+use rate_limit::{RateLimit, RateLimiterState};
+use resource_limits::ResourceLimits;
+use response_cache::{CacheReplay, CachedGeneration, CachedToken};
+use chunking::MarkdownChunker;
+use settings::{
+    ChunkMode, CloseBehavior, Settings, SettingsState, TrayIconTheme, WindowPlacement,
+};
+use storage::{PrunePolicy, PruneReport, StorageSummary, StorageSummaryCache};
+use usage::UsageStats;
+use ws_server::{WsServerInfo, WsServerState};
 
 #[derive(Default)]
 struct PythonRuntimeState {
@@ -23,482 +73,5924 @@ struct PythonRuntimeState {
 struct PythonRuntimeInner {
     child: Option<Child>,
     stdin: Option<ChildStdin>,
+    generations: HashMap<String, ActiveGeneration>,
+    loaded_model: Option<LoadedModel>,
+    pending_switch: Option<PendingSwitch>,
+    capabilities: Option<RunnerCapabilities>,
+    /// Protocol features from the runner's `ready` line, which comes before the
+    /// slower capabilities report.
+    ready_features: Option<Vec<String>>,
+    /// The current runner sent `ready` (with or without features), or stopped
+    /// being waited for. Requests only wait for it once per `runner_epoch`.
+    runner_ready: bool,
+    /// Notified when `runner_ready` is set or the runner goes away.
+    ready_signal: Arc<Condvar>,
+    draining: bool,
+    mock_mode: bool,
+    download_logs: HashMap<String, DownloadLog>,
+    onboarding_download_id: Option<String>,
+    downloads: HashMap<String, ActiveDownload>,
+    download_queue: VecDeque<String>,
+    main_window_hidden: bool,
+    /// Recent `chat_generate` payloads (with their resolved seed), oldest first.
+    recent_payloads: VecDeque<(String, ChatGeneratePayload)>,
+    /// Bumped on every spawn so a reader thread only cleans up its own runner.
+    runner_epoch: u64,
+    compare_batches: HashMap<String, CompareBatchState>,
+    /// Signalled by the reader thread when the runner acknowledges `shutdown`.
+    shutdown_ack: Option<mpsc::Receiver<()>>,
+    /// `validate_model_loads` calls waiting for the runner's `validate_result`.
+    validations: HashMap<String, mpsc::Sender<Result<(), String>>>,
+    /// `count_tokens` calls waiting for the runner's `token_count`.
+    token_counts: HashMap<String, mpsc::Sender<Result<u64, String>>>,
+    /// From the last capabilities handshake. Unlike `capabilities` it survives
+    /// the runner exiting, since the installed backend rarely changes.
+    supported_architectures: Option<Vec<String>>,
+    /// Raw runner traffic, oldest first. Only recorded in dev mode.
+    raw_log: VecDeque<RawRunnerLine>,
+    /// When the current runner process was spawned.
+    runner_started_at: Option<Instant>,
+    /// Crash restarts since the runner last stayed up for `RUNNER_STABLE_UPTIME`.
+    crash_restarts: u32,
+    /// A crash restart is waiting out its backoff. Cleared by a deliberate stop.
+    restart_pending: bool,
 }
 
-#[derive(serde::Deserialize)]
-struct ChatGeneratePayload {
-    model: String,
-    prompt: String,
-    max_new_tokens: Option<u32>,
-    temperature: Option<f32>,
+/// A runner process that is gone, as reported in `cerebro:runtime_exited`.
+struct RunnerExit {
+    /// Set when it ended on its own rather than being killed.
+    exited: Option<std::process::ExitStatus>,
+    code: Option<i32>,
+    /// Generations that were still running.
+    aborted: Vec<String>,
 }
 
-#[derive(serde::Serialize)]
-struct ChatGenerateStarted {
-    generation_id: String,
-}
+/// How long `reap_runner` waits for a runner to exit on its own before killing it.
+const RUNNER_REAP_GRACE: Duration = Duration::from_millis(300);
 
-#[derive(serde::Deserialize)]
-struct ModelDownloadPayload {
-    repo_id: String,
-    revision: Option<String>,
-    token: Option<String>,
+/// How many past generations `chat_regenerate` can re-issue.
+const RETAINED_PAYLOADS: usize = 16;
+
+const DOWNLOAD_LOG_MAX_LINES: usize = 500;
+/// How long a finished download's log is kept around for `get_download_log`.
+const DOWNLOAD_LOG_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Default)]
+struct DownloadLog {
+    lines: VecDeque<String>,
+    finished_at: Option<Instant>,
 }
 
-#[derive(serde::Serialize)]
-struct ModelDownloadStarted {
-    download_id: String,
-    local_dir: String,
+/// Lines `get_raw_runner_log` keeps, across all streams.
+const RAW_LOG_MAX_LINES: usize = 1000;
+
+/// Set to `1` to enable the developer commands in a release build.
+const DEV_MODE_ENV: &str = "CEREBRO_DEV_MODE";
+
+#[derive(Clone, serde::Serialize)]
+struct RawRunnerLine {
+    /// `stdin` (sent to the runner), `stdout` or `stderr`.
+    stream: &'static str,
+    line: String,
+    /// Milliseconds since the Unix epoch.
+    at_ms: u64,
 }
 
-#[derive(serde::Deserialize)]
-struct HttpRequestPayload {
-    method: String,
-    url: String,
-    headers: Option<HashMap<String, String>>,
-    body: Option<String>,
+/// Debug builds, or release builds started with `CEREBRO_DEV_MODE=1`.
+fn dev_mode_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        cfg!(debug_assertions) || std::env::var_os(DEV_MODE_ENV).is_some_and(|v| v == "1")
+    })
 }
 
-#[derive(serde::Serialize)]
-struct HttpResponsePayload {
-    status: u16,
-    status_text: String,
-    body_text: String,
+fn require_dev_mode(command: &str) -> Result<(), String> {
+    if dev_mode_enabled() {
+        Ok(())
+    } else {
+        Err(format!("{command} is only available in dev mode ({DEV_MODE_ENV}=1)"))
+    }
 }
 
-#[tauri::command]
-fn greet(name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", name)
+/// Level for one runner stderr line, from a `logging`-style prefix
+/// (`WARNING:root:...`, `ERROR ...`) or a `warnings` category
+/// (`file.py:12: UserWarning: ...`). Traceback lines are errors up to and
+/// including the exception line that ends them.
+fn runtime_log_level(line: &str, in_traceback: &mut bool) -> &'static str {
+    if line.starts_with("Traceback (most recent call last)") {
+        *in_traceback = true;
+        return "error";
+    }
+    if *in_traceback {
+        // The frames are indented; the exception line that closes them isn't.
+        if !line.starts_with(' ') {
+            *in_traceback = false;
+        }
+        return "error";
+    }
+
+    let head = line
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+    match head.to_ascii_uppercase().as_str() {
+        "DEBUG" => return "debug",
+        "INFO" => return "info",
+        "WARN" | "WARNING" => return "warning",
+        "ERROR" | "CRITICAL" | "FATAL" => return "error",
+        _ => {}
+    }
+    if line.contains("Warning: ") {
+        "warning"
+    } else {
+        "info"
+    }
 }
 
-fn resolve_runner_script_path(app: &tauri::AppHandle) -> Result<std::path::PathBuf, String> {
-    // In dev, use the repo path; in bundled apps, use resource_dir.
-    let dev_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("py/cerebro_runner.py");
-    if dev_path.exists() {
-        return Ok(dev_path);
+/// Fields `redact_for_log` masks: credentials, and what the user typed or the
+/// model wrote.
+const REDACTED_LOG_FIELDS: &[&str] = &["token", "prompt", "prefill", "images", "body", "text"];
+
+/// A runner message for the console log, with `REDACTED_LOG_FIELDS` replaced
+/// by their size at any depth. Lines that aren't JSON are dropped entirely,
+/// since there is no telling what they hold.
+fn redact_for_log(line: &str) -> String {
+    fn redact(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, v) in map.iter_mut() {
+                    if v.is_null() {
+                        continue;
+                    }
+                    if REDACTED_LOG_FIELDS.contains(&key.as_str()) {
+                        *v = format!("[redacted, {} bytes]", v.to_string().len()).into();
+                    } else {
+                        redact(v);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+            _ => {}
+        }
     }
 
-    let resource_dir = app
-        .path()
-        .resource_dir()
-        .map_err(|e| format!("Failed to resolve resource dir: {e}"))?;
-    Ok(resource_dir.join("py/cerebro_runner.py"))
+    match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("[{} bytes, not JSON]", line.len()),
+    }
 }
 
-fn resolve_runner_sidecar_path(app: &tauri::AppHandle) -> Result<Option<std::path::PathBuf>, String> {
-    // In dev, allow running a locally-built sidecar in src-tauri/bin.
-    let dev_bin_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("bin");
-    let dev_candidates = [
-        dev_bin_dir.join("cerebro_runner-aarch64-apple-darwin"),
-        dev_bin_dir.join("cerebro_runner-x86_64-apple-darwin"),
-        dev_bin_dir.join("cerebro_runner"),
-    ];
-    if let Some(p) = dev_candidates.into_iter().find(|p| p.exists()) {
-        return Ok(Some(p));
+impl PythonRuntimeInner {
+    fn record_raw(&mut self, stream: &'static str, line: &str) {
+        if self.raw_log.len() >= RAW_LOG_MAX_LINES {
+            self.raw_log.pop_front();
+        }
+        self.raw_log.push_back(RawRunnerLine {
+            stream,
+            line: line.to_string(),
+            at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        });
     }
 
-    // In bundled apps, the sidecar may be placed under Resources and/or MacOS.
-    let resource_dir = app
-        .path()
-        .resource_dir()
-        .map_err(|e| format!("Failed to resolve resource dir: {e}"))?;
+    /// Writes one message to the runner. A failed write means the pipe is
+    /// broken, so stdin is dropped and the child killed; the reader thread then
+    /// sees EOF and reports `cerebro:runtime_exited`.
+    fn send_to_runner(&mut self, msg: &serde_json::Value, what: &str) -> Result<(), String> {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return Err("Python runtime is not running".to_string());
+        };
+        let result = write_runner_message(stdin, msg, what);
+        if result.is_ok() && dev_mode_enabled() {
+            let mut logged = msg.clone();
+            for field in ["token", "headers"] {
+                if logged.get(field).is_some_and(|v| !v.is_null()) {
+                    logged[field] = "[redacted]".into();
+                }
+            }
+            self.record_raw("stdin", &logged.to_string());
+        }
+        if result.is_err() {
+            self.stdin = None;
+            if let Some(child) = self.child.as_mut() {
+                let _ = child.kill();
+            }
+        }
+        result
+    }
 
-    let mut candidates = vec![
-        resource_dir.join("cerebro_runner-aarch64-apple-darwin"),
-        resource_dir.join("cerebro_runner-x86_64-apple-darwin"),
-        resource_dir.join("cerebro_runner"),
-        resource_dir.join("bin").join("cerebro_runner-aarch64-apple-darwin"),
-        resource_dir.join("bin").join("cerebro_runner-x86_64-apple-darwin"),
-        resource_dir.join("bin").join("cerebro_runner"),
-    ];
+    /// The newest generation not already being cancelled or stopped by Cerebro.
+    fn latest_cancellable_generation(&self) -> Option<String> {
+        self.generations
+            .iter()
+            .filter(|(_, g)| g.cancel_requested_at.is_none() && g.finish_reason.is_none())
+            .max_by_key(|(_, g)| g.started_at)
+            .map(|(id, _)| id.clone())
+    }
 
-    if let Some(contents_dir) = resource_dir.parent() {
-        candidates.push(contents_dir.join("MacOS").join("cerebro_runner-aarch64-apple-darwin"));
-        candidates.push(contents_dir.join("MacOS").join("cerebro_runner-x86_64-apple-darwin"));
-        candidates.push(contents_dir.join("MacOS").join("cerebro_runner"));
-        candidates.push(contents_dir.join("MacOS").join("bin").join("cerebro_runner-aarch64-apple-darwin"));
-        candidates.push(contents_dir.join("MacOS").join("bin").join("cerebro_runner-x86_64-apple-darwin"));
-        candidates.push(contents_dir.join("MacOS").join("bin").join("cerebro_runner"));
+    /// Records the runner's `model_loaded`. The revision is only known when it
+    /// loaded the folder of the pending switch.
+    fn record_loaded_model(&mut self, repo_id: &str, local_dir: &str) {
+        let revision = self
+            .pending_switch
+            .as_ref()
+            .filter(|p| p.repo_id == repo_id && p.local_dir == local_dir)
+            .and_then(|p| p.revision.clone());
+        self.loaded_model = Some(LoadedModel {
+            repo_id: repo_id.to_string(),
+            revision,
+            local_dir: local_dir.to_string(),
+        });
     }
 
-    Ok(candidates.into_iter().find(|p| p.exists()))
-}
+    /// The revision of `repo_id` currently loaded, so a generation keeps using
+    /// what `switch_model` loaded instead of the default branch.
+    fn loaded_revision(&self, repo_id: &str) -> Option<String> {
+        self.loaded_model
+            .as_ref()
+            .filter(|m| m.repo_id == repo_id)
+            .and_then(|m| m.revision.clone())
+    }
 
-fn generate_id() -> String {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis();
-    format!("gen-{now}-{}", rand_suffix())
-}
+    /// Lets requests waiting in `wait_for_runner_ready` through.
+    fn mark_runner_ready(&mut self) {
+        self.runner_ready = true;
+        self.ready_signal.notify_all();
+    }
 
-fn rand_suffix() -> String {
-    // Keep it dependency-free.
-    let n = (SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .subsec_nanos()) as u64;
-    format!("{:x}", n)
-}
+    /// Kills (if need be) and reaps the runner, then forgets its state. `None`
+    /// when no runner was running.
+    fn reap_runner(&mut self) -> Option<RunnerExit> {
+        let mut child = self.child.take()?;
+        self.stdin = None;
+        // Only a runner that died on its own can have hit a resource limit. Its
+        // stdout can close a moment before it can be reaped, so give it a grace
+        // period before concluding it needs killing.
+        let deadline = Instant::now() + RUNNER_REAP_GRACE;
+        let exited = loop {
+            match child.try_wait() {
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                status => break status.ok().flatten(),
+            }
+        };
+        let status = exited.or_else(|| {
+            let _ = child.kill();
+            child.wait().ok()
+        });
+        if self
+            .runner_started_at
+            .is_some_and(|t| t.elapsed() >= RUNNER_STABLE_UPTIME)
+        {
+            self.crash_restarts = 0;
+        }
+        Some(RunnerExit {
+            exited,
+            code: status.and_then(|status| status.code()),
+            aborted: self.reset_runtime_state(),
+        })
+    }
 
-fn ensure_python_runtime(app: &tauri::AppHandle, state: &PythonRuntimeState) -> Result<(), String> {
-    let mut inner = state
-        .inner
-        .lock()
-        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    /// Forgets everything tied to the runner process, returning the ids of the
+    /// generations that were still running.
+    fn reset_runtime_state(&mut self) -> Vec<String> {
+        self.runner_started_at = None;
+        self.loaded_model = None;
+        self.pending_switch = None;
+        self.capabilities = None;
+        self.ready_features = None;
+        self.runner_ready = false;
+        self.ready_signal.notify_all();
+        // The runner took its download threads with it; partial files stay on disk
+        // so these can be resumed later.
+        for download in self.downloads.values_mut() {
+            download.paused = true;
+            download.queued = false;
+            download.clock = None;
+        }
+        self.download_queue.clear();
+        // Members of an unfinished compare batch are reported through `chat_error`,
+        // including the ones still waiting for their turn.
+        let mut aborted: Vec<String> = self
+            .compare_batches
+            .drain()
+            .flat_map(|(_, batch)| batch.pending)
+            .filter_map(|payload| payload.generation_id)
+            .collect();
+        // Dropping the senders wakes pending validations with a disconnect.
+        self.validations.clear();
+        self.token_counts.clear();
+        aborted.extend(self.generations.drain().map(|(id, _)| id));
+        aborted
+    }
 
-    if inner.child.is_some() && inner.stdin.is_some() {
-        return Ok(());
+    /// Removes a finished generation. A compare-batch member also records its
+    /// metrics, and the batch's `cerebro:compare_done` goes out with the last one.
+    fn finish_generation(
+        &mut self,
+        app: &tauri::AppHandle,
+        generation_id: &str,
+        outcome: &'static str,
+    ) -> Option<ActiveGeneration> {
+        let g = self.generations.remove(generation_id)?;
+        if let Some(tag) = &g.compare {
+            let result = CompareResult::new(generation_id, &g, tag, outcome);
+            self.record_compare_result(app, &tag.batch_id, result);
+        }
+        Some(g)
     }
 
-    let sidecar_path = resolve_runner_sidecar_path(app)?;
+    /// Adds a member's result to its batch, then starts the next member or,
+    /// after the last one, emits `cerebro:compare_done`.
+    fn record_compare_result(
+        &mut self,
+        app: &tauri::AppHandle,
+        batch_id: &str,
+        result: CompareResult,
+    ) {
+        let Some(batch) = self.compare_batches.get_mut(batch_id) else {
+            return;
+        };
+        batch.results.push(result);
+        if batch.results.len() < batch.expected {
+            if !batch.pending.is_empty() {
+                // Started once the caller releases the lock.
+                let (app, batch_id) = (app.clone(), batch_id.to_string());
+                std::thread::spawn(move || start_next_compare_member(&app, &batch_id));
+            }
+            return;
+        }
+        if let Some(batch) = self.compare_batches.remove(batch_id) {
+            let _ = app.emit(
+                "cerebro:compare_done",
+                serde_json::json!({
+                    "type": "compare_done",
+                    "batch_id": batch_id,
+                    "results": batch.results,
+                }),
+            );
+        }
+    }
 
-    let mut cmd = if let Some(sidecar_path) = sidecar_path {
-        Command::new(sidecar_path)
-    } else {
-        let script_path = resolve_runner_script_path(app)?;
-        if !script_path.exists() {
-            return Err(format!(
-                "Runner not found at {}",
-                script_path.display()
-            ));
+    /// Takes `generation_id` out of its batch's queue if it hasn't started yet.
+    fn take_pending_compare_member(&mut self, generation_id: &str) -> Option<CompareTag> {
+        self.compare_batches.values_mut().find_map(|batch| {
+            let index = batch
+                .pending
+                .iter()
+                .position(|p| p.generation_id.as_deref() == Some(generation_id))?;
+            batch.pending.remove(index)?.compare
+        })
+    }
+
+    /// True when high-frequency events should be held back because nobody can see them.
+    fn quiet_while_hidden(&self, app: &tauri::AppHandle) -> bool {
+        self.main_window_hidden && !app.state::<SettingsState>().get().emit_when_hidden
+    }
+
+    /// Records a streamed token and returns the text to emit now, if any. While
+    /// the window is hidden tokens accumulate in `pending_text` instead, as does
+    /// text the markdown chunker isn't ready to release.
+    fn accept_token(
+        &mut self,
+        app: &tauri::AppHandle,
+        generation_id: &str,
+        token: &str,
+    ) -> Option<String> {
+        let hidden = self.quiet_while_hidden(app);
+        let Some(g) = self.generations.get_mut(generation_id) else {
+            return Some(token.to_string());
+        };
+        let quiet = hidden && !g.external;
+        g.phase = GenerationPhase::Decoding;
+        g.first_token_at.get_or_insert_with(Instant::now);
+        g.last_activity = Instant::now();
+        g.chunks += 1;
+        g.text.push_str(token);
+        if g.cache_key.is_some() {
+            g.cached_tokens.push(CachedToken {
+                at_ms: g.started_at.elapsed().as_millis() as u64,
+                text: token.to_string(),
+            });
         }
-        let mut cmd = Command::new("python3");
-        cmd.arg("-u").arg(script_path);
-        cmd
-    };
+        if quiet {
+            g.pending_text.push_str(token);
+            return None;
+        }
+        if g.chunker.is_none() {
+            return Some(token.to_string());
+        }
+        g.pending_text.push_str(token);
+        g.take_held_text(false)
+    }
 
-    let mut child = cmd
-        .env("PYTHONUNBUFFERED", "1")
-        .env("PYTHONIOENCODING", "utf-8")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .map_err(|e| format!("Failed to start runner: {e}"))?;
+    /// Best-effort parse of the text so far, for generations that asked for JSON output.
+    fn partial_json(&self, generation_id: &str) -> Option<serde_json::Value> {
+        let g = self.generations.get(generation_id)?;
+        if !g.json_output {
+            return None;
+        }
+        partial_json::repair(&g.text)
+    }
 
-    let stdin = child
-        .stdin
-        .take()
-        .ok_or_else(|| "Failed to open runner stdin".to_string())?;
-    let stdout = child
-        .stdout
-        .take()
-        .ok_or_else(|| "Failed to open runner stdout".to_string())?;
+    /// Emits all of `generation_id`'s held-back text as one `chat_token`, since
+    /// it is ending. Emitting under the lock keeps it ordered before its
+    /// terminal event.
+    fn flush_pending_tokens(&mut self, app: &tauri::AppHandle, generation_id: &str) {
+        if let Some(g) = self.generations.get_mut(generation_id) {
+            if let Some(token) = g.take_held_text(true) {
+                emit_held_text(app, generation_id, g, &token);
+            }
+        }
+    }
 
-    // Spawn a blocking reader thread that emits events.
-    let app_handle = app.clone();
-    std::thread::spawn(move || {
-        let reader = BufReader::new(stdout);
-        for line in reader.lines().flatten() {
-            
-            eprintln!("Runner output: {}", line);
+    /// Emits what was held back while the window was hidden, one `chat_token`
+    /// per generation. They are still running, so the markdown chunker keeps
+    /// back a trailing partial word or open fence as usual. Emitting under the
+    /// lock keeps it ordered before any token the reader accepts afterwards.
+    fn release_pending_tokens(&mut self, app: &tauri::AppHandle) {
+        for (id, g) in self.generations.iter_mut() {
+            if let Some(token) = g.take_held_text(false) {
+                emit_held_text(app, id, g, &token);
+            }
+        }
+    }
 
-            let parsed: Result<serde_json::Value, _> = serde_json::from_str(&line);
-            let Ok(v) = parsed else {
-                let _ = app_handle.emit(
-                    "cerebro:chat_error",
-                    serde_json::json!({
-                      "generation_id": null,
-                      "message": "Invalid runner JSON"
-                    }),
-                );
+    fn download_infos(&self) -> Vec<DownloadInfo> {
+        self.downloads
+            .iter()
+            .map(|(download_id, download)| DownloadInfo {
+                download_id: download_id.clone(),
+                repo_id: download.repo_id.clone(),
+                paused: download.paused,
+                queued: download.queued,
+                progress: download.progress.clone(),
+            })
+            .collect()
+    }
+
+    fn persist_downloads(&self, app: &tauri::AppHandle) {
+        if let Err(e) = downloads::persist(app, &self.downloads) {
+            eprintln!("Failed to persist downloads: {e}");
+        }
+    }
+
+    fn running_downloads(&self) -> usize {
+        self.downloads
+            .values()
+            .filter(|d| !d.paused && !d.queued)
+            .count()
+    }
+
+    /// Parks `download_id` at the back of the queue and tells the UI its position.
+    fn enqueue_download(&mut self, app: &tauri::AppHandle, download_id: &str) {
+        if let Some(download) = self.downloads.get_mut(download_id) {
+            download.paused = false;
+            download.queued = true;
+        }
+        self.download_queue.push_back(download_id.to_string());
+        self.emit_queue_positions(app);
+    }
+
+    fn emit_queue_positions(&self, app: &tauri::AppHandle) {
+        for (i, download_id) in self.download_queue.iter().enumerate() {
+            let _ = app.emit(
+                "cerebro:model_download_queued",
+                serde_json::json!({
+                    "type": "download_queued",
+                    "download_id": download_id,
+                    "repo_id": self.downloads.get(download_id).map(|d| &d.repo_id),
+                    "position": i + 1,
+                }),
+            );
+        }
+    }
+
+    /// Hands queued downloads to the runner while there are free slots.
+    fn start_queued_downloads(&mut self, app: &tauri::AppHandle, settings: &Settings) {
+        let mut advanced = false;
+        while self.stdin.is_some()
+            && self.running_downloads() < settings.max_concurrent_downloads
+        {
+            let Some(download_id) = self.download_queue.pop_front() else {
+                break;
+            };
+            let Some(download) = self.downloads.get(&download_id).filter(|d| d.queued).cloned()
+            else {
                 continue;
             };
-
-            let msg_type = v.get("type").and_then(|x| x.as_str()).unwrap_or("");
-            match msg_type {
-                "chat_token" => {
-                    let _ = app_handle.emit("cerebro:chat_token", v);
-                }
-                "done" => {
-                    let _ = app_handle.emit("cerebro:chat_done", v);
-                }
-                "error" => {
-                    let _ = app_handle.emit("cerebro:chat_error", v);
-                }
-                "download_started" => {
-                    let _ = app_handle.emit("cerebro:model_download_started", v);
-                }
-                "download_progress" => {
-                    let _ = app_handle.emit("cerebro:model_download_progress", v);
-                }
-                "download_done" => {
-                    let _ = app_handle.emit("cerebro:model_download_done", v);
-                }
-                "download_error" => {
-                    let _ = app_handle.emit("cerebro:model_download_error", v);
+            advanced = true;
+            match send_download_message(self, &download_id, &download, settings) {
+                Ok(()) => {
+                    if let Some(download) = self.downloads.get_mut(&download_id) {
+                        download.queued = false;
+                    }
                 }
-                _ => {
-                    // ready/shutdown/unknown: ignore for now
+                Err(e) => {
+                    self.downloads.remove(&download_id);
+                    let _ = app.emit(
+                        "cerebro:model_download_error",
+                        serde_json::json!({
+                            "type": "download_error",
+                            "download_id": download_id,
+                            "repo_id": download.repo_id,
+                            "message": e,
+                        }),
+                    );
                 }
             }
         }
-    });
+        if advanced {
+            self.persist_downloads(app);
+            self.emit_queue_positions(app);
+        }
+    }
 
-    inner.child = Some(child);
-    inner.stdin = Some(stdin);
-    Ok(())
-}
+    fn record_download_line(&mut self, download_id: &str, line: &str, finished: bool) {
+        self.evict_download_logs();
 
-fn sanitize_dir_component(input: &str) -> String {
-    let mut out = String::with_capacity(input.len());
-    for ch in input.chars() {
-        let ok = ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.';
-        out.push(if ok { ch } else { '_' });
+        let log = self.download_logs.entry(download_id.to_string()).or_default();
+        if log.lines.len() >= DOWNLOAD_LOG_MAX_LINES {
+            log.lines.pop_front();
+        }
+        log.lines.push_back(line.to_string());
+        if finished {
+            log.finished_at = Some(Instant::now());
+        }
     }
-    if out.is_empty() {
-        "model".to_string()
-    } else {
-        out
+
+    fn evict_download_logs(&mut self) {
+        self.download_logs
+            .retain(|_, log| log.finished_at.is_none_or(|t| t.elapsed() < DOWNLOAD_LOG_TTL));
     }
 }
 
-fn compute_model_local_dir(app: &tauri::AppHandle, repo_id: &str) -> Result<PathBuf, String> {
-    let base = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+/// What the runner reports about its environment right after startup.
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct RunnerCapabilities {
+    versions: HashMap<String, String>,
+    devices: Vec<String>,
+    warnings: Vec<String>,
+    /// Optional protocol features, e.g. `prefill`.
+    features: Vec<String>,
+    memory: RunnerMemory,
+    /// Model classes the runner's backend can load, e.g. `LlamaForCausalLM`.
+    architectures: Vec<String>,
+}
 
-    let models_dir = base.join("models");
-    fs::create_dir_all(&models_dir)
-        .map_err(|e| format!("Failed to create models dir: {e}"))?;
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct RunnerMemory {
+    ram_bytes: Option<u64>,
+    /// Total memory of the first CUDA device, if any.
+    vram_bytes: Option<u64>,
+}
 
-    Ok(models_dir.join(sanitize_dir_component(repo_id)))
+#[derive(Clone, serde::Serialize)]
+struct LoadedModel {
+    repo_id: String,
+    revision: Option<String>,
+    local_dir: String,
 }
 
-#[tauri::command]
-fn python_runtime_start(app: tauri::AppHandle, state: State<PythonRuntimeState>) -> Result<(), String> {
-    ensure_python_runtime(&app, &state)
+struct PendingSwitch {
+    switch_id: String,
+    repo_id: String,
+    revision: Option<String>,
+    /// The folder being loaded, which tells its `model_loaded` apart from one
+    /// another request triggered for the same repo.
+    local_dir: String,
+}
+
+/// How often `cerebro:chat_progress` is emitted while a generation has no tokens yet.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum GenerationPhase {
+    Loading,
+    Prefill,
+    Decoding,
+}
+
+impl GenerationPhase {
+    fn from_runner(phase: &str) -> Option<Self> {
+        match phase {
+            "loading" => Some(Self::Loading),
+            "prefill" => Some(Self::Prefill),
+            "decoding" => Some(Self::Decoding),
+            _ => None,
+        }
+    }
+}
+
+struct ActiveGeneration {
+    model: String,
+    started_at: Instant,
+    phase: GenerationPhase,
+    first_token_at: Option<Instant>,
+    /// Everything streamed so far.
+    text: String,
+    /// Tokens not yet emitted because the window was hidden.
+    pending_text: String,
+    /// Set when Cerebro itself stopped the generation; echoed in `chat_done`.
+    finish_reason: Option<&'static str>,
+    /// When `chat_cancel` asked the runner to stop; cleared by the acknowledgement.
+    cancel_requested_at: Option<Instant>,
+    /// Streamed chunks, roughly one per token.
+    chunks: u32,
+    /// Set for generations started by `chat_compare`.
+    compare: Option<CompareTag>,
+    /// Constrained to JSON, so the reader also emits `cerebro:chat_partial_json`.
+    json_output: bool,
+    /// Streamed to a WebSocket client, which needs tokens even while the window is hidden.
+    external: bool,
+    /// Set in `ChunkMode::Markdown`, where tokens are released at safe boundaries.
+    chunker: Option<MarkdownChunker>,
+    /// Set for a response-cache miss; tokens are recorded so the finished
+    /// output can be stored under this key.
+    cache_key: Option<String>,
+    cached_tokens: Vec<CachedToken>,
+    /// The start, or the latest token.
+    last_activity: Instant,
+}
+
+/// How long the runner gets to acknowledge a cancel before the UI is warned.
+const CANCEL_ACK_GRACE: Duration = Duration::from_secs(5);
+
+impl ActiveGeneration {
+    fn new() -> Self {
+        Self {
+            model: String::new(),
+            started_at: Instant::now(),
+            last_activity: Instant::now(),
+            phase: GenerationPhase::Loading,
+            first_token_at: None,
+            text: String::new(),
+            pending_text: String::new(),
+            finish_reason: None,
+            cancel_requested_at: None,
+            chunks: 0,
+            compare: None,
+            json_output: false,
+            external: false,
+            chunker: None,
+            cache_key: None,
+            cached_tokens: Vec::new(),
+        }
+    }
+
+    /// The finished output to store in the response cache, if this
+    /// generation opted in.
+    fn cache_entry(self, finish_reason: Option<String>) -> Option<(String, CachedGeneration)> {
+        let key = self.cache_key?;
+        let entry = CachedGeneration {
+            tokens: self.cached_tokens,
+            finish_reason,
+        };
+        Some((key, entry))
+    }
+
+    /// Takes the held-back text that may go out now: all of it once `finished`,
+    /// else the prefix the markdown chunker deems safe (all without one).
+    fn take_held_text(&mut self, finished: bool) -> Option<String> {
+        let len = match &self.chunker {
+            Some(chunker) if !finished => chunker.safe_len(&self.pending_text),
+            _ => self.pending_text.len(),
+        };
+        if len == 0 {
+            return None;
+        }
+        let rest = self.pending_text.split_off(len);
+        let chunk = std::mem::replace(&mut self.pending_text, rest);
+        if let Some(chunker) = self.chunker.as_mut() {
+            chunker.advance(&chunk);
+        }
+        Some(chunk)
+    }
+
+    /// Records `reason` as why Cerebro is stopping this generation, unless an
+    /// earlier condition or a user cancel got there first. Returns whether it won.
+    fn claim_stop(&mut self, reason: &'static str) -> bool {
+        if self.finish_reason.is_some() || self.cancel_requested_at.is_some() {
+            return false;
+        }
+        self.finish_reason = Some(reason);
+        true
+    }
+
+    fn usage_entry(&self) -> usage::UsageEntry {
+        usage::UsageEntry {
+            model: self.model.clone(),
+            completion_tokens: u64::from(self.chunks),
+            duration_ms: self.started_at.elapsed().as_millis() as u64,
+            ttft_ms: self
+                .first_token_at
+                .map(|t| t.duration_since(self.started_at).as_millis() as u64),
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+/// What a terminal runner message (`done` / `cancelled` / `error`) is reported
+/// as. Each generation gets exactly one: the message that removed it from the
+/// map wins, and any later one for the same id (`removed` is `None`) is dropped.
+#[derive(Debug, PartialEq)]
+enum TerminalEvent {
+    /// `cerebro:chat_done`, with the finish reason to report, if any.
+    Done(Option<String>),
+    Cancelled,
+    Error,
+}
+
+fn terminal_event(
+    msg_type: &str,
+    runner_reason: Option<&str>,
+    removed: Option<&ActiveGeneration>,
+) -> Option<TerminalEvent> {
+    let g = removed?;
+    let claimed = g.finish_reason.map(str::to_string);
+    Some(match msg_type {
+        // The runner only reports `done` when it stopped on its own before
+        // seeing a cancel, so its reason (`stop` / `length` / `stop_sequence`)
+        // beats any stop Cerebro requested meanwhile.
+        "done" => TerminalEvent::Done(runner_reason.map(str::to_string).or(claimed)),
+        // A stop Cerebro initiated (e.g. max duration) is a normal finish to the UI.
+        "cancelled" => match claimed {
+            Some(reason) => TerminalEvent::Done(Some(reason)),
+            None => TerminalEvent::Cancelled,
+        },
+        _ => TerminalEvent::Error,
+    })
+}
+
+/// Stamps `repo_id` as used now in the model index. Never moves the stamp
+/// backwards, so concurrent generations can't race it to an older time.
+fn record_last_used(app: &tauri::AppHandle, repo_id: &str) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let result = app.state::<ModelIndexState>().update_entry(app, repo_id, |entry| {
+        entry.last_used = Some(entry.last_used.map_or(now, |t| t.max(now)));
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to record last use of {repo_id}: {e}");
+    }
+}
+
+/// Writes a finished generation to the response cache. Called after the
+/// runtime lock is released, since it touches the disk.
+fn store_cached_generation(app: &tauri::AppHandle, cached: Option<(String, CachedGeneration)>) {
+    if let Some((key, entry)) = cached {
+        if let Err(e) = response_cache::store(app, &key, &entry) {
+            eprintln!("{e}");
+        }
+    }
+}
+
+/// Appends a finished generation to the usage log and the latency samples.
+/// Called after the runtime lock is released, since it touches the disk.
+fn record_usage(app: &tauri::AppHandle, entry: Option<usage::UsageEntry>) {
+    if let Some(entry) = entry {
+        app.state::<LatencyState>().record(&entry);
+        if let Err(e) = usage::append(app, &entry) {
+            eprintln!("{e}");
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CompareTag {
+    batch_id: String,
+    model: String,
+}
+
+impl CompareTag {
+    /// Mirrors a `chat_token` as `cerebro:compare_token` so the UI can route it
+    /// to the model's column.
+    fn emit_token(&self, app: &tauri::AppHandle, generation_id: &str, token: &str) {
+        let _ = app.emit(
+            "cerebro:compare_token",
+            serde_json::json!({
+                "type": "compare_token",
+                "batch_id": self.batch_id,
+                "model": self.model,
+                "generation_id": generation_id,
+                "token": token,
+            }),
+        );
+    }
+}
+
+struct CompareBatchState {
+    expected: usize,
+    results: Vec<CompareResult>,
+    /// Members not started yet. They run one at a time, each started by the
+    /// previous one's end, so the runner never loads two models at once.
+    pending: VecDeque<ChatGeneratePayload>,
+}
+
+#[derive(serde::Serialize)]
+struct CompareResult {
+    model: String,
+    generation_id: String,
+    /// `done`, `cancelled` or `error`.
+    outcome: &'static str,
+    ttft_ms: Option<u64>,
+    tokens: u32,
+    tokens_per_sec: Option<f64>,
+}
+
+impl CompareResult {
+    fn new(
+        generation_id: &str,
+        g: &ActiveGeneration,
+        tag: &CompareTag,
+        outcome: &'static str,
+    ) -> Self {
+        let decode_secs = g.first_token_at.map(|t| t.elapsed().as_secs_f64());
+        Self {
+            model: tag.model.clone(),
+            generation_id: generation_id.to_string(),
+            outcome,
+            ttft_ms: g
+                .first_token_at
+                .map(|t| t.duration_since(g.started_at).as_millis() as u64),
+            tokens: g.chunks,
+            tokens_per_sec: decode_secs
+                .filter(|secs| *secs > 0.0)
+                .map(|secs| f64::from(g.chunks) / secs),
+        }
+    }
+}
+
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// One turn of a conversation the caller keeps itself.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct ChatMessage {
+    role: ChatRole,
+    content: String,
+}
+
+#[derive(Clone, serde::Deserialize)]
+struct ChatGeneratePayload {
+    model: String,
+    /// Optional alongside `messages`, where a non-empty prompt is sent as one
+    /// more user turn after them.
+    #[serde(default)]
+    prompt: String,
+    /// The whole conversation so far, rendered with the model's chat template
+    /// by the runner. Replaces the runner's own history for this generation,
+    /// which is left untouched. When it has no `system` turn, the default system
+    /// prompt goes first. Images go on the last user turn.
+    messages: Option<Vec<ChatMessage>>,
+    max_new_tokens: Option<u32>,
+    temperature: Option<f32>,
+    /// Nucleus sampling cutoff in 0..=1. Unset fields use the model's
+    /// `generation_config.json` defaults.
+    top_p: Option<f32>,
+    top_k: Option<u32>,
+    /// Above 1 discourages repeating earlier tokens.
+    repetition_penalty: Option<f32>,
+    /// Strings that end the generation when produced (not included in the
+    /// output). `chat_done` then has `finish_reason: "stop_sequence"`.
+    stop: Option<Vec<String>>,
+    seed: Option<u64>,
+    /// Existing assistant text the model should continue from. Only the
+    /// continuation is streamed back.
+    prefill: Option<String>,
+    response_format: Option<ResponseFormat>,
+    /// Hard wall-clock limit. Unlike an idle timeout it does not reset on tokens.
+    max_duration_ms: Option<u64>,
+    /// Idle limit: fails the generation with a `timeout` error when this long
+    /// passes without a token. The first window includes loading the model.
+    timeout_ms: Option<u64>,
+    #[serde(skip)]
+    compare: Option<CompareTag>,
+    #[serde(skip)]
+    external: bool,
+    /// Preassigned by callers that must know the id before the first event.
+    #[serde(skip)]
+    generation_id: Option<String>,
+    /// A generation to cancel in the same critical section that starts this one.
+    #[serde(skip)]
+    replaces: Option<String>,
+    /// Serve a deterministic request from the response cache, or store its
+    /// output there on a miss. Only applies to requests sent as `messages`,
+    /// whose whole context is part of the key.
+    #[serde(default)]
+    cache: bool,
+    /// How a cache hit is streamed; instant by default.
+    cache_replay: Option<CacheReplay>,
+    /// For vision-language models, placed before the prompt in the user turn.
+    images: Option<Vec<ImageInput>>,
+}
+
+impl ChatGeneratePayload {
+    /// A plain request with every optional parameter left to its default.
+    fn new(model: String, prompt: String) -> Self {
+        Self {
+            model,
+            prompt,
+            messages: None,
+            max_new_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            repetition_penalty: None,
+            stop: None,
+            seed: None,
+            prefill: None,
+            response_format: None,
+            max_duration_ms: None,
+            timeout_ms: None,
+            compare: None,
+            external: false,
+            generation_id: None,
+            replaces: None,
+            cache: false,
+            cache_replay: None,
+            images: None,
+        }
+    }
+}
+
+/// Generation parameters shared by every model in a `chat_compare` batch.
+#[derive(Default, serde::Deserialize)]
+struct CompareParams {
+    max_new_tokens: Option<u32>,
+    temperature: Option<f32>,
+    seed: Option<u64>,
+    max_duration_ms: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct CompareBatch {
+    batch_id: String,
+    /// Shared by all members so differences come from the models alone.
+    seed: u64,
+    generations: Vec<CompareMember>,
+}
+
+#[derive(serde::Serialize)]
+struct CompareMember {
+    model: String,
+    generation_id: String,
+}
+
+/// Constrains decoding so the output parses as JSON (optionally matching a schema).
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseFormat {
+    JsonObject,
+    JsonSchema { schema: serde_json::Value },
+}
+
+impl ResponseFormat {
+    /// Accepts the schema as an object or as a JSON string, and checks the
+    /// keywords the runner's grammar builder relies on.
+    fn validated(self) -> Result<Self, String> {
+        let Self::JsonSchema { schema } = self else {
+            return Ok(self);
+        };
+        let schema = match schema {
+            serde_json::Value::String(raw) => serde_json::from_str(&raw)
+                .map_err(|e| format!("response_format.schema is not valid JSON: {e}"))?,
+            other => other,
+        };
+        if !schema.is_object() {
+            return Err("response_format.schema must be a JSON object".to_string());
+        }
+        if schema.get("properties").is_some_and(|p| !p.is_object()) {
+            return Err("response_format.schema.properties must be an object".to_string());
+        }
+        if let Some(required) = schema.get("required") {
+            let all_strings = required
+                .as_array()
+                .is_some_and(|r| r.iter().all(|x| x.is_string()));
+            if !all_strings {
+                return Err(
+                    "response_format.schema.required must be an array of strings".to_string(),
+                );
+            }
+        }
+        Ok(Self::JsonSchema { schema })
+    }
+}
+
+#[derive(serde::Serialize)]
+struct PythonCheck {
+    interpreter: String,
+    version: Option<String>,
+    sidecar: bool,
+}
+
+#[derive(serde::Serialize)]
+struct RuntimeVersions {
+    versions: HashMap<String, String>,
+    devices: Vec<String>,
+    warnings: Vec<String>,
+    features: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct DrainReport {
+    /// Generations that finished on their own while draining.
+    completed: Vec<String>,
+    /// Generations still running when the timeout hit and the runner was killed.
+    aborted: Vec<String>,
+    forced: bool,
+    waited_ms: u64,
+}
+
+#[derive(serde::Serialize)]
+struct ChatGenerateStarted {
+    generation_id: String,
+    seed: u64,
+}
+
+#[derive(serde::Serialize)]
+struct GenerationMeta {
+    generation_id: String,
+    model: String,
+    /// Unix milliseconds.
+    started_at: u64,
+    elapsed_ms: u64,
+    phase: GenerationPhase,
+    /// A cancel was sent and the runner hasn't confirmed it yet.
+    cancelling: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct GenerationSnapshot {
+    generation_id: String,
+    phase: GenerationPhase,
+    elapsed_ms: u64,
+    /// Full text streamed so far, including anything held back while hidden.
+    text: String,
+}
+
+#[derive(serde::Serialize)]
+struct RuntimeStatus {
+    running: bool,
+    pid: Option<u32>,
+    uptime_ms: Option<u64>,
+    /// Set when the runner was found to have exited since the last check.
+    exit_code: Option<i32>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct RuntimeSnapshot {
+    running: bool,
+    mock_mode: bool,
+    draining: bool,
+    switching: bool,
+    loaded_model: Option<LoadedModel>,
+}
+
+/// Payload of `cerebro:state_resync`.
+#[derive(Clone, serde::Serialize)]
+struct StateSnapshot {
+    generations: Vec<GenerationSnapshot>,
+    downloads: Vec<DownloadInfo>,
+    runtime: RuntimeSnapshot,
+}
+
+#[derive(serde::Serialize)]
+struct MemoryEstimate {
+    weights_bytes: u64,
+    kv_cache_bytes: u64,
+    overhead_bytes: u64,
+    total_bytes: u64,
+    /// `cuda` when the model would go to a GPU, otherwise `ram`.
+    device: String,
+    /// `None` until the runner has reported the machine's memory.
+    available_bytes: Option<u64>,
+    headroom_bytes: Option<i64>,
+    fits: Option<bool>,
+}
+
+#[derive(serde::Serialize)]
+struct ModelSwitchStarted {
+    switch_id: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ModelDownloadPayload {
+    repo_id: String,
+    revision: Option<String>,
+    /// Overrides the token saved with `set_hf_token` for this download.
+    token: Option<String>,
+    /// Size of the download, for the free-space check. Falls back to the
+    /// repo's storage from cached Hub metadata; unchecked when neither is known.
+    expected_size_bytes: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct ModelDownloadStarted {
+    download_id: String,
+    local_dir: String,
+    /// True when the download is waiting behind `max_concurrent_downloads`.
+    queued: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct HttpRequestPayload {
+    method: String,
+    url: String,
+    headers: Option<HashMap<String, String>>,
+    body: Option<String>,
+    /// Limit for the whole exchange, body included. Defaults to
+    /// `HTTP_REQUEST_TIMEOUT`.
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    response_as: HttpResponseAs,
+    /// Set to false to get redirects back as they are, `Location` header and all.
+    follow_redirects: Option<bool>,
+    /// Defaults to `DEFAULT_MAX_REDIRECTS`; at most `MAX_REDIRECTS_LIMIT`.
+    max_redirects: Option<usize>,
+}
+
+const MAX_REDIRECTS_LIMIT: usize = 20;
+
+/// How `http_request` returns the response body.
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HttpResponseAs {
+    /// Decoded as text into `body_text`.
+    #[default]
+    Text,
+    /// The raw bytes, base64-encoded into `body_base64`, for images and other
+    /// binary content that text decoding would mangle.
+    Base64,
+}
+
+#[derive(serde::Deserialize)]
+struct HttpDownloadPayload {
+    url: String,
+    headers: Option<HashMap<String, String>>,
+    /// Where to save, relative to app_data_dir/downloads. Missing folders are
+    /// created; the file appears only once the download completes.
+    dest_path: String,
+    /// Replace a file already at `dest_path` instead of failing.
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(serde::Serialize)]
+struct HttpResponsePayload {
+    status: u16,
+    status_text: String,
+    /// Where the response came from, after any redirects.
+    final_url: String,
+    /// Empty when the body was requested as base64.
+    body_text: String,
+    body_base64: Option<String>,
+    content_type: Option<String>,
+    /// Lowercased names. Repeated headers are joined with `, `; values that
+    /// aren't visible ASCII are left out.
+    headers: HashMap<String, String>,
+}
+
+#[tauri::command]
+fn greet(name: &str) -> String {
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
+
+/// Environment variable pointing at a runner script, for nonstandard installs.
+const RUNNER_SCRIPT_ENV: &str = "CEREBRO_RUNNER";
+
+/// Finds `cerebro_runner.py`, trying in order: the `runner_script_path`
+/// setting, `$CEREBRO_RUNNER`, the repo checkout (dev), resource_dir and the
+/// executable's directory. Some Linux packaging (AppImage, Flatpak) keeps the
+/// script next to the executable, or has no resolvable resource_dir at all.
+fn resolve_runner_script_path(app: &tauri::AppHandle) -> Result<std::path::PathBuf, String> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    if let Some(path) = app.state::<SettingsState>().get().runner_script_path {
+        candidates.push(PathBuf::from(path));
+    }
+    if let Some(path) = std::env::var_os(RUNNER_SCRIPT_ENV).filter(|p| !p.is_empty()) {
+        candidates.push(PathBuf::from(path));
+    }
+    candidates.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("py/cerebro_runner.py"));
+    if let Ok(resource_dir) = app.path().resource_dir() {
+        candidates.push(resource_dir.join("py/cerebro_runner.py"));
+    }
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+    {
+        candidates.push(exe_dir.join("py/cerebro_runner.py"));
+        candidates.push(exe_dir.join("cerebro_runner.py"));
+    }
+
+    if let Some(found) = candidates.iter().find(|p| p.is_file()) {
+        return Ok(found.clone());
+    }
+    let tried: Vec<String> = candidates.iter().map(|p| p.display().to_string()).collect();
+    Err(format!("Python runner not found (tried: {})", tried.join(", ")))
+}
+
+fn resolve_runner_sidecar_path(app: &tauri::AppHandle) -> Result<Option<std::path::PathBuf>, String> {
+    // In dev, allow running a locally-built sidecar in src-tauri/bin.
+    let dev_bin_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("bin");
+    let dev_candidates = [
+        dev_bin_dir.join("cerebro_runner-aarch64-apple-darwin"),
+        dev_bin_dir.join("cerebro_runner-x86_64-apple-darwin"),
+        dev_bin_dir.join("cerebro_runner"),
+    ];
+    if let Some(p) = dev_candidates.into_iter().find(|p| p.exists()) {
+        return Ok(Some(p));
+    }
+
+    // In bundled apps, the sidecar may be placed under Resources and/or MacOS.
+    let resource_dir = app
+        .path()
+        .resource_dir()
+        .map_err(|e| format!("Failed to resolve resource dir: {e}"))?;
+
+    let mut candidates = vec![
+        resource_dir.join("cerebro_runner-aarch64-apple-darwin"),
+        resource_dir.join("cerebro_runner-x86_64-apple-darwin"),
+        resource_dir.join("cerebro_runner"),
+        resource_dir.join("bin").join("cerebro_runner-aarch64-apple-darwin"),
+        resource_dir.join("bin").join("cerebro_runner-x86_64-apple-darwin"),
+        resource_dir.join("bin").join("cerebro_runner"),
+    ];
+
+    if let Some(contents_dir) = resource_dir.parent() {
+        candidates.push(contents_dir.join("MacOS").join("cerebro_runner-aarch64-apple-darwin"));
+        candidates.push(contents_dir.join("MacOS").join("cerebro_runner-x86_64-apple-darwin"));
+        candidates.push(contents_dir.join("MacOS").join("cerebro_runner"));
+        candidates.push(contents_dir.join("MacOS").join("bin").join("cerebro_runner-aarch64-apple-darwin"));
+        candidates.push(contents_dir.join("MacOS").join("bin").join("cerebro_runner-x86_64-apple-darwin"));
+        candidates.push(contents_dir.join("MacOS").join("bin").join("cerebro_runner"));
+    }
+
+    Ok(candidates.into_iter().find(|p| p.exists()))
+}
+
+/// Ids are unique within the process thanks to the counter; the timestamp
+/// and random suffix keep them apart from ids issued by earlier runs, e.g.
+/// persisted downloads.
+fn generate_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let seq = NEXT.fetch_add(1, Ordering::Relaxed);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("gen-{now}-{seq:x}-{}", rand_suffix())
+}
+
+fn rand_suffix() -> String {
+    use ring::rand::SecureRandom;
+
+    let mut bytes = [0u8; 4];
+    if ring::rand::SystemRandom::new().fill(&mut bytes).is_err() {
+        // Still unique through the counter; only cross-run separation suffers.
+        bytes = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos()
+            .to_le_bytes();
+    }
+    format!("{:08x}", u32::from_le_bytes(bytes))
+}
+
+/// Interpreter names tried, in order, when no bundled sidecar is available.
+#[cfg(windows)]
+const PYTHON_CANDIDATES: &[&str] = &["python", "py", "python3"];
+#[cfg(not(windows))]
+const PYTHON_CANDIDATES: &[&str] = &["python3", "python"];
+
+fn find_on_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(program);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        #[cfg(windows)]
+        {
+            let exe = candidate.with_extension("exe");
+            if exe.is_file() {
+                return Some(exe);
+            }
+        }
+        None
+    })
+}
+
+fn python_not_found() -> CommandError {
+    CommandError::PythonNotFound {
+        tried: PYTHON_CANDIDATES.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+/// Interpreter used when the `python_interpreter` setting is unset.
+const PYTHON_INTERPRETER_ENV: &str = "CEREBRO_PYTHON";
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    true
+}
+
+/// Resolves a configured interpreter. Bare names such as `py` are looked up
+/// on PATH; anything else must be an existing executable file.
+fn validate_interpreter(raw: &str) -> Result<PathBuf, CommandError> {
+    let path = PathBuf::from(raw);
+    let path = match path.components().count() {
+        1 => find_on_path(raw).unwrap_or(path),
+        _ => path,
+    };
+    if !path.is_file() {
+        return Err(format!("Python interpreter not found: {raw}").into());
+    }
+    if !is_executable(&path) {
+        return Err(format!("Python interpreter is not executable: {}", path.display()).into());
+    }
+    Ok(path)
+}
+
+/// The `python_interpreter` setting, then `$CEREBRO_PYTHON`, then the first
+/// of `PYTHON_CANDIDATES` found on PATH.
+fn find_python_interpreter(app: &tauri::AppHandle) -> Result<PathBuf, CommandError> {
+    let configured = app
+        .state::<SettingsState>()
+        .get()
+        .python_interpreter
+        .or_else(|| std::env::var(PYTHON_INTERPRETER_ENV).ok())
+        .filter(|p| !p.trim().is_empty());
+    if let Some(raw) = configured {
+        return validate_interpreter(&raw);
+    }
+    PYTHON_CANDIDATES
+        .iter()
+        .find_map(|c| find_on_path(c))
+        .ok_or_else(python_not_found)
+}
+
+fn ensure_python_runtime(
+    app: &tauri::AppHandle,
+    state: &PythonRuntimeState,
+) -> Result<(), CommandError> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+
+    if inner.child.is_some() && inner.stdin.is_some() {
+        let epoch = inner.runner_epoch;
+        drop(inner);
+        return wait_for_runner_ready(&state.inner, epoch, RUNNER_READY_TIMEOUT);
+    }
+    // A child without stdin is left over from a failed write.
+    handle_runner_exit(&mut inner, app);
+
+    let sidecar_path = resolve_runner_sidecar_path(app)?;
+    let runtime = state.inner.clone();
+
+    let uses_sidecar = sidecar_path.is_some();
+    let mut cmd = if let Some(sidecar_path) = sidecar_path {
+        Command::new(sidecar_path)
+    } else {
+        let script_path = resolve_runner_script_path(app)?;
+        let mut cmd = Command::new(find_python_interpreter(app)?);
+        cmd.arg("-u").arg(script_path);
+        cmd
+    };
+
+    let settings = app.state::<SettingsState>().get();
+    cmd.envs(&settings.runner_env);
+    if let Some(cwd) = &settings.runner_cwd {
+        cmd.current_dir(cwd);
+    }
+    if let Some(limits) = &settings.resource_limits {
+        resource_limits::apply(&mut cmd, limits);
+    }
+
+    let mut child = cmd
+        .env("PYTHONUNBUFFERED", "1")
+        .env("PYTHONIOENCODING", "utf-8")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound && !uses_sidecar {
+                python_not_found()
+            } else {
+                CommandError::from(format!("Failed to start runner: {e}"))
+            }
+        })?;
+
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| "Failed to open runner stdin".to_string())?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| "Failed to open runner stdout".to_string())?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| "Failed to open runner stderr".to_string())?;
+
+    // Passed through to our own stderr as before, emitted as
+    // `cerebro:runtime_log` for the UI, and kept for `get_raw_runner_log` in
+    // dev mode. Read lossily: stopping at a line that isn't UTF-8 would leave
+    // the pipe full and stall the runner.
+    let stderr_runtime = runtime.clone();
+    let stderr_app = app.clone();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stderr);
+        let mut buf = Vec::new();
+        let mut in_traceback = false;
+        while reader.read_until(b'\n', &mut buf).is_ok_and(|n| n > 0) {
+            let text = String::from_utf8_lossy(&buf);
+            let line = text.trim_end_matches(['\r', '\n']);
+            eprintln!("{line}");
+            if !line.trim().is_empty() {
+                let _ = stderr_app.emit(
+                    "cerebro:runtime_log",
+                    serde_json::json!({
+                        "level": runtime_log_level(line, &mut in_traceback),
+                        "message": line,
+                        "timestamp": SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64,
+                    }),
+                );
+            }
+            if dev_mode_enabled() {
+                if let Ok(mut inner) = stderr_runtime.lock() {
+                    inner.record_raw("stderr", line);
+                }
+            }
+            buf.clear();
+        }
+    });
+
+    inner.runner_epoch += 1;
+    inner.runner_ready = false;
+    let epoch = inner.runner_epoch;
+    let (ack_tx, ack_rx) = mpsc::channel();
+    inner.shutdown_ack = Some(ack_rx);
+
+    // Spawn a blocking reader thread that emits events.
+    let app_handle = app.clone();
+    std::thread::spawn(move || {
+        let reader = BufReader::new(stdout);
+        for line in reader.lines().map_while(Result::ok) {
+            if dev_mode_enabled() {
+                eprintln!("Runner output: {}", redact_for_log(&line));
+                if let Ok(mut inner) = runtime.lock() {
+                    inner.record_raw("stdout", &line);
+                }
+            }
+
+            let parsed: Result<serde_json::Value, _> = serde_json::from_str(&line);
+            let Ok(v) = parsed else {
+                let _ = app_handle.emit(
+                    "cerebro:chat_error",
+                    serde_json::json!({
+                      "generation_id": null,
+                      "message": "Invalid runner JSON"
+                    }),
+                );
+                continue;
+            };
+
+            let msg_type = v.get("type").and_then(|x| x.as_str()).unwrap_or("");
+            let generation_id = v
+                .get("generation_id")
+                .and_then(|x| x.as_str())
+                .map(str::to_string);
+
+            if let Some(download_id) = v.get("download_id").and_then(|x| x.as_str()) {
+                if let Ok(mut inner) = runtime.lock() {
+                    let finished = matches!(
+                        msg_type,
+                        "download_done" | "download_error" | "download_cancelled"
+                    );
+                    inner.record_download_line(download_id, &line, finished);
+                }
+            }
+            let is_onboarding = {
+                let download_id = v.get("download_id").and_then(|x| x.as_str());
+                download_id.is_some()
+                    && runtime
+                        .lock()
+                        .map(|inner| inner.onboarding_download_id.as_deref() == download_id)
+                        .unwrap_or(false)
+            };
+            if is_onboarding {
+                let stage = match msg_type {
+                    "download_done" => Some("done"),
+                    "download_error" => Some("error"),
+                    "download_cancelled" => Some("cancelled"),
+                    "download_started" | "download_progress" => Some("downloading"),
+                    _ => None,
+                };
+                if let Some(stage) = stage {
+                    let _ = app_handle.emit(
+                        "cerebro:onboarding_progress",
+                        serde_json::json!({
+                            "stage": stage,
+                            "download_id": v.get("download_id"),
+                            "repo_id": v.get("repo_id"),
+                            "n": v.get("n"),
+                            "total": v.get("total"),
+                            "message": v.get("message"),
+                            // Set by the runner for auth failures, e.g. `gated`.
+                            "reason": v.get("reason"),
+                            "license_url": v.get("license_url"),
+                        }),
+                    );
+                }
+                if matches!(stage, Some("done" | "error" | "cancelled")) {
+                    if let Ok(mut inner) = runtime.lock() {
+                        inner.onboarding_download_id = None;
+                    }
+                }
+            }
+
+            match msg_type {
+                "chat_token" => {
+                    let token = v.get("token").and_then(|x| x.as_str()).unwrap_or("");
+                    let (chunk, compare, partial) = match (&generation_id, runtime.lock()) {
+                        (Some(id), Ok(mut inner)) => {
+                            let chunk = inner.accept_token(&app_handle, id, token);
+                            let compare = inner.generations.get(id).and_then(|g| g.compare.clone());
+                            let partial = chunk.is_some().then(|| inner.partial_json(id)).flatten();
+                            (chunk, compare, partial)
+                        }
+                        _ => (Some(token.to_string()), None, None),
+                    };
+                    if let Some(chunk) = chunk {
+                        if let (Some(tag), Some(id)) = (compare, &generation_id) {
+                            tag.emit_token(&app_handle, id, &chunk);
+                        }
+                        if let (Some(value), Some(id)) = (partial, &generation_id) {
+                            emit_partial_json(&app_handle, id, value);
+                        }
+                        let mut v = v;
+                        v["token"] = chunk.into();
+                        let _ = app_handle.emit("cerebro:chat_token", v);
+                    }
+                }
+                "phase" => {
+                    let phase = v
+                        .get("phase")
+                        .and_then(|x| x.as_str())
+                        .and_then(GenerationPhase::from_runner);
+                    if let (Some(id), Some(phase), Ok(mut inner)) =
+                        (&generation_id, phase, runtime.lock())
+                    {
+                        if let Some(g) = inner.generations.get_mut(id) {
+                            g.phase = phase;
+                        }
+                    }
+                }
+                // Each generation gets exactly one terminal event: whichever of
+                // `done` / `cancelled` / `error` removes it from the map first.
+                "done" => {
+                    let mut v = v;
+                    let mut usage = None;
+                    let mut cached = None;
+                    let mut first = generation_id.is_none();
+                    if let (Some(id), Ok(mut inner)) = (&generation_id, runtime.lock()) {
+                        inner.flush_pending_tokens(&app_handle, id);
+                        let removed = inner.finish_generation(&app_handle, id, "done");
+                        usage = removed.as_ref().map(ActiveGeneration::usage_entry);
+                        // Older runners don't send a reason.
+                        let runner_reason = v
+                            .get("finish_reason")
+                            .and_then(|x| x.as_str())
+                            .map(str::to_string);
+                        let event =
+                            terminal_event("done", runner_reason.as_deref(), removed.as_ref());
+                        if let Some(TerminalEvent::Done(reason)) = event {
+                            first = true;
+                            if let Some(reason) = reason {
+                                v["finish_reason"] = reason.into();
+                            }
+                        }
+                        // Output Cerebro cut short isn't what the request produces.
+                        cached = removed
+                            .filter(|g| runner_reason.is_some() || g.finish_reason.is_none())
+                            .and_then(|g| g.cache_entry(runner_reason));
+                    }
+                    if first {
+                        record_usage(&app_handle, usage);
+                        store_cached_generation(&app_handle, cached);
+                        let _ = app_handle.emit("cerebro:chat_done", v);
+                    }
+                }
+                "cancelled" => {
+                    let mut usage = None;
+                    let mut event = generation_id.is_none().then_some(TerminalEvent::Cancelled);
+                    if let (Some(id), Ok(mut inner)) = (&generation_id, runtime.lock()) {
+                        inner.flush_pending_tokens(&app_handle, id);
+                        let claimed = inner
+                            .generations
+                            .get(id)
+                            .is_some_and(|g| g.finish_reason.is_some());
+                        let outcome = if claimed { "done" } else { "cancelled" };
+                        let removed = inner.finish_generation(&app_handle, id, outcome);
+                        event = terminal_event("cancelled", None, removed.as_ref());
+                        if claimed {
+                            usage = removed.as_ref().map(ActiveGeneration::usage_entry);
+                        }
+                    }
+                    match event {
+                        Some(TerminalEvent::Done(reason)) => {
+                            record_usage(&app_handle, usage);
+                            let mut v = v;
+                            v["type"] = "done".into();
+                            v["finish_reason"] = reason.into();
+                            let _ = app_handle.emit("cerebro:chat_done", v);
+                        }
+                        Some(_) => {
+                            let _ = app_handle.emit("cerebro:chat_cancelled", v);
+                        }
+                        None => {}
+                    }
+                }
+                "error" => {
+                    let mut v = v;
+                    let out_of_memory =
+                        v.get("reason").and_then(|x| x.as_str()) == Some("out_of_memory");
+                    let mut first = generation_id.is_none();
+                    if let (Some(id), Ok(mut inner)) = (&generation_id, runtime.lock()) {
+                        inner.flush_pending_tokens(&app_handle, id);
+                        let removed = inner.finish_generation(&app_handle, id, "error");
+                        first = terminal_event("error", None, removed.as_ref()).is_some();
+                        if out_of_memory {
+                            v["unloaded"] = unload_after_oom(&app_handle, &mut inner).into();
+                        }
+                    }
+                    if first {
+                        let _ = app_handle.emit("cerebro:chat_error", v);
+                    }
+                }
+                "model_unloaded" => {
+                    if let Ok(mut inner) = runtime.lock() {
+                        inner.loaded_model = None;
+                    }
+                    let _ = app_handle.emit("cerebro:model_unloaded", v);
+                }
+                "ready" => {
+                    // Older runners send a bare `ready`; feature checks then
+                    // rely on the capabilities report.
+                    let features = v
+                        .get("features")
+                        .and_then(|x| serde_json::from_value::<Vec<String>>(x.clone()).ok());
+                    if let Ok(mut inner) = runtime.lock() {
+                        if features.is_some() {
+                            inner.ready_features = features;
+                        }
+                        inner.mark_runner_ready();
+                    }
+                }
+                "capabilities" => {
+                    let caps: RunnerCapabilities =
+                        serde_json::from_value(v.clone()).unwrap_or_default();
+                    for warning in &caps.warnings {
+                        let _ = app_handle.emit(
+                            "cerebro:runtime_warning",
+                            serde_json::json!({ "message": warning }),
+                        );
+                    }
+                    if let Ok(mut inner) = runtime.lock() {
+                        inner.supported_architectures = Some(caps.architectures.clone());
+                        inner.capabilities = Some(caps);
+                        inner.mark_runner_ready();
+                    }
+                    let _ = app_handle.emit("cerebro:runtime_capabilities", v);
+                }
+                "model_loaded" => {
+                    if let Ok(mut inner) = runtime.lock() {
+                        let repo_id = v.get("repo_id").and_then(|x| x.as_str()).unwrap_or("");
+                        let local_dir = v.get("model").and_then(|x| x.as_str()).unwrap_or("");
+                        inner.record_loaded_model(repo_id, local_dir);
+                    }
+                    let _ = app_handle.emit("cerebro:model_loaded", v);
+                }
+                "model_load_progress" => {
+                    let _ = app_handle.emit("cerebro:model_switch_progress", v);
+                }
+                "model_load_done" | "model_load_error" => {
+                    let switch_id = v.get("switch_id").and_then(|x| x.as_str());
+                    if let Ok(mut inner) = runtime.lock() {
+                        if inner.pending_switch.as_ref().map(|p| p.switch_id.as_str()) == switch_id {
+                            inner.pending_switch = None;
+                        }
+                    }
+                    let event = if msg_type == "model_load_done" {
+                        "cerebro:model_switch_done"
+                    } else {
+                        "cerebro:model_switch_error"
+                    };
+                    let _ = app_handle.emit(event, v);
+                }
+                "validate_result" => {
+                    let validate_id = v.get("validate_id").and_then(|x| x.as_str());
+                    let result = if v.get("ok").and_then(|x| x.as_bool()) == Some(true) {
+                        Ok(())
+                    } else {
+                        Err(v
+                            .get("message")
+                            .and_then(|x| x.as_str())
+                            .unwrap_or("Model failed to load")
+                            .to_string())
+                    };
+                    if let (Some(id), Ok(mut inner)) = (validate_id, runtime.lock()) {
+                        if let Some(tx) = inner.validations.remove(id) {
+                            let _ = tx.send(result);
+                        }
+                    }
+                }
+                "token_count" => {
+                    let count_id = v.get("count_id").and_then(|x| x.as_str());
+                    let result = match v.get("tokens").and_then(|x| x.as_u64()) {
+                        Some(tokens) if v.get("ok").and_then(|x| x.as_bool()) == Some(true) => {
+                            Ok(tokens)
+                        }
+                        _ => Err(v
+                            .get("message")
+                            .and_then(|x| x.as_str())
+                            .unwrap_or("Failed to count tokens")
+                            .to_string()),
+                    };
+                    if let (Some(id), Ok(mut inner)) = (count_id, runtime.lock()) {
+                        if let Some(tx) = inner.token_counts.remove(id) {
+                            let _ = tx.send(result);
+                        }
+                    }
+                }
+                "download_started" => {
+                    let _ = app_handle.emit("cerebro:model_download_started", v);
+                }
+                "download_progress" => {
+                    // The snapshot is always kept, so dropping the event while
+                    // hidden loses nothing `list_active_downloads` can't recover.
+                    let mut quiet = false;
+                    if let Ok(mut inner) = runtime.lock() {
+                        let download = v
+                            .get("download_id")
+                            .and_then(|x| x.as_str())
+                            .and_then(|id| inner.downloads.get_mut(id));
+                        if let Some(download) = download {
+                            let n = v.get("n").and_then(|x| x.as_u64()).unwrap_or(0);
+                            match download.clock.as_mut() {
+                                Some(clock) => clock.observe(n),
+                                None => download.clock = Some(ProgressClock::new(n)),
+                            }
+                            download.progress = Some(DownloadProgress {
+                                n,
+                                total: v.get("total").and_then(|x| x.as_u64()),
+                                desc: v.get("desc").and_then(|x| x.as_str()).map(str::to_string),
+                            });
+                        }
+                        quiet = inner.quiet_while_hidden(&app_handle);
+                    }
+                    if !quiet {
+                        let _ = app_handle.emit("cerebro:model_download_progress", v);
+                    }
+                }
+                "download_paused" => {
+                    if let Some(download_id) = v.get("download_id").and_then(|x| x.as_str()) {
+                        if let Ok(mut inner) = runtime.lock() {
+                            if let Some(download) = inner.downloads.get_mut(download_id) {
+                                download.paused = true;
+                                // Time spent paused would drag the measured throughput down.
+                                download.clock = None;
+                            }
+                            inner.persist_downloads(&app_handle);
+                            let settings = app_handle.state::<SettingsState>().get();
+                            inner.start_queued_downloads(&app_handle, &settings);
+                        }
+                    }
+                    let _ = app_handle.emit("cerebro:model_download_paused", v);
+                }
+                "download_done" | "download_error" | "download_cancelled" => {
+                    app_handle.state::<StorageSummaryCache>().invalidate();
+                    if let Some(download_id) = v.get("download_id").and_then(|x| x.as_str()) {
+                        if let Ok(mut inner) = runtime.lock() {
+                            if inner.downloads.remove(download_id).is_some() {
+                                inner.persist_downloads(&app_handle);
+                            }
+                            let settings = app_handle.state::<SettingsState>().get();
+                            inner.start_queued_downloads(&app_handle, &settings);
+                        }
+                    }
+                    let event = match msg_type {
+                        "download_done" => "cerebro:model_download_done",
+                        "download_cancelled" => "cerebro:model_download_cancelled",
+                        _ => "cerebro:model_download_error",
+                    };
+                    let _ = app_handle.emit(event, v);
+                }
+                "shutdown" | "bye" => {
+                    // Sent without touching the lock: `finish_shutdown` waits for it unlocked.
+                    let _ = ack_tx.send(());
+                    let _ = app_handle.emit("cerebro:runtime_shutdown_ack", v);
+                }
+                _ => {
+                    // ready/unknown: ignore for now
+                }
+            }
+        }
+
+        // EOF: the runner is gone. Ignore it if a newer runner already replaced this one.
+        if let Ok(mut inner) = runtime.lock() {
+            if inner.runner_epoch == epoch {
+                handle_runner_exit(&mut inner, &app_handle);
+            }
+        }
+    });
+
+    inner.child = Some(child);
+    inner.runner_started_at = Some(Instant::now());
+    // Whatever spawned this runner, a scheduled crash restart is now moot.
+    inner.restart_pending = false;
+    inner.stdin = Some(stdin);
+    drop(inner);
+    wait_for_runner_ready(&state.inner, epoch, RUNNER_READY_TIMEOUT)
+}
+
+/// How long a request waits for a freshly spawned runner's `ready` line.
+const RUNNER_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Blocks until runner `epoch` has sent `ready`, so a request sent right after
+/// a spawn isn't refused by `require_runner_feature`. Returns at once when it
+/// already has. Past `timeout` the runner counts as ready, so later requests
+/// don't wait again, and the feature checks decide.
+fn wait_for_runner_ready(
+    runtime: &Mutex<PythonRuntimeInner>,
+    epoch: u64,
+    timeout: Duration,
+) -> Result<(), CommandError> {
+    let inner = runtime
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let signal = inner.ready_signal.clone();
+    let (mut inner, wait) = signal
+        .wait_timeout_while(inner, timeout, |inner| {
+            inner.runner_epoch == epoch && inner.child.is_some() && !inner.runner_ready
+        })
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    if inner.runner_epoch != epoch || inner.child.is_none() {
+        return Err("Python runner exited during startup".to_string().into());
+    }
+    if wait.timed_out() {
+        eprintln!("Python runner not ready after {}s", timeout.as_secs());
+        inner.runner_ready = true;
+    }
+    Ok(())
+}
+
+/// Consecutive crash restarts before giving up.
+const RUNNER_RESTART_ATTEMPTS: u32 = 3;
+/// Delay before the first restart; doubled for each further attempt.
+const RUNNER_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+/// A runner that stayed up this long resets the attempt count.
+const RUNNER_STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+/// Respawns the runner after `delay`, unless it was started or stopped on
+/// purpose in the meantime.
+fn schedule_runner_restart(app: tauri::AppHandle, attempt: u32, delay: Duration) {
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        let state = app.state::<PythonRuntimeState>();
+        let still_wanted = state
+            .inner
+            .lock()
+            .map(|mut inner| std::mem::take(&mut inner.restart_pending))
+            .unwrap_or(false);
+        if !still_wanted {
+            return;
+        }
+        match ensure_python_runtime(&app, &state) {
+            Ok(()) => {
+                let _ = app.emit(
+                    "cerebro:runtime_restarted",
+                    serde_json::json!({ "attempt": attempt }),
+                );
+            }
+            Err(e) => {
+                let _ = app.emit(
+                    "cerebro:runtime_restart_failed",
+                    serde_json::json!({ "attempt": attempt, "error": e }),
+                );
+            }
+        }
+    });
+}
+
+/// Cleans up after a runner that went away without `python_runtime_stop`
+/// (crash, OOM kill, broken pipe): fails its generations and emits
+/// `cerebro:runtime_exited`. Does nothing if the child was already taken.
+fn handle_runner_exit(inner: &mut PythonRuntimeInner, app: &tauri::AppHandle) {
+    let Some(RunnerExit {
+        exited,
+        code,
+        aborted,
+    }) = inner.reap_runner()
+    else {
+        return;
+    };
+    let settings = app.state::<SettingsState>().get();
+    if let (Some(limits), Some(status)) = (&settings.resource_limits, exited) {
+        if let Some(limit) = resource_limits::exceeded_limit(limits, status) {
+            let _ = app.emit(
+                "cerebro:runtime_resource_limit",
+                serde_json::json!({ "limit": limit, "code": code }),
+            );
+        }
+    }
+    inner.persist_downloads(app);
+
+    let restart = (settings.auto_restart_runner
+        && !inner.draining
+        && inner.crash_restarts < RUNNER_RESTART_ATTEMPTS)
+        .then(|| {
+            inner.crash_restarts += 1;
+            inner.restart_pending = true;
+            (inner.crash_restarts, RUNNER_RESTART_BACKOFF * 2u32.pow(inner.crash_restarts - 1))
+        });
+    let _ = app.emit(
+        "cerebro:runtime_crashed",
+        serde_json::json!({
+            "code": code,
+            "restarting": restart.is_some(),
+            "attempt": restart.map(|(attempt, _)| attempt),
+            "max_attempts": RUNNER_RESTART_ATTEMPTS,
+            "retry_in_ms": restart.map(|(_, delay)| delay.as_millis() as u64),
+        }),
+    );
+    if let Some((attempt, delay)) = restart {
+        schedule_runner_restart(app.clone(), attempt, delay);
+    }
+
+    for generation_id in &aborted {
+        let _ = app.emit(
+            "cerebro:chat_error",
+            serde_json::json!({
+                "type": "error",
+                "generation_id": generation_id,
+                "message": "The Python runner exited unexpectedly",
+                "reason": "runtime_exited",
+            }),
+        );
+    }
+    let _ = app.emit(
+        "cerebro:runtime_exited",
+        serde_json::json!({
+            "code": code,
+            "aborted_generations": aborted,
+        }),
+    );
+}
+
+fn emit_partial_json(app: &tauri::AppHandle, generation_id: &str, value: serde_json::Value) {
+    let _ = app.emit(
+        "cerebro:chat_partial_json",
+        serde_json::json!({
+            "type": "chat_partial_json",
+            "generation_id": generation_id,
+            "value": value,
+        }),
+    );
+}
+
+/// Emits text `g` held back as a `chat_token`, mirrored for compare batches and
+/// followed by the repaired JSON so far for JSON output.
+fn emit_held_text(app: &tauri::AppHandle, id: &str, g: &ActiveGeneration, token: &str) {
+    let _ = app.emit(
+        "cerebro:chat_token",
+        serde_json::json!({
+            "type": "chat_token",
+            "generation_id": id,
+            "token": token,
+        }),
+    );
+    if let Some(tag) = &g.compare {
+        tag.emit_token(app, id, token);
+    }
+    if g.json_output {
+        if let Some(value) = partial_json::repair(&g.text) {
+            emit_partial_json(app, id, value);
+        }
+    }
+}
+
+fn write_runner_message(
+    stdin: &mut ChildStdin,
+    msg: &serde_json::Value,
+    what: &str,
+) -> Result<(), String> {
+    let line = serde_json::to_string(msg).map_err(|e| format!("Serialize error: {e}"))?;
+    stdin
+        .write_all(line.as_bytes())
+        .and_then(|_| stdin.write_all(b"\n"))
+        .and_then(|_| stdin.flush())
+        .map_err(|e| format!("Failed to write {what} to python runner: {e}"))
+}
+
+/// Emits `cerebro:chat_progress` every second until the generation produces its
+/// first token or finishes, so the UI can show elapsed time during load/prefill.
+fn spawn_generation_heartbeat(
+    app: tauri::AppHandle,
+    runtime: Arc<Mutex<PythonRuntimeInner>>,
+    generation_id: String,
+) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(HEARTBEAT_INTERVAL);
+
+        let (elapsed_ms, phase) = {
+            let Ok(inner) = runtime.lock() else {
+                return;
+            };
+            let Some(g) = inner.generations.get(&generation_id) else {
+                return;
+            };
+            if g.first_token_at.is_some() {
+                return;
+            }
+            if inner.quiet_while_hidden(&app) {
+                continue;
+            }
+            (g.started_at.elapsed().as_millis() as u64, g.phase)
+        };
+
+        let _ = app.emit(
+            "cerebro:chat_progress",
+            serde_json::json!({
+                "generation_id": generation_id,
+                "elapsed_ms": elapsed_ms,
+                "phase": phase,
+            }),
+        );
+    });
+}
+
+/// How often `spawn_progress_interpolator` looks for downloads gone quiet.
+const PROGRESS_INTERPOLATION_INTERVAL: Duration = Duration::from_secs(1);
+
+/// While `interpolate_download_progress` is on, emits estimated
+/// `cerebro:model_download_progress` events, flagged `estimated: true`, for
+/// downloads the runner hasn't reported on for a while. The estimate comes
+/// from the measured throughput; the next real event supersedes it.
+fn spawn_progress_interpolator(app: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(PROGRESS_INTERPOLATION_INTERVAL);
+        if !app.state::<SettingsState>().get().interpolate_download_progress {
+            continue;
+        }
+
+        let estimates: Vec<serde_json::Value> = {
+            let runtime = app.state::<PythonRuntimeState>();
+            let Ok(inner) = runtime.inner.lock() else {
+                return;
+            };
+            if inner.quiet_while_hidden(&app) {
+                continue;
+            }
+            inner
+                .downloads
+                .iter()
+                .filter(|(_, d)| !d.paused && !d.queued)
+                .filter_map(|(id, d)| {
+                    let (progress, clock) = (d.progress.as_ref()?, d.clock.as_ref()?);
+                    if clock.since_last() < PROGRESS_INTERPOLATION_INTERVAL {
+                        return None;
+                    }
+                    Some(serde_json::json!({
+                        "type": "download_progress",
+                        "download_id": id,
+                        "repo_id": d.repo_id,
+                        "n": clock.estimate(progress.total)?,
+                        "total": progress.total,
+                        "desc": progress.desc,
+                        "estimated": true,
+                    }))
+                })
+                .collect()
+        };
+        for estimate in estimates {
+            let _ = app.emit("cerebro:model_download_progress", estimate);
+        }
+    });
+}
+
+/// Fails the generation once `timeout` passes without a token: the runner is
+/// told to cancel and `cerebro:chat_error` goes out with `reason: "timeout"`
+/// right away, in case the runner is too stuck to answer. Exits as soon as it
+/// sees the generation gone.
+fn spawn_idle_timeout_watchdog(
+    app: tauri::AppHandle,
+    runtime: Arc<Mutex<PythonRuntimeInner>>,
+    generation_id: String,
+    timeout: Duration,
+) {
+    std::thread::spawn(move || {
+        let mut wait = timeout;
+        loop {
+            std::thread::sleep(wait);
+            let Ok(mut inner) = runtime.lock() else {
+                return;
+            };
+            let Some(g) = inner.generations.get(&generation_id) else {
+                return;
+            };
+            let idle = g.last_activity.elapsed();
+            if idle < timeout {
+                wait = timeout - idle;
+                continue;
+            }
+            // Already on its way out for another reason.
+            if g.finish_reason.is_some() || g.cancel_requested_at.is_some() {
+                return;
+            }
+            if let Err(e) = cancel_generation(&mut inner, &generation_id) {
+                eprintln!("Failed to cancel timed-out generation {generation_id}: {e}");
+            }
+            inner.flush_pending_tokens(&app, &generation_id);
+            inner.finish_generation(&app, &generation_id, "error");
+            drop(inner);
+
+            let _ = app.emit(
+                "cerebro:chat_error",
+                serde_json::json!({
+                    "type": "error",
+                    "generation_id": generation_id,
+                    "message": format!("No output for {} ms", timeout.as_millis()),
+                    "reason": "timeout",
+                    "timeout_ms": timeout.as_millis() as u64,
+                }),
+            );
+            return;
+        }
+    });
+}
+
+/// Stops `generation_id` once it has run for `max_duration`. The runner's
+/// acknowledgement is then reported as `chat_done` with `finish_reason: "max_duration"`.
+/// Checks back every `WATCHDOG_POLL`, so it exits soon after the generation ends
+/// rather than outliving it by up to `max_duration`.
+fn spawn_max_duration_watchdog(
+    runtime: Arc<Mutex<PythonRuntimeInner>>,
+    generation_id: String,
+    max_duration: Duration,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let deadline = Instant::now() + max_duration;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            std::thread::sleep(left.min(WATCHDOG_POLL));
+
+            let Ok(mut inner) = runtime.lock() else {
+                return;
+            };
+            let Some(g) = inner.generations.get_mut(&generation_id) else {
+                return;
+            };
+            if Instant::now() < deadline {
+                continue;
+            }
+            if g.claim_stop("max_duration") {
+                if let Err(e) = cancel_generation(&mut inner, &generation_id) {
+                    eprintln!("Failed to stop generation {generation_id} at max duration: {e}");
+                }
+            }
+            return;
+        }
+    })
+}
+
+/// How often `spawn_max_duration_watchdog` looks in on its generation.
+const WATCHDOG_POLL: Duration = Duration::from_millis(250);
+
+/// Emits `cerebro:cancel_unacknowledged` if `generation_id` is still running
+/// `CANCEL_ACK_GRACE` after a cancel, which usually means the runner is stuck.
+fn spawn_cancel_ack_watchdog(
+    app: tauri::AppHandle,
+    runtime: Arc<Mutex<PythonRuntimeInner>>,
+    generation_id: String,
+) {
+    std::thread::spawn(move || {
+        std::thread::sleep(CANCEL_ACK_GRACE);
+
+        let waited = {
+            let Ok(inner) = runtime.lock() else {
+                return;
+            };
+            let Some(requested_at) = inner
+                .generations
+                .get(&generation_id)
+                .and_then(|g| g.cancel_requested_at)
+            else {
+                return;
+            };
+            requested_at.elapsed()
+        };
+        let _ = app.emit(
+            "cerebro:cancel_unacknowledged",
+            serde_json::json!({
+                "generation_id": generation_id,
+                "waited_ms": waited.as_millis() as u64,
+            }),
+        );
+    });
+}
+
+fn sanitize_dir_component(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for ch in input.chars() {
+        let ok = ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.';
+        out.push(if ok { ch } else { '_' });
+    }
+    if out.is_empty() {
+        "model".to_string()
+    } else {
+        out
+    }
+}
+
+/// Resolves the local directory for `repo_id`, failing if nothing has been downloaded there.
+fn require_local_model(app: &tauri::AppHandle, repo_id: &str) -> Result<PathBuf, String> {
+    require_local_revision(app, repo_id, None)
+}
+
+/// Like `require_local_model`, for a specific downloaded revision.
+fn require_local_revision(
+    app: &tauri::AppHandle,
+    repo_id: &str,
+    revision: Option<&str>,
+) -> Result<PathBuf, String> {
+    let model_local_dir = models::revision_dir(&compute_model_local_dir(app, repo_id)?, revision);
+
+    let has_any_files = fs::read_dir(&model_local_dir)
+        .ok()
+        .and_then(|mut it| it.next())
+        .is_some();
+    if !has_any_files {
+        return Err(format!(
+            "Model is not available locally. Download it first. Expected dir: {}",
+            model_local_dir.display()
+        ));
+    }
+    Ok(model_local_dir)
+}
+
+fn compute_model_local_dir(app: &tauri::AppHandle, repo_id: &str) -> Result<PathBuf, String> {
+    let base = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+
+    let models_dir = base.join("models");
+    fs::create_dir_all(&models_dir)
+        .map_err(|e| format!("Failed to create models dir: {e}"))?;
+
+    // A previously recorded mapping wins, so the directory stays stable.
+    let index = app.state::<ModelIndexState>();
+    if let Some(dir_name) = index.entry(repo_id).and_then(|e| e.dir_name) {
+        return Ok(models_dir.join(dir_name));
+    }
+
+    let (dir_name, hashed) = models::model_dir_name(&models_dir, repo_id);
+    if hashed {
+        index.update_entry(app, repo_id, |entry| entry.dir_name = Some(dir_name.clone()))?;
+    }
+    Ok(models_dir.join(dir_name))
+}
+
+#[derive(Clone, Copy, serde::Serialize)]
+struct SamplingParams {
+    top_p: Option<f32>,
+    top_k: Option<u32>,
+    repetition_penalty: Option<f32>,
+}
+
+/// Validates the optional sampling knobs. Unset ones stay unset so the model's
+/// own defaults apply; `clamp_generation_params` pulls `top_p` into range.
+fn normalize_sampling_params(
+    payload: &ChatGeneratePayload,
+    settings: &Settings,
+) -> Result<SamplingParams, String> {
+    let top_p = match payload.top_p {
+        Some(p) if !p.is_finite() => return Err("top_p must be a finite number".to_string()),
+        Some(p) if (0.0..=1.0).contains(&p) => Some(p),
+        Some(p) if settings.clamp_generation_params => Some(p.clamp(0.0, 1.0)),
+        Some(p) => return Err(format!("top_p must be between 0 and 1 (got {p})")),
+        None => None,
+    };
+    if payload.top_k == Some(0) {
+        return Err("top_k must be greater than 0".to_string());
+    }
+    if let Some(penalty) = payload.repetition_penalty {
+        if !penalty.is_finite() || penalty <= 0.0 {
+            return Err(format!(
+                "repetition_penalty must be a positive number (got {penalty})"
+            ));
+        }
+    }
+    Ok(SamplingParams {
+        top_p,
+        top_k: payload.top_k,
+        repetition_penalty: payload.repetition_penalty,
+    })
+}
+
+const MAX_STOP_SEQUENCES: usize = 8;
+
+/// Rejects empty stop strings and more than `MAX_STOP_SEQUENCES`; an empty
+/// list is the same as none.
+fn validate_stop_sequences(stop: Option<Vec<String>>) -> Result<Option<Vec<String>>, String> {
+    let Some(stop) = stop.filter(|s| !s.is_empty()) else {
+        return Ok(None);
+    };
+    if stop.len() > MAX_STOP_SEQUENCES {
+        return Err(format!(
+            "At most {MAX_STOP_SEQUENCES} stop sequences are allowed (got {})",
+            stop.len()
+        ));
+    }
+    if stop.iter().any(String::is_empty) {
+        return Err("Stop sequences must not be empty".to_string());
+    }
+    Ok(Some(stop))
+}
+
+/// Validates `temperature`/`max_new_tokens`, applying defaults when unset.
+/// Out-of-range values are rejected unless `clamp_generation_params` is enabled.
+fn normalize_generation_params(
+    temperature: Option<f32>,
+    max_new_tokens: Option<u32>,
+    settings: &Settings,
+) -> Result<(f32, u32), String> {
+    const TEMPERATURE_RANGE: (f32, f32) = (0.0, 2.0);
+
+    let ceiling = settings.max_new_tokens_ceiling.max(1);
+    let clamp = settings.clamp_generation_params;
+
+    let temperature = temperature.unwrap_or(0.2);
+    if !temperature.is_finite() {
+        return Err("temperature must be a finite number".to_string());
+    }
+    let temperature = if (TEMPERATURE_RANGE.0..=TEMPERATURE_RANGE.1).contains(&temperature) {
+        temperature
+    } else if clamp {
+        temperature.clamp(TEMPERATURE_RANGE.0, TEMPERATURE_RANGE.1)
+    } else {
+        return Err(format!(
+            "temperature must be between {} and {} (got {temperature})",
+            TEMPERATURE_RANGE.0, TEMPERATURE_RANGE.1
+        ));
+    };
+
+    let max_new_tokens = max_new_tokens.unwrap_or(256.min(ceiling));
+    if max_new_tokens == 0 {
+        return Err("max_new_tokens must be greater than 0".to_string());
+    }
+    let max_new_tokens = if max_new_tokens <= ceiling {
+        max_new_tokens
+    } else if clamp {
+        ceiling
+    } else {
+        return Err(format!(
+            "max_new_tokens must be at most {ceiling} (got {max_new_tokens})"
+        ));
+    };
+
+    Ok((temperature, max_new_tokens))
+}
+
+/// Rejects a blank prompt when nothing else (`messages`, a prefill) gives the
+/// model something to go on, unless `allow_empty` opts into unconditioned output.
+fn check_prompt(
+    prompt: &str,
+    has_other_input: bool,
+    allow_empty: bool,
+) -> Result<(), CommandError> {
+    if prompt.trim().is_empty() && !has_other_input && !allow_empty {
+        return Err(CommandError::EmptyPrompt);
+    }
+    Ok(())
+}
+
+/// The runner seeds python/numpy/torch via `transformers.set_seed`; numpy only accepts 32-bit seeds.
+const MAX_SEED: u64 = u32::MAX as u64;
+
+fn resolve_seed(seed: Option<u64>) -> Result<u64, String> {
+    use std::hash::{BuildHasher, Hasher};
+
+    match seed {
+        Some(seed) if seed > MAX_SEED => Err(format!("seed must be at most {MAX_SEED} (got {seed})")),
+        Some(seed) => Ok(seed),
+        None => {
+            let random = std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish();
+            Ok(random & MAX_SEED)
+        }
+    }
+}
+
+#[tauri::command]
+fn get_settings(settings: State<SettingsState>) -> Settings {
+    settings.get()
+}
+
+#[tauri::command]
+fn set_generation_limits(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    max_new_tokens_ceiling: u32,
+    clamp_out_of_range: bool,
+) -> Result<Settings, String> {
+    if max_new_tokens_ceiling == 0 {
+        return Err("max_new_tokens_ceiling must be greater than 0".to_string());
+    }
+    settings.update(&app, |s| {
+        s.max_new_tokens_ceiling = max_new_tokens_ceiling;
+        s.clamp_generation_params = clamp_out_of_range;
+    })
+}
+
+/// Normalizes a Hugging Face base URL, rejecting anything that isn't http(s) with a host.
+fn normalize_hf_endpoint(base_url: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(base_url.trim())
+        .map_err(|e| format!("Invalid Hugging Face endpoint: {e}"))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("Hugging Face endpoint must use http or https".to_string());
+    }
+    if url.host_str().is_none() {
+        return Err("Hugging Face endpoint must include a host".to_string());
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err("Hugging Face endpoint must not include a query or fragment".to_string());
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+fn redact_header_values(headers: &HashMap<String, String>) -> String {
+    let mut names: Vec<&str> = headers.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names
+        .iter()
+        .map(|name| format!("{name}: <redacted>"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Sets the Hugging Face base URL used for downloads. An empty string restores the default.
+#[tauri::command]
+fn set_hf_endpoint(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    base_url: String,
+) -> Result<Settings, String> {
+    let endpoint = if base_url.trim().is_empty() {
+        None
+    } else {
+        Some(normalize_hf_endpoint(&base_url)?)
+    };
+    settings.update(&app, |s| s.hf_endpoint = endpoint)
+}
+
+/// Sets extra environment variables for the runner, restarting it if it is running.
+#[tauri::command]
+fn set_runner_env(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    env: HashMap<String, String>,
+) -> Result<Settings, CommandError> {
+    for (key, value) in &env {
+        if key.trim().is_empty() || key.contains('=') || key.contains('\0') {
+            return Err(format!("Invalid environment variable name: {key:?}").into());
+        }
+        if value.contains('\0') {
+            return Err(format!("Invalid value for environment variable {key}").into());
+        }
+    }
+    let env: HashMap<String, String> = env
+        .into_iter()
+        .map(|(k, v)| (k.trim().to_string(), v))
+        .collect();
+    apply_runner_config(&app, &state, &settings, |s| s.runner_env = env)
+}
+
+/// Sets the runner's working directory (`None` to inherit), restarting it if it is running.
+#[tauri::command]
+fn set_runner_cwd(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    cwd: Option<String>,
+) -> Result<Settings, CommandError> {
+    let cwd = cwd.filter(|c| !c.trim().is_empty());
+    if let Some(cwd) = &cwd {
+        if !PathBuf::from(cwd).is_dir() {
+            return Err(format!("Not a directory: {cwd}").into());
+        }
+    }
+    apply_runner_config(&app, &state, &settings, |s| s.runner_cwd = cwd)
+}
+
+/// Caps the runner's memory and CPU time (`None` to lift the caps), restarting
+/// it if it is running. Only enforced on Unix; Windows ignores the limits.
+#[tauri::command]
+fn set_resource_limits(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    limits: Option<ResourceLimits>,
+) -> Result<Settings, CommandError> {
+    if let Some(limits) = &limits {
+        if limits.max_memory_bytes == Some(0) || limits.max_cpu_seconds == Some(0) {
+            return Err("Resource limits must be greater than 0".to_string().into());
+        }
+    }
+    let limits = limits.filter(|l| *l != ResourceLimits::default());
+    apply_runner_config(&app, &state, &settings, |s| s.resource_limits = limits)
+}
+
+/// Overrides where `cerebro_runner.py` is loaded from (`None` to search the
+/// default locations), restarting the runner if it is running.
+#[tauri::command]
+fn set_runner_script_path(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    path: Option<String>,
+) -> Result<Settings, CommandError> {
+    let path = path.filter(|p| !p.trim().is_empty());
+    if let Some(path) = &path {
+        if !PathBuf::from(path).is_file() {
+            return Err(format!("Not a file: {path}").into());
+        }
+    }
+    apply_runner_config(&app, &state, &settings, |s| s.runner_script_path = path)
+}
+
+/// Sets the Python interpreter that runs the runner script (`None` to search
+/// PATH again), restarting the runner if it is running.
+#[tauri::command]
+fn python_runtime_set_interpreter(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    path: Option<String>,
+) -> Result<Settings, CommandError> {
+    let path = path.filter(|p| !p.trim().is_empty());
+    if let Some(path) = &path {
+        validate_interpreter(path)?;
+    }
+    apply_runner_config(&app, &state, &settings, |s| s.python_interpreter = path)
+}
+
+/// Saves a spawn-time setting and restarts a running runner so it takes effect.
+/// Refused while the runner has a generation or download in flight.
+fn apply_runner_config(
+    app: &tauri::AppHandle,
+    state: &PythonRuntimeState,
+    settings: &SettingsState,
+    f: impl FnOnce(&mut Settings),
+) -> Result<Settings, CommandError> {
+    // Held from the checks through the shutdown, so nothing can start in between
+    // and be cut off by the restart.
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    if inner.child.is_some() {
+        if !inner.generations.is_empty() {
+            return Err("Can't restart the runner while a generation is running"
+                .to_string()
+                .into());
+        }
+        // Queued downloads haven't started; the restart pauses them as usual.
+        if inner.downloads.values().any(|d| !d.paused && !d.queued) {
+            return Err("Can't restart the runner while a download is running; pause it first"
+                .to_string()
+                .into());
+        }
+    }
+
+    let updated = settings.update(app, f)?;
+    if inner.child.is_none() {
+        return Ok(updated);
+    }
+    let stopping = begin_shutdown(&mut inner, app, "runtime_stopped");
+    drop(inner);
+    if let Some(stopping) = stopping {
+        finish_shutdown(stopping, SHUTDOWN_GRACE);
+    }
+    ensure_python_runtime(app, state)?;
+    Ok(updated)
+}
+
+/// Hosts or IPs `http_request` may reach even if they resolve to internal addresses.
+#[tauri::command]
+fn set_http_allowlist(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    hosts: Vec<String>,
+) -> Result<Settings, String> {
+    let hosts: Vec<String> = hosts
+        .into_iter()
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .collect();
+    settings.update(&app, |s| s.http_allowlist = hosts)
+}
+
+/// Turns the private-address guard on or off, and whether loopback is exempt from it.
+#[tauri::command]
+fn set_http_guard(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    block_private: bool,
+    allow_localhost: bool,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| {
+        s.http_block_private = block_private;
+        s.http_allow_localhost = allow_localhost;
+    })
+}
+
+/// Saves the Hugging Face token to the OS credential store. Downloads started
+/// without a token use it from then on.
+#[tauri::command]
+fn set_hf_token(token: String) -> Result<(), String> {
+    let token = token.trim();
+    if token.is_empty() {
+        return Err("The token is empty; use clear_hf_token to remove it".to_string());
+    }
+    hf_token::store(token)
+}
+
+#[tauri::command]
+fn clear_hf_token() -> Result<(), String> {
+    hf_token::clear()
+}
+
+#[tauri::command]
+fn set_hf_headers(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    headers: HashMap<String, String>,
+) -> Result<Settings, String> {
+    for (name, value) in &headers {
+        reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name: {name}"))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value for header {name}"))?;
+    }
+    let headers: HashMap<String, String> = headers
+        .into_iter()
+        .map(|(k, v)| (k.trim().to_string(), v))
+        .collect();
+
+    if dev_mode_enabled() {
+        println!("Hugging Face headers set: [{}]", redact_header_values(&headers));
+    }
+    hf_token::store_headers(&headers)?;
+    settings.update(&app, |s| s.hf_headers = headers)
+}
+
+/// Names of the configured `hf_headers`, which `get_settings` leaves out.
+#[tauri::command]
+fn get_hf_header_names(settings: State<SettingsState>) -> Vec<String> {
+    let mut names: Vec<String> = settings.get().hf_headers.into_keys().collect();
+    names.sort_unstable();
+    names
+}
+
+#[tauri::command]
+fn set_hide_during_generation(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.hide_during_generation = enabled)
+}
+
+/// Caps how many downloads run at once; extra ones queue until a slot frees up.
+#[tauri::command]
+fn set_max_concurrent_downloads(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    n: usize,
+) -> Result<Settings, String> {
+    if n == 0 {
+        return Err("max_concurrent_downloads must be at least 1".to_string());
+    }
+    let updated = settings.update(&app, |s| s.max_concurrent_downloads = n)?;
+    state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .start_queued_downloads(&app, &updated);
+    Ok(updated)
+}
+
+/// Overrides the rate limit of a network-heavy command (`model_download_start`,
+/// `http_request`, `measure_download_speed`, `prefetch_model_info`). `None`
+/// restores the default.
+#[tauri::command]
+fn set_rate_limit(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    limiter: State<RateLimiterState>,
+    command: String,
+    limit: Option<RateLimit>,
+) -> Result<Settings, String> {
+    if !rate_limit::is_limited_command(&command) {
+        return Err(format!("{command} is not rate limited"));
+    }
+    if limit.is_some_and(|l| l.burst == 0 || l.per_minute == 0) {
+        return Err("burst and per_minute must be at least 1".to_string());
+    }
+    let updated = settings.update(&app, |s| match limit {
+        Some(limit) => {
+            s.rate_limits.insert(command.clone(), limit);
+        }
+        None => {
+            s.rate_limits.remove(&command);
+        }
+    })?;
+    limiter.reset(&command);
+    Ok(updated)
+}
+
+/// Chooses what closing the main window does: hide it (the default), quit, or
+/// ask the UI through `cerebro:close_requested`.
+#[tauri::command]
+fn set_close_behavior(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    behavior: CloseBehavior,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.close_behavior = behavior)
+}
+
+/// Chooses whether the main window opens next to the tray icon (the default)
+/// or where it was last left, restored across restarts.
+#[tauri::command]
+fn set_window_placement(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    placement: WindowPlacement,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.window_placement = placement)
+}
+
+/// Rebinds the shortcut that toggles the main window, or turns it off with
+/// `None`. Fails without changing anything when the chord can't be parsed or
+/// is already taken.
+#[tauri::command]
+async fn set_global_shortcut(
+    app: tauri::AppHandle,
+    settings: State<'_, SettingsState>,
+    shortcut: Option<String>,
+) -> Result<Settings, String> {
+    let shortcut = shortcut.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    #[cfg(desktop)]
+    global_shortcut::rebind(
+        &app,
+        settings.get().global_shortcut.as_deref(),
+        shortcut.as_deref(),
+    )?;
+    #[cfg(not(desktop))]
+    if shortcut.is_some() {
+        return Err("Global shortcuts are only available on desktop".to_string());
+    }
+    settings.update(&app, |s| s.global_shortcut = shortcut)
+}
+
+/// Saves the main window's geometry under `WindowPlacement::Remember`.
+fn remember_window_geometry(app: &tauri::AppHandle) {
+    if app.state::<SettingsState>().get().window_placement != WindowPlacement::Remember {
+        return;
+    }
+    let Some(geometry) = app
+        .get_webview_window("main")
+        .and_then(|window| window_geometry::capture(&window))
+    else {
+        return;
+    };
+    if let Err(e) = window_geometry::save(app, &geometry) {
+        eprintln!("{e}");
+    }
+}
+
+/// Chooses how streamed text is split into `chat_token` events. Applies to
+/// generations started afterwards.
+#[tauri::command]
+fn set_chunk_mode(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    mode: ChunkMode,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.chunk_mode = mode)
+}
+
+/// Smooths download progress by estimating it between the runner's events.
+#[tauri::command]
+fn set_download_progress_interpolation(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.interpolate_download_progress = enabled)
+}
+
+/// Lets `chat_generate` run with an empty prompt instead of failing with `EmptyPrompt`.
+#[tauri::command]
+fn set_allow_empty_prompt(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.allow_empty_prompt = enabled)
+}
+
+/// Reloads the latency samples from the usage log on every start. Turning it
+/// on loads them right away.
+#[tauri::command]
+async fn set_persist_latency_stats(
+    app: tauri::AppHandle,
+    settings: State<'_, SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    let updated = settings.update(&app, |s| s.persist_latency_stats = enabled)?;
+    if enabled {
+        tauri::async_runtime::spawn_blocking(move || load_latency_samples(&app))
+            .await
+            .map_err(|e| format!("Latency stats task failed: {e}"))?;
+    }
+    Ok(updated)
+}
+
+/// Replaces the in-memory latency samples with the usage log's.
+fn load_latency_samples(app: &tauri::AppHandle) {
+    match usage::entries(app) {
+        Ok(entries) => app.state::<LatencyState>().reset_from(&entries),
+        Err(e) => eprintln!("{e}"),
+    }
+}
+
+/// Respawns the runner automatically after a crash.
+#[tauri::command]
+fn set_auto_restart_runner(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.auto_restart_runner = enabled)
+}
+
+/// Unloads the model whenever a generation runs out of memory.
+#[tauri::command]
+fn set_unload_on_oom(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.unload_on_oom = enabled)
+}
+
+/// Makes `chat_generate` refuse prompts that won't fit the model's context.
+#[tauri::command]
+fn set_enforce_context(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    settings.update(&app, |s| s.enforce_context = enabled)
+}
+
+/// Streams token and progress events to the main window even while it is hidden.
+/// When off (the default) they are held back and flushed when the window shows.
+#[tauri::command]
+fn set_emit_when_hidden(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    enabled: bool,
+) -> Result<Settings, String> {
+    let updated = settings.update(&app, |s| s.emit_when_hidden = enabled)?;
+    if enabled {
+        state
+            .inner
+            .lock()
+            .map_err(|_| "Python runtime mutex poisoned".to_string())?
+            .release_pending_tokens(&app);
+    }
+    Ok(updated)
+}
+
+/// Records whether the main window is showing; on show, flushes text held back meanwhile.
+fn note_main_window_visible(app: &tauri::AppHandle, visible: bool) {
+    let state = app.state::<PythonRuntimeState>();
+    let Ok(mut inner) = state.inner.lock() else {
+        return;
+    };
+    inner.main_window_hidden = !visible;
+    if visible {
+        inner.release_pending_tokens(app);
+    }
+}
+
+/// Whether focus loss should leave the window visible: true while any
+/// generation is streaming, unless the user opted into hiding anyway.
+fn suppress_hide(app: &tauri::AppHandle) -> bool {
+    if app.state::<SettingsState>().get().hide_during_generation {
+        return false;
+    }
+    app.state::<PythonRuntimeState>()
+        .inner
+        .lock()
+        .map(|inner| !inner.generations.is_empty())
+        .unwrap_or(false)
+}
+
+#[tauri::command]
+fn python_runtime_start(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+) -> Result<(), CommandError> {
+    ensure_python_runtime(&app, &state)
+}
+
+/// Whether the runner process is alive. A runner found dead is cleaned up on
+/// the spot (with `cerebro:runtime_exited`), so the next call respawns it.
+#[tauri::command]
+fn python_runtime_status(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+) -> Result<RuntimeStatus, String> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let Some(child) = inner.child.as_mut() else {
+        return Ok(RuntimeStatus {
+            running: false,
+            pid: None,
+            uptime_ms: None,
+            exit_code: None,
+        });
+    };
+    match child.try_wait() {
+        Ok(None) => Ok(RuntimeStatus {
+            running: true,
+            pid: Some(child.id()),
+            uptime_ms: inner
+                .runner_started_at
+                .map(|t| t.elapsed().as_millis() as u64),
+            exit_code: None,
+        }),
+        exited => {
+            let exit_code = exited.ok().flatten().and_then(|status| status.code());
+            handle_runner_exit(&mut inner, &app);
+            Ok(RuntimeStatus {
+                running: false,
+                pid: None,
+                uptime_ms: None,
+                exit_code,
+            })
+        }
+    }
+}
+
+/// Reports which interpreter would run the runner, without starting it.
+#[tauri::command]
+fn check_python(app: tauri::AppHandle) -> Result<PythonCheck, CommandError> {
+    if let Some(sidecar_path) = resolve_runner_sidecar_path(&app)? {
+        return Ok(PythonCheck {
+            interpreter: sidecar_path.to_string_lossy().to_string(),
+            version: None,
+            sidecar: true,
+        });
+    }
+
+    let interpreter = find_python_interpreter(&app)?;
+    let output = Command::new(&interpreter)
+        .arg("--version")
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                python_not_found()
+            } else {
+                CommandError::from(format!("Failed to run {}: {e}", interpreter.display()))
+            }
+        })?;
+
+    // Older interpreters print the version to stderr.
+    let raw = if output.stdout.is_empty() {
+        output.stderr
+    } else {
+        output.stdout
+    };
+    let version = String::from_utf8_lossy(&raw)
+        .trim()
+        .trim_start_matches("Python")
+        .trim()
+        .to_string();
+
+    Ok(PythonCheck {
+        interpreter: interpreter.to_string_lossy().to_string(),
+        version: (!version.is_empty()).then_some(version),
+        sidecar: false,
+    })
+}
+
+/// How long a stopping runner gets to acknowledge `shutdown`, and then to exit,
+/// before it is killed.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+/// A runner detached by `begin_shutdown` that still has to be reaped.
+struct StoppingRunner {
+    child: Child,
+    ack: Option<mpsc::Receiver<()>>,
+}
+
+#[derive(serde::Serialize)]
+struct ShutdownReport {
+    /// The runner answered `shutdown` before exiting.
+    acknowledged: bool,
+    /// It had to be killed.
+    forced: bool,
+    code: Option<i32>,
+}
+
+/// Sends `shutdown` and detaches the runner from `inner`, whose state is reset
+/// right away. Generations it cuts short end with a `cerebro:chat_error`
+/// carrying `reason`, and interrupted downloads with `cerebro:model_download_paused`.
+/// The process itself is reaped by `finish_shutdown`, which should run without
+/// the lock so the reader can still pass the acknowledgement on.
+fn begin_shutdown(
+    inner: &mut PythonRuntimeInner,
+    app: &tauri::AppHandle,
+    reason: &'static str,
+) -> Option<StoppingRunner> {
+    if let Some(mut stdin) = inner.stdin.take() {
+        let _ = stdin.write_all(b"{\"type\":\"shutdown\"}\n");
+        let _ = stdin.flush();
+    }
+    let ack = inner.shutdown_ack.take();
+    inner.restart_pending = false;
+    inner.crash_restarts = 0;
+    let interrupted: Vec<(String, String)> = inner
+        .downloads
+        .iter()
+        .filter(|(_, d)| !d.paused)
+        .map(|(id, d)| (id.clone(), d.repo_id.clone()))
+        .collect();
+    let aborted = inner.reset_runtime_state();
+    if !interrupted.is_empty() {
+        inner.persist_downloads(app);
+    }
+
+    let message = match reason {
+        "drain_timeout" => "The runtime was drained before the generation finished",
+        _ => "The Python runner was stopped",
+    };
+    for generation_id in &aborted {
+        let _ = app.emit(
+            "cerebro:chat_error",
+            serde_json::json!({
+                "type": "error",
+                "generation_id": generation_id,
+                "message": message,
+                "reason": reason,
+            }),
+        );
+    }
+    for (download_id, repo_id) in interrupted {
+        let _ = app.emit(
+            "cerebro:model_download_paused",
+            serde_json::json!({
+                "type": "download_paused",
+                "download_id": download_id,
+                "repo_id": repo_id,
+            }),
+        );
+    }
+    inner.child.take().map(|child| StoppingRunner { child, ack })
+}
+
+/// Waits up to `grace` for the acknowledgement and the exit that follows it,
+/// killing the runner if either doesn't come.
+fn finish_shutdown(stopping: StoppingRunner, grace: Duration) -> ShutdownReport {
+    let StoppingRunner { mut child, ack } = stopping;
+    let acknowledged = !grace.is_zero() && ack.is_some_and(|rx| rx.recv_timeout(grace).is_ok());
+
+    let deadline = Instant::now() + if acknowledged { grace } else { Duration::ZERO };
+    loop {
+        if let Ok(Some(status)) = child.try_wait() {
+            return ShutdownReport {
+                acknowledged,
+                forced: false,
+                code: status.code(),
+            };
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let _ = child.kill();
+    let code = child.wait().ok().and_then(|status| status.code());
+    ShutdownReport {
+        acknowledged,
+        forced: true,
+        code,
+    }
+}
+
+/// Asks the runner to exit and waits for it to confirm, force-killing it after
+/// `SHUTDOWN_GRACE`. Returns `None` if no runner was running.
+#[tauri::command]
+async fn python_runtime_stop(
+    app: tauri::AppHandle,
+    state: State<'_, PythonRuntimeState>,
+) -> Result<Option<ShutdownReport>, String> {
+    let stopping = begin_shutdown(
+        &mut *state
+            .inner
+            .lock()
+            .map_err(|_| "Python runtime mutex poisoned".to_string())?,
+        &app,
+        "runtime_stopped",
+    );
+    let Some(stopping) = stopping else {
+        return Ok(None);
+    };
+    tauri::async_runtime::spawn_blocking(move || finish_shutdown(stopping, SHUTDOWN_GRACE))
+        .await
+        .map(Some)
+        .map_err(|e| format!("Shutdown task failed: {e}"))
+}
+
+/// Stops accepting generations, waits up to `timeout_ms` for active ones to
+/// finish, then shuts the runner down (forcibly if some are still running).
+#[tauri::command]
+async fn runtime_drain(
+    app: tauri::AppHandle,
+    state: State<'_, PythonRuntimeState>,
+    timeout_ms: u64,
+) -> Result<DrainReport, String> {
+    let runtime = state.inner.clone();
+    tauri::async_runtime::spawn_blocking(move || drain_runtime(&app, &runtime, timeout_ms))
+        .await
+        .map_err(|e| format!("Drain task failed: {e}"))?
+}
+
+/// Blocking body of `runtime_drain`.
+fn drain_runtime(
+    app: &tauri::AppHandle,
+    runtime: &Mutex<PythonRuntimeInner>,
+    timeout_ms: u64,
+) -> Result<DrainReport, String> {
+    let active: Vec<String> = {
+        let mut inner = runtime
+            .lock()
+            .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+        inner.draining = true;
+        inner.generations.keys().cloned().collect()
+    };
+
+    let _ = app.emit(
+        "cerebro:runtime_draining",
+        serde_json::json!({
+            "active": active,
+            "timeout_ms": timeout_ms,
+        }),
+    );
+
+    let started = Instant::now();
+    let deadline = Duration::from_millis(timeout_ms);
+    loop {
+        let idle = runtime
+            .lock()
+            .map(|inner| inner.generations.is_empty())
+            .unwrap_or(true);
+        if idle || started.elapsed() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut inner = runtime
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let aborted: Vec<String> = inner.generations.keys().cloned().collect();
+    let completed = active
+        .into_iter()
+        .filter(|id| !aborted.contains(id))
+        .collect();
+    // Generations still running end with a `drain_timeout` error, so every
+    // listener sees them stop, not just this caller.
+    let stopping = begin_shutdown(&mut inner, app, "drain_timeout");
+    inner.draining = false;
+    drop(inner);
+    // Still-busy runners are killed outright; idle ones get the handshake.
+    let grace = if aborted.is_empty() {
+        SHUTDOWN_GRACE
+    } else {
+        Duration::ZERO
+    };
+    if let Some(stopping) = stopping {
+        finish_shutdown(stopping, grace);
+    }
+
+    Ok(DrainReport {
+        completed,
+        forced: !aborted.is_empty(),
+        aborted,
+        waited_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// How long quitting waits for running generations before killing the runner.
+const QUIT_DRAIN_TIMEOUT_MS: u64 = 5_000;
+
+/// Drains the runner off the calling thread, then exits the app.
+fn quit_after_drain(app: &tauri::AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let runtime = app.state::<PythonRuntimeState>().inner.clone();
+        if let Err(e) = drain_runtime(&app, &runtime, QUIT_DRAIN_TIMEOUT_MS) {
+            eprintln!("Failed to drain the runner before quitting: {e}");
+        }
+        app.exit(0);
+    });
+}
+
+/// Quits Cerebro, e.g. after the UI confirmed a `cerebro:close_requested` prompt.
+#[tauri::command]
+fn quit_app(app: tauri::AppHandle) {
+    quit_after_drain(&app);
+}
+
+/// Python/library versions reported by the runner's capabilities message.
+#[tauri::command]
+fn runtime_versions(state: State<PythonRuntimeState>) -> Result<RuntimeVersions, String> {
+    let inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let Some(caps) = inner.capabilities.as_ref() else {
+        return Err("Runner has not reported its versions yet. Start the runtime first.".to_string());
+    };
+    Ok(RuntimeVersions {
+        versions: caps.versions.clone(),
+        devices: caps.devices.clone(),
+        warnings: caps.warnings.clone(),
+        features: caps.features.clone(),
+    })
+}
+
+/// Recovery hook for an out-of-memory generation: asks the runner to drop its
+/// model when `unload_on_oom` is set. Returns whether an unload was requested.
+fn unload_after_oom(app: &tauri::AppHandle, inner: &mut PythonRuntimeInner) -> bool {
+    if !app.state::<SettingsState>().get().unload_on_oom
+        || require_runner_feature(inner, "unload").is_err()
+    {
+        return false;
+    }
+    let msg = serde_json::json!({ "type": "unload" });
+    inner.send_to_runner(&msg, "unload").is_ok()
+}
+
+/// Fails unless the running runner advertised `feature` in its capabilities.
+/// The `ready` line's list stands in until the full capabilities arrive.
+fn require_runner_feature(inner: &PythonRuntimeInner, feature: &str) -> Result<(), String> {
+    let features = inner
+        .capabilities
+        .as_ref()
+        .map(|caps| &caps.features)
+        .or(inner.ready_features.as_ref());
+    match features {
+        None => Err(format!(
+            "Runner has not reported its capabilities yet; `{feature}` can't be used until it does"
+        )),
+        Some(features) if !features.iter().any(|f| f == feature) => {
+            Err(format!("The Python runner does not support `{feature}`"))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+#[tauri::command]
+async fn chat_generate(
+    app: tauri::AppHandle,
+    state: State<'_, PythonRuntimeState>,
+    settings: State<'_, SettingsState>,
+    payload: ChatGeneratePayload,
+) -> Result<ChatGenerateStarted, CommandError> {
+    if settings.get().enforce_context {
+        check_context_budget(&app, &state, &settings, &payload).await?;
+    }
+    generate_and_retain(app, &state, &settings, payload)
+}
+
+/// Fails with `ContextOverflow` when the prompt plus `max_new_tokens` won't fit
+/// the model's context. Models that don't declare a context length, and mock
+/// mode, pass unchecked.
+async fn check_context_budget(
+    app: &tauri::AppHandle,
+    state: &PythonRuntimeState,
+    settings: &SettingsState,
+    payload: &ChatGeneratePayload,
+) -> Result<(), CommandError> {
+    let mock_mode = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .mock_mode;
+    if mock_mode {
+        return Ok(());
+    }
+    let (_, max_new_tokens) =
+        normalize_generation_params(payload.temperature, payload.max_new_tokens, &settings.get())?;
+    let model_local_dir = require_local_model(app, &payload.model)?;
+    let Some(context_length) = models::context_length(&model_local_dir) else {
+        return Ok(());
+    };
+
+    let prompt_tokens = request_token_count(
+        app,
+        state,
+        &payload.model,
+        &payload.prompt,
+        payload.messages.as_deref(),
+        payload.prefill.as_deref(),
+    )
+    .await?;
+    if prompt_tokens + u64::from(max_new_tokens) > context_length {
+        return Err(CommandError::ContextOverflow {
+            prompt_tokens,
+            max_new_tokens,
+            context_length,
+        });
+    }
+    Ok(())
+}
+
+/// How long a token count may take, including a cold model load.
+const COUNT_TOKENS_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Asks the runner how many prompt tokens `prompt` becomes for `repo_id`, with
+/// the conversation history and chat template applied as `generate` would.
+async fn request_token_count(
+    app: &tauri::AppHandle,
+    state: &PythonRuntimeState,
+    repo_id: &str,
+    prompt: &str,
+    messages: Option<&[ChatMessage]>,
+    prefill: Option<&str>,
+) -> Result<u64, CommandError> {
+    let messages = messages.filter(|messages| !messages.is_empty());
+    ensure_python_runtime(app, state)?;
+    let model_local_dir = require_local_model(app, repo_id)?;
+
+    let count_id = generate_id();
+    let (tx, rx) = mpsc::channel();
+    {
+        let mut inner = state
+            .inner
+            .lock()
+            .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+        require_runner_feature(&inner, "count_tokens")?;
+        if messages.is_some() {
+            require_runner_feature(&inner, "messages")?;
+        }
+        let msg = serde_json::json!({
+            "type": "count_tokens",
+            "count_id": count_id,
+            "model": model_local_dir.to_string_lossy(),
+            "repo_id": repo_id,
+            "prompt": prompt,
+            "messages": messages,
+            "prefill": prefill.filter(|p| !p.is_empty()),
+        });
+        inner.send_to_runner(&msg, "count_tokens")?;
+        inner.token_counts.insert(count_id.clone(), tx);
+    }
+
+    let runtime = state.inner.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let result = match rx.recv_timeout(COUNT_TOKENS_TIMEOUT) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(format!(
+                "Token count did not finish within {}s",
+                COUNT_TOKENS_TIMEOUT.as_secs()
+            )),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err("Python runtime stopped before counting tokens".to_string())
+            }
+        };
+        if let Ok(mut inner) = runtime.lock() {
+            inner.token_counts.remove(&count_id);
+        }
+        result
+    })
+    .await
+    .map_err(|e| format!("Token count task failed: {e}"))?
+    .map_err(CommandError::from)
+}
+
+/// Prompt tokens `prompt` would take for `model`, conversation history and
+/// chat template included. Loads the model if it isn't already.
+#[tauri::command]
+async fn count_tokens(
+    app: tauri::AppHandle,
+    state: State<'_, PythonRuntimeState>,
+    model: String,
+    prompt: String,
+    prefill: Option<String>,
+) -> Result<u64, CommandError> {
+    request_token_count(&app, &state, &model, &prompt, None, prefill.as_deref()).await
+}
+
+/// The context window a downloaded model declares, if any.
+#[tauri::command]
+fn model_context_length(app: tauri::AppHandle, model: String) -> Result<Option<u64>, String> {
+    let model_local_dir = require_local_model(&app, &model)?;
+    Ok(models::context_length(&model_local_dir))
+}
+
+/// "Stop and regenerate": cancels `old_generation_id` and starts `new_payload`
+/// under one hold of the runtime lock, so nothing else can reach the runner in
+/// between. If the new generation can't start, the old one keeps running.
+#[tauri::command]
+async fn chat_replace(
+    app: tauri::AppHandle,
+    state: State<'_, PythonRuntimeState>,
+    settings: State<'_, SettingsState>,
+    old_generation_id: String,
+    new_payload: ChatGeneratePayload,
+) -> Result<ChatGenerateStarted, CommandError> {
+    if settings.get().enforce_context {
+        check_context_budget(&app, &state, &settings, &new_payload).await?;
+    }
+    let payload = ChatGeneratePayload {
+        replaces: Some(old_generation_id),
+        ..new_payload
+    };
+    generate_and_retain(app, &state, &settings, payload)
+}
+
+/// Re-issues the payload of an earlier generation, optionally with a new seed.
+/// Without one the original seed is reused, so the output repeats.
+#[tauri::command]
+fn chat_regenerate(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    generation_id: String,
+    new_seed: Option<u64>,
+) -> Result<ChatGenerateStarted, CommandError> {
+    let mut payload = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .recent_payloads
+        .iter()
+        .find(|(id, _)| *id == generation_id)
+        .map(|(_, payload)| payload.clone())
+        .ok_or_else(|| format!("Generation {generation_id} is no longer retained"))?;
+    if new_seed.is_some() {
+        payload.seed = new_seed;
+    }
+    generate_and_retain(app, &state, &settings, payload)
+}
+
+/// Starts a generation and keeps its payload around for `chat_regenerate`.
+fn generate_and_retain(
+    app: tauri::AppHandle,
+    state: &PythonRuntimeState,
+    settings: &SettingsState,
+    payload: ChatGeneratePayload,
+) -> Result<ChatGenerateStarted, CommandError> {
+    let mut retained = payload.clone();
+    let started = start_generation(app, state, settings, payload)?;
+    retained.seed = Some(started.seed);
+    retained.external = false;
+    retained.generation_id = None;
+    retained.replaces = None;
+
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    if inner.recent_payloads.len() >= RETAINED_PAYLOADS {
+        inner.recent_payloads.pop_front();
+    }
+    inner
+        .recent_payloads
+        .push_back((started.generation_id.clone(), retained));
+    Ok(started)
+}
+
+fn start_generation(
+    app: tauri::AppHandle,
+    state: &PythonRuntimeState,
+    settings: &SettingsState,
+    payload: ChatGeneratePayload,
+) -> Result<ChatGenerateStarted, CommandError> {
+    let (temperature, max_new_tokens) =
+        normalize_generation_params(payload.temperature, payload.max_new_tokens, &settings.get())?;
+    let sampling = normalize_sampling_params(&payload, &settings.get())?;
+    let stop = validate_stop_sequences(payload.stop)?;
+    let has_prefill = payload.prefill.as_deref().is_some_and(|p| !p.is_empty());
+    let messages = payload.messages.filter(|messages| !messages.is_empty());
+    check_prompt(
+        &payload.prompt,
+        messages.is_some() || has_prefill,
+        settings.get().allow_empty_prompt,
+    )?;
+    let seed = resolve_seed(payload.seed)?;
+    let response_format = payload.response_format.map(ResponseFormat::validated).transpose()?;
+    let max_duration = match payload.max_duration_ms {
+        Some(0) => return Err("max_duration_ms must be greater than 0".to_string().into()),
+        Some(ms) => Some(Duration::from_millis(ms)),
+        None => None,
+    };
+    let idle_timeout = match payload.timeout_ms {
+        Some(0) => return Err("timeout_ms must be greater than 0".to_string().into()),
+        Some(ms) => Some(Duration::from_millis(ms)),
+        None => None,
+    };
+    let chunker = match settings.get().chunk_mode {
+        ChunkMode::Raw => None,
+        ChunkMode::Markdown => Some(MarkdownChunker::default()),
+    };
+    let replaces = payload.replaces;
+    let images = payload
+        .images
+        .as_deref()
+        .filter(|images| !images.is_empty())
+        .map(images::prepare)
+        .transpose()?;
+    // Keyed on the seed the caller fixed, not the one resolved for this run.
+    // Image requests are never cached, since files can change behind a path.
+    // Nor are plain prompts: the runner answers them in the context of its own
+    // history, which the key can't see and a replay wouldn't extend.
+    let cacheable = payload.cache && images.is_none() && messages.is_some();
+    // The runner gets the revision `switch_model` loaded for this repo, if any.
+    let revision = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .loaded_revision(&payload.model);
+    let cache_key = (cacheable && response_cache::is_deterministic(temperature, payload.seed))
+        .then(|| {
+            // Replaced or re-downloaded weights must not replay the old outputs.
+            let local_dir = compute_model_local_dir(&app, &payload.model)?;
+            let weights = models::weights_fingerprint(&models::revision_dir(
+                &local_dir,
+                revision.as_deref(),
+            ));
+            let request = serde_json::json!({
+                "model": payload.model,
+                "weights": weights,
+                "prompt": payload.prompt,
+                "messages": messages,
+                "prefill": payload.prefill.as_deref().filter(|p| !p.is_empty()),
+                "max_new_tokens": max_new_tokens,
+                "response_format": response_format,
+                "sampling": sampling,
+                "stop": stop,
+            });
+            Ok::<_, String>(response_cache::cache_key(&request, temperature, payload.seed))
+        })
+        .transpose()?;
+    let cached = cache_key.as_deref().and_then(|key| response_cache::load(&app, key));
+
+    {
+        let mut inner = state
+            .inner
+            .lock()
+            .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+        // A cache hit is streamed by Cerebro like mock mode, without the runner.
+        let mock_mode = inner.mock_mode;
+        if mock_mode || cached.is_some() {
+            if inner.draining {
+                return Err("Python runtime is shutting down".to_string().into());
+            }
+            if let Some(old) = &replaces {
+                cancel_generation(&mut inner, old)?;
+            }
+            let generation_id = payload.generation_id.unwrap_or_else(generate_id);
+            let generation = ActiveGeneration {
+                model: payload.model.clone(),
+                compare: payload.compare,
+                external: payload.external,
+                chunker,
+                ..ActiveGeneration::new()
+            };
+            inner.generations.insert(generation_id.clone(), generation);
+            drop(inner);
+
+            if let Some(old) = replaces {
+                spawn_cancel_ack_watchdog(app.clone(), state.inner.clone(), old);
+            }
+            spawn_generation_heartbeat(app.clone(), state.inner.clone(), generation_id.clone());
+            if let Some(max_duration) = max_duration {
+                spawn_max_duration_watchdog(
+                    state.inner.clone(),
+                    generation_id.clone(),
+                    max_duration,
+                );
+            }
+            if let Some(timeout) = idle_timeout {
+                let runtime = state.inner.clone();
+                spawn_idle_timeout_watchdog(app.clone(), runtime, generation_id.clone(), timeout);
+            }
+            match cached.filter(|_| !mock_mode) {
+                Some(entry) => {
+                    let _ = app.emit(
+                        "cerebro:chat_cache_hit",
+                        serde_json::json!({
+                            "generation_id": generation_id,
+                            "key": cache_key,
+                            "tokens": entry.tokens.len(),
+                        }),
+                    );
+                    let replay = mock::Replay {
+                        finish_reason: entry.finish_reason.clone(),
+                        tokens: entry.replay_tokens(payload.cache_replay.unwrap_or_default()),
+                        cached: true,
+                    };
+                    let runtime = state.inner.clone();
+                    mock::spawn_replay(app, runtime, generation_id.clone(), seed, replay);
+                }
+                None => {
+                    let runtime = state.inner.clone();
+                    mock::spawn_mock_generation(app, runtime, generation_id.clone(), seed);
+                }
+            }
+            return Ok(ChatGenerateStarted { generation_id, seed });
+        }
+    }
+
+    ensure_python_runtime(&app, state)?;
+
+    // Always load from the previously downloaded local directory.
+    // The UI passes the model as a Hugging Face repo id; we map it to our
+    // app_data_dir/models/<sanitized_repo_id> location, or to the revision
+    // `switch_model` loaded for it.
+    let model_local_dir = require_local_revision(&app, &payload.model, revision.as_deref())?;
+    let model_local_dir_str = model_local_dir.to_string_lossy().to_string();
+    let prefill = payload.prefill.filter(|p| !p.is_empty());
+    if images.is_some() && !images::accepts_images(&model_local_dir) {
+        return Err(CommandError::NotMultimodal {
+            repo_id: payload.model,
+        });
+    }
+
+    let generation_id = payload.generation_id.unwrap_or_else(generate_id);
+    let msg = serde_json::json!({
+        "type": "generate",
+        "generation_id": generation_id,
+        "model": model_local_dir_str,
+        "repo_id": payload.model,
+        "prompt": payload.prompt,
+        "messages": messages,
+        "max_new_tokens": max_new_tokens,
+        "temperature": temperature,
+        "top_p": sampling.top_p,
+        "top_k": sampling.top_k,
+        "repetition_penalty": sampling.repetition_penalty,
+        "stop": stop,
+        "seed": seed,
+        "prefill": prefill,
+        "response_format": response_format,
+        "images": images,
+    });
+
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    if inner.draining {
+        return Err("Python runtime is shutting down".to_string().into());
+    }
+    if inner.pending_switch.is_some() {
+        return Err("A model switch is in progress".to_string().into());
+    }
+    if prefill.is_some() {
+        require_runner_feature(&inner, "prefill")?;
+    }
+    if response_format.is_some() {
+        require_runner_feature(&inner, "response_format")?;
+    }
+    if images.is_some() {
+        require_runner_feature(&inner, "images")?;
+    }
+    if messages.is_some() {
+        require_runner_feature(&inner, "messages")?;
+    }
+    if stop.is_some() {
+        require_runner_feature(&inner, "stop")?;
+    }
+    if inner.stdin.is_none() {
+        return Err(CommandError::RuntimeNotRunning);
+    }
+    // The runner reads its stdin in order, so the cancel lands before the generate.
+    if let Some(old) = &replaces {
+        cancel_generation(&mut inner, old)?;
+    }
+    inner.send_to_runner(&msg, "generate")?;
+
+    let generation = ActiveGeneration {
+        model: payload.model.clone(),
+        compare: payload.compare,
+        json_output: response_format.is_some(),
+        external: payload.external,
+        chunker,
+        cache_key,
+        ..ActiveGeneration::new()
+    };
+    inner.generations.insert(generation_id.clone(), generation);
+    drop(inner);
+
+    record_last_used(&app, &payload.model);
+    if let Some(old) = replaces {
+        spawn_cancel_ack_watchdog(app.clone(), state.inner.clone(), old);
+    }
+    if let Some(timeout) = idle_timeout {
+        let runtime = state.inner.clone();
+        spawn_idle_timeout_watchdog(app.clone(), runtime, generation_id.clone(), timeout);
+    }
+    spawn_generation_heartbeat(app, state.inner.clone(), generation_id.clone());
+    if let Some(max_duration) = max_duration {
+        spawn_max_duration_watchdog(state.inner.clone(), generation_id.clone(), max_duration);
+    }
+
+    Ok(ChatGenerateStarted { generation_id, seed })
+}
+
+/// Runs `prompt` once per model with the same parameters and seed. Members go
+/// to the runner one after another, so only one model is loaded at a time, and
+/// all their ids are returned up front; besides the usual `chat_*` events,
+/// tokens are mirrored as `cerebro:compare_token` and `cerebro:compare_done`
+/// carries per-model metrics once every member has finished.
+#[tauri::command]
+fn chat_compare(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    prompt: String,
+    params: Option<CompareParams>,
+    models: Vec<String>,
+) -> Result<CompareBatch, CommandError> {
+    let mut unique: Vec<String> = Vec::new();
+    for model in models {
+        let model = model.trim();
+        if !model.is_empty() && !unique.iter().any(|m| m == model) {
+            unique.push(model.to_string());
+        }
+    }
+    if unique.len() < 2 {
+        return Err("chat_compare needs at least two distinct models".to_string().into());
+    }
+    let params = params.unwrap_or_default();
+    let seed = resolve_seed(params.seed)?;
+
+    let batch_id = generate_id();
+    let mut generations: Vec<CompareMember> = Vec::new();
+    let mut pending: VecDeque<ChatGeneratePayload> = VecDeque::new();
+    for model in unique {
+        // Preassigned so every member's events can be routed from the start.
+        let generation_id = generate_id();
+        pending.push_back(ChatGeneratePayload {
+            max_new_tokens: params.max_new_tokens,
+            temperature: params.temperature,
+            seed: Some(seed),
+            max_duration_ms: params.max_duration_ms,
+            compare: Some(CompareTag {
+                batch_id: batch_id.clone(),
+                model: model.clone(),
+            }),
+            generation_id: Some(generation_id.clone()),
+            ..ChatGeneratePayload::new(model.clone(), prompt.clone())
+        });
+        generations.push(CompareMember {
+            model,
+            generation_id,
+        });
+    }
+    let first = pending.pop_front().expect("at least two members");
+    state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .compare_batches
+        .insert(
+            batch_id.clone(),
+            CompareBatchState {
+                expected: generations.len(),
+                results: Vec::new(),
+                pending,
+            },
+        );
+
+    // The rest follow one by one from `record_compare_result`.
+    if let Err(e) = start_generation(app.clone(), &state, &settings, first) {
+        if let Ok(mut inner) = state.inner.lock() {
+            inner.compare_batches.remove(&batch_id);
+        }
+        return Err(e);
+    }
+
+    Ok(CompareBatch {
+        batch_id,
+        seed,
+        generations,
+    })
+}
+
+/// Starts the next queued member of `batch_id`. One that fails to start ends
+/// with a `cerebro:chat_error` and the batch moves on.
+fn start_next_compare_member(app: &tauri::AppHandle, batch_id: &str) {
+    let state = app.state::<PythonRuntimeState>();
+    let payload = match state.inner.lock() {
+        Ok(mut inner) => inner
+            .compare_batches
+            .get_mut(batch_id)
+            .and_then(|batch| batch.pending.pop_front()),
+        Err(_) => None,
+    };
+    let Some(payload) = payload else {
+        return;
+    };
+    let (generation_id, tag) = (payload.generation_id.clone(), payload.compare.clone());
+    let settings = app.state::<SettingsState>();
+    let Err(e) = start_generation(app.clone(), &state, &settings, payload) else {
+        return;
+    };
+    let (Some(generation_id), Some(tag)) = (generation_id, tag) else {
+        return;
+    };
+    let _ = app.emit(
+        "cerebro:chat_error",
+        serde_json::json!({
+            "type": "error",
+            "generation_id": generation_id,
+            "message": e.to_string(),
+        }),
+    );
+    let result = CompareResult::new(&generation_id, &ActiveGeneration::new(), &tag, "error");
+    if let Ok(mut inner) = state.inner.lock() {
+        inner.record_compare_result(app, batch_id, result);
+    };
+}
+
+/// Asks the runner to stop `generation_id`. Completion is reported separately
+/// through `cerebro:chat_cancelled`.
+#[tauri::command]
+fn chat_cancel(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    generation_id: String,
+) -> Result<(), String> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    // A compare member still waiting for its turn never reaches the runner.
+    if let Some(tag) = inner.take_pending_compare_member(&generation_id) {
+        let unstarted = ActiveGeneration::new();
+        let result = CompareResult::new(&generation_id, &unstarted, &tag, "cancelled");
+        inner.record_compare_result(&app, &tag.batch_id, result);
+        let _ = app.emit(
+            "cerebro:chat_cancelled",
+            serde_json::json!({ "type": "cancelled", "generation_id": generation_id }),
+        );
+        return Ok(());
+    }
+    cancel_generation(&mut inner, &generation_id)?;
+    drop(inner);
+    spawn_cancel_ack_watchdog(app, state.inner.clone(), generation_id);
+    Ok(())
+}
+
+/// Cancels the most recently started generation that isn't already stopping
+/// and returns its id, so repeated calls work back through older ones.
+#[tauri::command]
+fn chat_cancel_latest(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+) -> Result<String, String> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let Some(generation_id) = inner.latest_cancellable_generation() else {
+        return Err("No active generation to cancel".to_string());
+    };
+    cancel_generation(&mut inner, &generation_id)?;
+    drop(inner);
+    spawn_cancel_ack_watchdog(app, state.inner.clone(), generation_id.clone());
+    Ok(generation_id)
+}
+
+fn cancel_generation(inner: &mut PythonRuntimeInner, generation_id: &str) -> Result<(), String> {
+    if dev_mode_enabled() {
+        println!("Requesting cancel for generation_id={generation_id}");
+    }
+
+    if let Some(g) = inner.generations.get_mut(generation_id) {
+        g.cancel_requested_at.get_or_insert_with(Instant::now);
+    }
+    if inner.mock_mode {
+        // The mock stream watches `cancel_requested_at` itself.
+        return Ok(());
+    }
+
+    let msg = serde_json::json!({
+        "type": "cancel",
+        "generation_id": generation_id,
+    });
+    if inner.stdin.is_none() {
+        return Ok(());
+    }
+    inner.send_to_runner(&msg, "cancel")
+}
+
+/// Serves `chat_generate`/`model_download_start` from canned data instead of the
+/// Python runner, for frontend development and demos.
+#[tauri::command]
+fn set_mock_mode(state: State<PythonRuntimeState>, enabled: bool) -> Result<(), String> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    inner.mock_mode = enabled;
+    Ok(())
+}
+
+/// Writes `json` to the runner's stdin as-is, starting the runner if needed, so
+/// protocol messages can be tried out without adding a command for them. Dev
+/// mode only.
+#[tauri::command]
+fn send_raw_runner_message(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    json: String,
+) -> Result<(), String> {
+    require_dev_mode("send_raw_runner_message")?;
+    let msg: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid JSON: {e}"))?;
+    if !msg.is_object() {
+        return Err("Runner messages must be JSON objects".to_string());
+    }
+    ensure_python_runtime(&app, &state).map_err(|e| e.to_string())?;
+
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    inner.send_to_runner(&msg, "raw message")
+}
+
+/// Recent raw runner traffic (stdin, stdout and stderr), oldest first. Dev mode only.
+#[tauri::command]
+fn get_raw_runner_log(state: State<PythonRuntimeState>) -> Result<Vec<RawRunnerLine>, String> {
+    require_dev_mode("get_raw_runner_log")?;
+    let inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    Ok(inner.raw_log.iter().cloned().collect())
+}
+
+/// Emits `cerebro:state_resync` with every active generation (and its text so
+/// far), every active download and the runtime status, so a freshly loaded
+/// webview can rebuild its view. Safe to call any number of times.
+#[tauri::command]
+fn resync_state(app: tauri::AppHandle, state: State<PythonRuntimeState>) -> Result<(), String> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+
+    let generations = inner
+        .generations
+        .iter_mut()
+        .map(|(generation_id, g)| {
+            // The snapshot carries the full text, so nothing is left to flush.
+            g.pending_text.clear();
+            GenerationSnapshot {
+                generation_id: generation_id.clone(),
+                phase: g.phase,
+                elapsed_ms: g.started_at.elapsed().as_millis() as u64,
+                text: g.text.clone(),
+            }
+        })
+        .collect();
+    let snapshot = StateSnapshot {
+        generations,
+        downloads: inner.download_infos(),
+        runtime: RuntimeSnapshot {
+            running: inner.child.is_some(),
+            mock_mode: inner.mock_mode,
+            draining: inner.draining,
+            switching: inner.pending_switch.is_some(),
+            loaded_model: inner.loaded_model.clone(),
+        },
+    };
+
+    // Emitted under the lock so no token can slip in between snapshot and event.
+    let _ = app.emit("cerebro:state_resync", snapshot);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_loaded_model(state: State<PythonRuntimeState>) -> Result<Option<LoadedModel>, String> {
+    let inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    Ok(inner.loaded_model.clone())
+}
+
+/// Unloads the current model and loads `repo_id`, reporting through
+/// `cerebro:model_switch_progress` / `_done` / `_error`.
+#[tauri::command]
+fn switch_model(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    repo_id: String,
+    revision: Option<String>,
+) -> Result<ModelSwitchStarted, CommandError> {
+    ensure_python_runtime(&app, &state)?;
+
+    let model_local_dir = require_local_revision(&app, &repo_id, revision.as_deref())?;
+    let model_local_dir_str = model_local_dir.to_string_lossy().to_string();
+
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    if !inner.generations.is_empty() {
+        return Err("Cannot switch models while a generation is active".to_string().into());
+    }
+    if inner.pending_switch.is_some() {
+        return Err("A model switch is already in progress".to_string().into());
+    }
+
+    let switch_id = generate_id();
+    let msg = serde_json::json!({
+        "type": "load",
+        "switch_id": switch_id,
+        "model": model_local_dir_str,
+        "repo_id": repo_id,
+        "revision": revision,
+    });
+
+    inner.send_to_runner(&msg, "load")?;
+
+    inner.pending_switch = Some(PendingSwitch {
+        switch_id: switch_id.clone(),
+        repo_id: repo_id.clone(),
+        revision: revision.clone(),
+        local_dir: model_local_dir_str,
+    });
+    drop(inner);
+
+    let _ = app.emit(
+        "cerebro:model_switch_started",
+        serde_json::json!({
+            "switch_id": switch_id,
+            "repo_id": repo_id,
+            "revision": revision,
+        }),
+    );
+
+    Ok(ModelSwitchStarted { switch_id })
+}
+
+/// How long `validate_model_loads` waits for the runner to load and drop a model.
+const VALIDATE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Smoke-tests a downloaded model: the runner loads it on the side and frees it
+/// again, failing with the exact load error. The currently loaded model is left
+/// alone. The outcome is also emitted as `cerebro:model_validate_result`.
+#[tauri::command]
+async fn validate_model_loads(
+    app: tauri::AppHandle,
+    state: State<'_, PythonRuntimeState>,
+    repo_id: String,
+) -> Result<(), CommandError> {
+    ensure_python_runtime(&app, &state)?;
+    let model_local_dir = require_local_model(&app, &repo_id)?;
+
+    let validate_id = generate_id();
+    let (tx, rx) = mpsc::channel();
+    {
+        let mut inner = state
+            .inner
+            .lock()
+            .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+        require_runner_feature(&inner, "validate")?;
+        let msg = serde_json::json!({
+            "type": "validate",
+            "validate_id": validate_id,
+            "model": model_local_dir.to_string_lossy(),
+        });
+        inner.send_to_runner(&msg, "validate")?;
+        inner.validations.insert(validate_id.clone(), tx);
+    }
+
+    let runtime = state.inner.clone();
+    let id = validate_id.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let result = match rx.recv_timeout(VALIDATE_TIMEOUT) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(format!(
+                "Model did not load within {}s",
+                VALIDATE_TIMEOUT.as_secs()
+            )),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err("Python runtime stopped before the model loaded".to_string())
+            }
+        };
+        if let Ok(mut inner) = runtime.lock() {
+            inner.validations.remove(&id);
+        }
+        result
+    })
+    .await
+    .map_err(|e| format!("Validation task failed: {e}"))?;
+
+    let _ = app.emit(
+        "cerebro:model_validate_result",
+        serde_json::json!({
+            "validate_id": validate_id,
+            "repo_id": repo_id,
+            "ok": result.is_ok(),
+            "message": result.as_ref().err(),
+        }),
+    );
+    result.map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn model_download_start(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    payload: ModelDownloadPayload,
+) -> Result<ModelDownloadStarted, CommandError> {
+    start_model_download(&app, &state, &settings, payload)
+}
+
+/// True if any directory under app_data_dir/models has content.
+fn any_model_downloaded(app: &tauri::AppHandle) -> Result<bool, String> {
+    let models_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?
+        .join("models");
+    let Ok(entries) = fs::read_dir(&models_dir) else {
+        return Ok(false);
+    };
+    Ok(entries.flatten().any(|entry| {
+        entry.path().is_dir()
+            && fs::read_dir(entry.path())
+                .ok()
+                .and_then(|mut it| it.next())
+                .is_some()
+    }))
+}
+
+/// First-run helper: downloads `default_model_repo_id` unless a model is already
+/// installed, reporting through `cerebro:onboarding_progress`. Returns `None` when skipped.
+#[tauri::command]
+fn setup_default_model(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+) -> Result<Option<ModelDownloadStarted>, CommandError> {
+    let repo_id = settings.get().default_model_repo_id;
+
+    if any_model_downloaded(&app)? {
+        let _ = app.emit(
+            "cerebro:onboarding_progress",
+            serde_json::json!({ "stage": "skipped", "repo_id": repo_id }),
+        );
+        return Ok(None);
+    }
+
+    let started = start_model_download(
+        &app,
+        &state,
+        &settings,
+        ModelDownloadPayload {
+            repo_id: repo_id.clone(),
+            revision: None,
+            token: None,
+            expected_size_bytes: None,
+        },
+    )?;
+
+    if let Ok(mut inner) = state.inner.lock() {
+        inner.onboarding_download_id = Some(started.download_id.clone());
+    }
+    let _ = app.emit(
+        "cerebro:onboarding_progress",
+        serde_json::json!({
+            "stage": "started",
+            "download_id": started.download_id,
+            "repo_id": repo_id,
+        }),
+    );
+
+    Ok(Some(started))
+}
+
+fn start_model_download(
+    app: &tauri::AppHandle,
+    state: &PythonRuntimeState,
+    settings: &SettingsState,
+    payload: ModelDownloadPayload,
+) -> Result<ModelDownloadStarted, CommandError> {
+    let settings = settings.get();
+    app.state::<NetworkState>().ensure_online()?;
+    app.state::<RateLimiterState>()
+        .acquire("model_download_start", &settings)?;
+    let mock_mode = state.inner.lock().map(|i| i.mock_mode).unwrap_or(false);
+    if !mock_mode {
+        ensure_python_runtime(app, state)?;
+    }
+
+    let download_id = generate_id();
+    // Pinned revisions get their own folder so they don't overwrite the default branch.
+    let local_dir = models::revision_dir(
+        &compute_model_local_dir(app, &payload.repo_id)?,
+        payload.revision.as_deref(),
+    );
+    // Recording the repo id lets `list_models` map the folder back to it.
+    app.state::<ModelIndexState>()
+        .update_entry(app, &payload.repo_id, |_| {})?;
+    let local_dir_str = local_dir.to_string_lossy().to_string();
+
+    if mock_mode {
+        mock::spawn_mock_download(
+            app.clone(),
+            download_id.clone(),
+            payload.repo_id,
+            local_dir_str.clone(),
+        );
+        return Ok(ModelDownloadStarted {
+            download_id,
+            local_dir: local_dir_str,
+            queued: false,
+        });
+    }
+
+    warn_if_unsupported_architecture(app, &payload.repo_id);
+    let expected_bytes = payload.expected_size_bytes.or_else(|| {
+        app.state::<ModelInfoCache>()
+            .get(&payload.repo_id)
+            .and_then(|info| info.get("usedStorage")?.as_u64())
+    });
+    if let Some(expected) = expected_bytes {
+        ensure_free_space(&payload.repo_id, &local_dir, expected)?;
+    }
+    let lock = lock_model_dir(app, &payload.repo_id)?;
+
+    let download = ActiveDownload {
+        repo_id: payload.repo_id,
+        revision: payload.revision,
+        local_dir: local_dir_str.clone(),
+        paused: false,
+        queued: false,
+        token: payload.token,
+        progress: None,
+        clock: None,
+        lock: Some(Arc::new(lock)),
+    };
+
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let queued = inner.running_downloads() >= settings.max_concurrent_downloads;
+    if queued {
+        inner.downloads.insert(download_id.clone(), download);
+        inner.enqueue_download(app, &download_id);
+    } else {
+        send_download_message(&mut inner, &download_id, &download, &settings)?;
+        inner.downloads.insert(download_id.clone(), download);
+    }
+    inner.persist_downloads(app);
+
+    Ok(ModelDownloadStarted {
+        download_id,
+        local_dir: local_dir_str,
+        queued,
+    })
+}
+
+/// Left free on top of a download, so finishing one doesn't fill the disk.
+const DOWNLOAD_FREE_SPACE_RESERVE: u64 = 256 * 1024 * 1024;
+
+/// Fails when the volume holding `local_dir` can't fit the `expected_bytes`
+/// still missing from it. Files already there count, so resumes aren't refused.
+fn ensure_free_space(
+    repo_id: &str,
+    local_dir: &Path,
+    expected_bytes: u64,
+) -> Result<(), CommandError> {
+    let Some(free_bytes) = storage::free_bytes(local_dir) else {
+        return Ok(());
+    };
+    let present: u64 = models::walk_files(local_dir).iter().map(|(_, len)| len).sum();
+    let required_bytes = expected_bytes.saturating_sub(present) + DOWNLOAD_FREE_SPACE_RESERVE;
+    if free_bytes < required_bytes {
+        return Err(CommandError::InsufficientDiskSpace {
+            repo_id: repo_id.to_string(),
+            required_bytes,
+            free_bytes,
+        });
+    }
+    Ok(())
+}
+
+fn lock_model_dir(
+    app: &tauri::AppHandle,
+    repo_id: &str,
+) -> Result<model_lock::ModelDirLock, CommandError> {
+    model_lock::acquire(&compute_model_local_dir(app, repo_id)?, repo_id)
+}
+
+/// Asks the runner to (re)start `download_id`. Files already on disk are
+/// skipped or continued with a Range request, which is what makes resume work.
+fn send_download_message(
+    inner: &mut PythonRuntimeInner,
+    download_id: &str,
+    download: &ActiveDownload,
+    settings: &Settings,
+) -> Result<(), String> {
+    let msg = serde_json::json!({
+        "type": "download",
+        "download_id": download_id,
+        "repo_id": download.repo_id,
+        "revision": download.revision,
+        "local_dir": download.local_dir,
+        // Files already there are continued rather than fetched again.
+        "resume": models::has_content(Path::new(&download.local_dir)),
+        // Read here rather than kept on the download, so a resume after a
+        // restart still authenticates and the token isn't held in memory.
+        "token": download.token.clone().or_else(hf_token::load),
+        "endpoint": settings.hf_endpoint,
+        "headers": settings.hf_headers,
+    });
+
+    inner.send_to_runner(&msg, "download")
+}
+
+/// Stops transferring `download_id` while keeping partial files. The runner
+/// confirms with `cerebro:model_download_paused`.
+#[tauri::command]
+fn pause_download(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    download_id: String,
+) -> Result<(), String> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let Some(download) = inner.downloads.get_mut(&download_id) else {
+        return Err(format!("Unknown download: {download_id}"));
+    };
+    if download.paused {
+        return Ok(());
+    }
+    // Not started yet, so there is nothing for the runner to stop.
+    if download.queued {
+        download.queued = false;
+        download.paused = true;
+        let repo_id = download.repo_id.clone();
+        inner.download_queue.retain(|id| id != &download_id);
+        inner.persist_downloads(&app);
+        inner.emit_queue_positions(&app);
+        let _ = app.emit(
+            "cerebro:model_download_paused",
+            serde_json::json!({
+                "type": "download_paused",
+                "download_id": download_id,
+                "repo_id": repo_id,
+            }),
+        );
+        return Ok(());
+    }
+    let msg = serde_json::json!({
+        "type": "download_pause",
+        "download_id": download_id,
+    });
+    inner.send_to_runner(&msg, "download_pause")
+}
+
+/// Continues a paused download (including one paused in a previous session)
+/// from the bytes already on disk.
+#[tauri::command]
+fn resume_download(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    download_id: String,
+) -> Result<(), CommandError> {
+    let mut download = {
+        let inner = state
+            .inner
+            .lock()
+            .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+        match inner.downloads.get(&download_id) {
+            None => return Err(format!("Unknown download: {download_id}").into()),
+            Some(download) if !download.paused => return Ok(()),
+            Some(download) => download.clone(),
+        }
+    };
+    // Downloads restored from a previous run start out unlocked.
+    if download.lock.is_none() {
+        download.lock = Some(Arc::new(lock_model_dir(&app, &download.repo_id)?));
+    }
+
+    app.state::<NetworkState>().ensure_online()?;
+    ensure_python_runtime(&app, &state)?;
+    let settings = settings.get();
+
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    if inner.running_downloads() >= settings.max_concurrent_downloads {
+        inner.enqueue_download(&app, &download_id);
+    } else {
+        send_download_message(&mut inner, &download_id, &download, &settings)?;
+        if let Some(entry) = inner.downloads.get_mut(&download_id) {
+            entry.paused = false;
+        }
+    }
+    if let Some(entry) = inner.downloads.get_mut(&download_id) {
+        entry.lock.clone_from(&download.lock);
+    }
+    inner.persist_downloads(&app);
+    drop(inner);
+
+    let _ = app.emit(
+        "cerebro:model_download_resumed",
+        serde_json::json!({
+            "type": "download_resumed",
+            "download_id": download_id,
+            "repo_id": download.repo_id,
+        }),
+    );
+    Ok(())
+}
+
+/// Picks up an interrupted download of `repo_id`: resumes its paused entry if
+/// there is one, otherwise starts a download that continues from the partial
+/// files in its folder. Fails when there is nothing on disk to resume from.
+#[tauri::command]
+fn model_download_resume(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    settings: State<SettingsState>,
+    repo_id: String,
+    revision: Option<String>,
+) -> Result<ModelDownloadStarted, CommandError> {
+    let local_dir = models::revision_dir(
+        &compute_model_local_dir(&app, &repo_id)?,
+        revision.as_deref(),
+    );
+    let local_dir_str = local_dir.to_string_lossy().to_string();
+
+    let existing = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .downloads
+        .iter()
+        .find(|(_, d)| d.local_dir == local_dir_str)
+        .map(|(id, d)| (id.clone(), d.paused));
+    if let Some((download_id, paused)) = existing {
+        if paused {
+            resume_download(app.clone(), state.clone(), settings, download_id.clone())?;
+        }
+        let queued = state.inner.lock().is_ok_and(|inner| {
+            inner
+                .downloads
+                .get(&download_id)
+                .is_some_and(|d| d.queued)
+        });
+        return Ok(ModelDownloadStarted {
+            download_id,
+            local_dir: local_dir_str,
+            queued,
+        });
+    }
+
+    if !models::has_content(&local_dir) {
+        return Err(format!("Nothing to resume: {} is empty", local_dir.display()).into());
+    }
+    start_model_download(
+        &app,
+        &state,
+        &settings,
+        ModelDownloadPayload {
+            repo_id,
+            revision,
+            token: None,
+            expected_size_bytes: None,
+        },
+    )
+}
+
+/// Generations that are currently running, oldest first.
+#[tauri::command]
+fn chat_list_active(state: State<PythonRuntimeState>) -> Result<Vec<GenerationMeta>, String> {
+    let inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    let now = SystemTime::now();
+    let mut active: Vec<GenerationMeta> = inner
+        .generations
+        .iter()
+        .map(|(id, g)| {
+            let elapsed = g.started_at.elapsed();
+            GenerationMeta {
+                generation_id: id.clone(),
+                model: g.model.clone(),
+                started_at: (now - elapsed)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+                elapsed_ms: elapsed.as_millis() as u64,
+                phase: g.phase,
+                cancelling: g.cancel_requested_at.is_some(),
+            }
+        })
+        .collect();
+    active.sort_by_key(|g| std::cmp::Reverse(g.elapsed_ms));
+    Ok(active)
+}
+
+/// Downloads that have started but not finished, paused ones included, with
+/// the latest progress snapshot for each.
+#[tauri::command]
+fn list_active_downloads(state: State<PythonRuntimeState>) -> Result<Vec<DownloadInfo>, String> {
+    let inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    Ok(inner.download_infos())
+}
+
+/// Runner messages seen for one download, oldest first.
+#[tauri::command]
+fn get_download_log(
+    state: State<PythonRuntimeState>,
+    download_id: String,
+) -> Result<Vec<String>, String> {
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+    inner.evict_download_logs();
+    Ok(inner
+        .download_logs
+        .get(&download_id)
+        .map(|log| log.lines.iter().cloned().collect())
+        .unwrap_or_default())
+}
+
+/// Inspects a downloaded model's files to report weight format, quantization and
+/// an approximate parameter count. Cached in the model index until the weights change.
+#[tauri::command]
+fn detect_model_format(
+    app: tauri::AppHandle,
+    index: State<ModelIndexState>,
+    repo_id: String,
+) -> Result<ModelFormat, String> {
+    cached_model_format(&app, &index, &repo_id).map(|(_, format)| format)
+}
+
+/// A small text file (`config.json`, `README.md`, ...) from a downloaded model,
+/// for the model-details panel. Never reads outside the model's folder.
+#[tauri::command]
+fn read_model_file(
+    app: tauri::AppHandle,
+    repo_id: String,
+    relative_path: String,
+) -> Result<String, String> {
+    let model_dir = require_local_model(&app, &repo_id)?;
+    models::read_model_text_file(&model_dir, &relative_path)
+}
+
+/// The Jinja chat template of a downloaded model, so the UI can preview how
+/// messages will be formatted.
+#[tauri::command]
+fn get_chat_template(app: tauri::AppHandle, repo_id: String) -> Result<String, CommandError> {
+    let model_dir = require_local_model(&app, &repo_id)?;
+    models::chat_template(&model_dir).ok_or(CommandError::NoChatTemplate { repo_id })
+}
+
+fn cached_model_format(
+    app: &tauri::AppHandle,
+    index: &ModelIndexState,
+    repo_id: &str,
+) -> Result<(PathBuf, ModelFormat), String> {
+    let model_dir = require_local_model(app, repo_id)?;
+    let fingerprint = models::weights_fingerprint(&model_dir);
+
+    if let Some(cached) = index.entry(repo_id).and_then(|e| e.format) {
+        if cached.fingerprint == fingerprint {
+            return Ok((model_dir, cached.format));
+        }
+    }
+
+    let format = models::detect_model_format(&model_dir);
+    index.update_entry(app, repo_id, |entry| {
+        entry.format = Some(CachedFormat {
+            fingerprint,
+            format: format.clone(),
+        });
+    })?;
+    Ok((model_dir, format))
+}
+
+const DEFAULT_ESTIMATE_CONTEXT_TOKENS: u32 = 4096;
+/// Allocator slack, activations and the CUDA/torch runtime itself.
+const MEMORY_OVERHEAD_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Ballpark memory needed to load `repo_id` and run `context_tokens` of
+/// context, compared against what the runner reported for this machine.
+#[tauri::command]
+fn estimate_model_memory(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    index: State<ModelIndexState>,
+    repo_id: String,
+    context_tokens: Option<u32>,
+) -> Result<MemoryEstimate, String> {
+    let (model_dir, format) = cached_model_format(&app, &index, &repo_id)?;
+    let context_tokens = context_tokens.unwrap_or(DEFAULT_ESTIMATE_CONTEXT_TOKENS);
+
+    let weights_bytes = models::loaded_weight_bytes(&format);
+    let kv_cache_bytes =
+        models::kv_cache_bytes(&model_dir, u64::from(context_tokens)).unwrap_or(0);
+    let overhead_bytes = weights_bytes / 10 + MEMORY_OVERHEAD_BYTES;
+    let total_bytes = weights_bytes + kv_cache_bytes + overhead_bytes;
+
+    let memory = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .capabilities
+        .as_ref()
+        .map(|caps| caps.memory.clone())
+        .unwrap_or_default();
+    let (device, available_bytes) = match memory.vram_bytes {
+        Some(vram) => ("cuda", Some(vram)),
+        None => ("ram", memory.ram_bytes),
+    };
+    let headroom_bytes = available_bytes.map(|a| a as i64 - total_bytes as i64);
+
+    Ok(MemoryEstimate {
+        weights_bytes,
+        kv_cache_bytes,
+        overhead_bytes,
+        total_bytes,
+        device: device.to_string(),
+        available_bytes,
+        headroom_bytes,
+        fits: headroom_bytes.map(|h| h >= 0),
+    })
+}
+
+fn models_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?
+        .join("models"))
+}
+
+/// Installed models with their tags and favorite flag, optionally filtered.
+#[tauri::command]
+fn list_models(
+    app: tauri::AppHandle,
+    index: State<ModelIndexState>,
+    filter: Option<ModelFilter>,
+    sort: Option<ModelSort>,
+) -> Result<Vec<ModelInfo>, String> {
+    let filter = filter.unwrap_or_default();
+    let mut models: Vec<ModelInfo> = index
+        .installed_models(&models_dir(&app)?)
+        .into_iter()
+        .filter(|m| filter.matches(m))
+        .collect();
+    if let Some(sort) = sort {
+        sort.apply(&mut models);
+    }
+    Ok(models)
+}
+
+/// Every model folder on disk with its size, for a model picker.
+#[tauri::command]
+fn model_list_downloaded(
+    app: tauri::AppHandle,
+    index: State<ModelIndexState>,
+) -> Result<Vec<models::DownloadedModel>, String> {
+    Ok(index
+        .installed_models(&models_dir(&app)?)
+        .into_iter()
+        .map(models::DownloadedModel::from)
+        .collect())
+}
+
+/// Locally downloaded revisions of `repo_id` with their size and age, so the UI
+/// can offer which one to load or delete.
+#[tauri::command]
+fn list_model_revisions(
+    app: tauri::AppHandle,
+    repo_id: String,
+) -> Result<Vec<models::RevisionInfo>, String> {
+    Ok(models::list_revisions(&compute_model_local_dir(&app, &repo_id)?))
+}
+
+/// Replaces the tags on `repo_id`. Blank tags and case-insensitive duplicates are dropped.
+#[tauri::command]
+fn set_model_tags(
+    app: tauri::AppHandle,
+    index: State<ModelIndexState>,
+    repo_id: String,
+    tags: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !cleaned.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            cleaned.push(tag.to_string());
+        }
+    }
+    index
+        .update_entry(&app, &repo_id, |entry| entry.tags = cleaned)
+        .map(|entry| entry.tags)
+}
+
+#[tauri::command]
+fn get_model_tags(index: State<ModelIndexState>, repo_id: String) -> Vec<String> {
+    index.entry(&repo_id).map(|e| e.tags).unwrap_or_default()
+}
+
+#[tauri::command]
+fn set_model_favorite(
+    app: tauri::AppHandle,
+    index: State<ModelIndexState>,
+    repo_id: String,
+    favorite: bool,
+) -> Result<(), String> {
+    index.update_entry(&app, &repo_id, |entry| entry.favorite = favorite)?;
+    Ok(())
+}
+
+/// Deletes `repo_id` from disk, pinned revisions included, and returns the
+/// bytes freed. Refused while a generation is using the model; its tags and
+/// favorite flag stay in the index for a later re-download.
+#[tauri::command]
+async fn model_delete(
+    app: tauri::AppHandle,
+    state: State<'_, PythonRuntimeState>,
+    repo_id: String,
+) -> Result<u64, CommandError> {
+    let base = models_dir(&app)?;
+    let dir = compute_model_local_dir(&app, &repo_id)?;
+    models::ensure_model_subdir(&base, &dir)?;
+    // Held until the folders are gone, so a download or import can't start
+    // writing into them halfway through.
+    let _lock = model_lock::acquire(&dir, &repo_id)?;
+
+    let in_use = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .generations
+        .values()
+        .any(|g| g.model == repo_id);
+    if in_use {
+        return Err(format!("{repo_id} is in use by a running generation").into());
+    }
+
+    let revisions = models::list_revisions(&dir);
+    let freed = tauri::async_runtime::spawn_blocking(move || {
+        let mut freed = 0;
+        for revision in revisions {
+            let path = PathBuf::from(&revision.local_dir);
+            models::ensure_model_subdir(&base, &path)?;
+            fs::remove_dir_all(&path)
+                .map_err(|e| format!("Failed to delete {}: {e}", path.display()))?;
+            freed += revision.size_bytes;
+        }
+        Ok::<u64, String>(freed)
+    })
+    .await
+    .map_err(|e| format!("Delete task failed: {e}"))??;
+    app.state::<StorageSummaryCache>().invalidate();
+
+    let _ = app.emit(
+        "cerebro:model_deleted",
+        serde_json::json!({ "repo_id": repo_id, "freed_bytes": freed }),
+    );
+    Ok(freed)
+}
+
+/// Adopts a model downloaded outside the app (e.g. with `huggingface-cli`) by
+/// copying, or moving when `move_files` is set, `source_dir` into the managed store.
+/// Where it came from is recorded in the folder's `meta.json`, so it survives
+/// an index rebuild or the folder being copied elsewhere.
+#[tauri::command]
+async fn import_model(
+    app: tauri::AppHandle,
+    repo_id: String,
+    source_dir: String,
+    move_files: Option<bool>,
+) -> Result<(), CommandError> {
+    let source = PathBuf::from(&source_dir);
+    if !source.is_dir() {
+        return Err(format!("Not a directory: {source_dir}").into());
+    }
+    if !models::looks_like_model_dir(&source) {
+        return Err(format!(
+            "{source_dir} does not look like a model folder (no config.json or weight files)"
+        )
+        .into());
+    }
+
+    let dest = compute_model_local_dir(&app, &repo_id)?;
+    // Held until the import returns, so a download can't start writing into
+    // the folder halfway through the copy.
+    let _lock = model_lock::acquire(&dest, &repo_id)?;
+    if fs::read_dir(&dest).is_ok_and(|mut it| it.next().is_some()) {
+        return Err(format!("{repo_id} is already installed at {}", dest.display()).into());
+    }
+
+    let handle = app.clone();
+    let (repo, src, target) = (repo_id.clone(), source.clone(), dest.clone());
+    tauri::async_runtime::spawn_blocking(move || {
+        let _ = fs::remove_dir(&target);
+        // A rename is instant on the same volume; fall back to copying otherwise.
+        if move_files.unwrap_or(false) && fs::rename(&src, &target).is_ok() {
+            return Ok(());
+        }
+        models::copy_model_files(&src, &target, |n, total, file| {
+            let _ = handle.emit(
+                "cerebro:model_import_progress",
+                serde_json::json!({
+                    "repo_id": repo,
+                    "n": n,
+                    "total": total,
+                    "file": file.to_string_lossy(),
+                }),
+            );
+        })?;
+        if move_files.unwrap_or(false) {
+            fs::remove_dir_all(&src)
+                .map_err(|e| format!("Copied, but failed to remove {}: {e}", src.display()))?;
+        }
+        Ok::<(), String>(())
+    })
+    .await
+    .map_err(|e| format!("Import task failed: {e}"))??;
+    app.state::<StorageSummaryCache>().invalidate();
+
+    if !models::looks_like_model_dir(&dest) {
+        return Err(format!(
+            "Import finished but {} has no config.json or weight files",
+            dest.display()
+        )
+        .into());
+    }
+
+    let provenance = serde_json::json!({
+        "repo_id": repo_id,
+        "imported_from": source_dir,
+        "imported_at": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        "moved": move_files.unwrap_or(false),
+    });
+    if let serde_json::Value::Object(fields) = provenance {
+        models::merge_meta(&dest, fields)?;
+    }
+
+    app.state::<ModelIndexState>()
+        .update_entry(&app, &repo_id, |entry| entry.imported_from = Some(source_dir))?;
+    let _ = app.emit(
+        "cerebro:model_import_done",
+        serde_json::json!({
+            "repo_id": repo_id,
+            "path": dest.to_string_lossy(),
+        }),
+    );
+    Ok(())
+}
+
+/// Copies the managed folder of `repo_id` to `dest_dir/<org>/<name>`, e.g. to
+/// share it or use it with another tool, and writes a `meta.json` describing
+/// it there unless the model ships a file of that name. Reports
+/// `cerebro:model_export_progress` and `cerebro:model_export_done`.
+#[tauri::command]
+async fn export_model(
+    app: tauri::AppHandle,
+    repo_id: String,
+    dest_dir: String,
+) -> Result<(), String> {
+    let source = require_local_model(&app, &repo_id)?;
+    let dest_root = PathBuf::from(&dest_dir);
+    if !dest_root.is_absolute() || !dest_root.is_dir() {
+        return Err(format!("Not an existing absolute directory: {dest_dir}"));
+    }
+    let dest_root = dest_root
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {dest_dir}: {e}"))?;
+    let store = models_dir(&app)?
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve models dir: {e}"))?;
+    if dest_root.starts_with(&store) {
+        return Err("Can't export into Cerebro's own model store".to_string());
+    }
+    let target = models::export_target(&dest_root, &repo_id)?;
+    if fs::read_dir(&target).is_ok_and(|mut it| it.next().is_some()) {
+        return Err(format!("{} already exists and is not empty", target.display()));
+    }
+
+    let entry = app.state::<ModelIndexState>().entry(&repo_id).unwrap_or_default();
+    let meta = serde_json::json!({
+        "repo_id": repo_id,
+        "exported_from": "cerebro",
+        "exported_at": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        "format": entry.format.map(|cached| cached.format),
+        "tags": entry.tags,
+    });
+
+    let handle = app.clone();
+    let (repo, dest) = (repo_id.clone(), target.clone());
+    tauri::async_runtime::spawn_blocking(move || {
+        models::copy_model_files(&source, &dest, |n, total, file| {
+            let _ = handle.emit(
+                "cerebro:model_export_progress",
+                serde_json::json!({
+                    "repo_id": repo,
+                    "n": n,
+                    "total": total,
+                    "file": file.to_string_lossy(),
+                }),
+            );
+        })?;
+        let meta_path = dest.join("meta.json");
+        if !meta_path.exists() {
+            let raw = serde_json::to_string_pretty(&meta)
+                .map_err(|e| format!("Serialize error: {e}"))?;
+            fs::write(&meta_path, raw).map_err(|e| format!("Failed to write meta.json: {e}"))?;
+        }
+        Ok::<(), String>(())
+    })
+    .await
+    .map_err(|e| format!("Export task failed: {e}"))??;
+
+    let _ = app.emit(
+        "cerebro:model_export_done",
+        serde_json::json!({
+            "repo_id": repo_id,
+            "path": target.to_string_lossy(),
+        }),
+    );
+    Ok(())
+}
+
+/// Stops a download. By default the partial files stay on disk so a later
+/// download of the same repo resumes; `discard` deletes them instead. Reported
+/// through `cerebro:model_download_cancelled` with the bytes kept.
+#[tauri::command]
+fn model_download_cancel(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    download_id: String,
+    discard: Option<bool>,
+) -> Result<(), String> {
+    cancel_download(&app, &state, download_id, discard.unwrap_or(false))
+}
+
+fn cancel_download(
+    app: &tauri::AppHandle,
+    state: &PythonRuntimeState,
+    download_id: String,
+    discard: bool,
+) -> Result<(), String> {
+    let msg = serde_json::json!({
+        "type": "download_cancel",
+        "download_id": download_id,
+        "discard": discard,
+    });
+
+    let mut inner = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+
+    // A paused or queued download has no runner thread to tell; just forget it.
+    // Its partial files (and the runner's state file from the pause) stay put.
+    if inner
+        .downloads
+        .get(&download_id)
+        .is_some_and(|d| d.paused || d.queued)
+    {
+        let download = inner.downloads.remove(&download_id);
+        inner.download_queue.retain(|id| id != &download_id);
+        inner.persist_downloads(app);
+        inner.emit_queue_positions(app);
+        drop(inner);
+
+        let local_dir = download.as_ref().map(|d| PathBuf::from(&d.local_dir));
+        let mut kept_bytes: u64 = local_dir
+            .as_deref()
+            .map(|dir| models::walk_files(dir).iter().map(|(_, len)| len).sum())
+            .unwrap_or(0);
+        if let (true, Some(dir)) = (discard, &local_dir) {
+            fs::remove_dir_all(dir)
+                .or_else(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(e),
+                })
+                .map_err(|e| format!("Failed to delete {}: {e}", dir.display()))?;
+            kept_bytes = 0;
+            app.state::<StorageSummaryCache>().invalidate();
+        }
+        let _ = app.emit(
+            "cerebro:model_download_cancelled",
+            serde_json::json!({
+                "type": "download_cancelled",
+                "download_id": download_id,
+                "repo_id": download.map(|d| d.repo_id),
+                "discarded": discard,
+                "kept_bytes": kept_bytes,
+            }),
+        );
+        return Ok(());
+    }
+
+    if inner.stdin.is_none() {
+        return Ok(());
+    }
+    inner.send_to_runner(&msg, "download_cancel")
+}
+
+#[derive(serde::Serialize)]
+struct OfflineReport {
+    aborted_requests: usize,
+    /// Downloads that were running or queued. Their files are kept for a resume.
+    cancelled_downloads: Vec<String>,
+}
+
+/// Switches network access off: in-flight HTTP requests are aborted, running
+/// and queued downloads are cancelled (keeping their files), and network
+/// commands fail with `Offline` until `go_online`.
+#[tauri::command]
+fn go_offline(
+    app: tauri::AppHandle,
+    state: State<PythonRuntimeState>,
+    network: State<NetworkState>,
+) -> Result<OfflineReport, CommandError> {
+    let aborted_requests = network.go_offline()?;
+
+    let mut active: Vec<(String, bool)> = state
+        .inner
+        .lock()
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .downloads
+        .iter()
+        .filter(|(_, d)| !d.paused)
+        .map(|(id, d)| (id.clone(), d.queued))
+        .collect();
+    // Queued ones go first so a cancelled download doesn't free a slot for them.
+    active.sort_by_key(|(_, queued)| !queued);
+    for (download_id, _) in &active {
+        if let Err(e) = cancel_download(&app, &state, download_id.clone(), false) {
+            eprintln!("Failed to cancel download {download_id}: {e}");
+        }
+    }
+
+    let _ = app.emit("cerebro:network_status", serde_json::json!({ "offline": true }));
+    Ok(OfflineReport {
+        aborted_requests,
+        cancelled_downloads: active.into_iter().map(|(id, _)| id).collect(),
+    })
 }
 
 #[tauri::command]
-fn python_runtime_stop(state: State<PythonRuntimeState>) -> Result<(), String> {
-    let mut inner = state
+fn go_online(app: tauri::AppHandle, network: State<NetworkState>) -> Result<(), CommandError> {
+    network.go_online()?;
+    let _ = app.emit("cerebro:network_status", serde_json::json!({ "offline": false }));
+    Ok(())
+}
+
+/// Hub metadata for `repo_id`, from the cache while fresh. Otherwise fetched,
+/// waiting out the `prefetch_model_info` rate limit instead of failing.
+async fn load_model_info(
+    app: &tauri::AppHandle,
+    repo_id: &str,
+) -> Result<serde_json::Value, CommandError> {
+    let cache = app.state::<ModelInfoCache>();
+    if let Some(info) = cache.get(repo_id) {
+        return Ok(info);
+    }
+    let settings = app.state::<SettingsState>().get();
+    loop {
+        match app
+            .state::<RateLimiterState>()
+            .acquire("prefetch_model_info", &settings)
+        {
+            Ok(()) => break,
+            Err(CommandError::RateLimited { retry_after_ms, .. }) => {
+                tokio::time::sleep(Duration::from_millis(retry_after_ms)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let client = app.state::<HttpClientState>().client.clone();
+    let info = app
+        .state::<NetworkState>()
+        .run(hub::fetch_model_info(client, settings, repo_id.to_string()))
+        .await?;
+    cache.insert(repo_id, info.clone());
+    Ok(info)
+}
+
+/// Model classes the Python runner can load, as reported in its capabilities.
+/// Kept from the last handshake, so this works while the runner is stopped.
+#[tauri::command]
+fn supported_architectures(state: State<PythonRuntimeState>) -> Result<Vec<String>, String> {
+    state
         .inner
         .lock()
-        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
+        .map_err(|_| "Python runtime mutex poisoned".to_string())?
+        .supported_architectures
+        .clone()
+        .ok_or_else(|| "The Python runner has not reported its capabilities yet".to_string())
+}
 
-    if let Some(mut stdin) = inner.stdin.take() {
-        let _ = stdin.write_all(b"{\"type\":\"shutdown\"}\n");
-        let _ = stdin.flush();
-    }
+/// Looks up `repo_id`'s architectures on the Hub in the background and emits
+/// `cerebro:unsupported_architecture` if the runner can load none of them. The
+/// download itself goes ahead; this is only a heads-up for the UI.
+fn warn_if_unsupported_architecture(app: &tauri::AppHandle, repo_id: &str) {
+    let supported = app
+        .state::<PythonRuntimeState>()
+        .inner
+        .lock()
+        .ok()
+        .and_then(|inner| inner.supported_architectures.clone())
+        .filter(|supported| !supported.is_empty());
+    let Some(supported) = supported else {
+        return;
+    };
+    let (app, repo_id) = (app.clone(), repo_id.to_string());
+    tauri::async_runtime::spawn(async move {
+        let Ok(info) = load_model_info(&app, &repo_id).await else {
+            return;
+        };
+        let architectures = hub::architectures(&info);
+        if architectures.is_empty() || architectures.iter().any(|a| supported.contains(a)) {
+            return;
+        }
+        let _ = app.emit(
+            "cerebro:unsupported_architecture",
+            serde_json::json!({
+                "repo_id": repo_id,
+                "architectures": architectures,
+            }),
+        );
+    });
+}
 
-    if let Some(mut child) = inner.child.take() {
-        let _ = child.kill();
-    }
-    Ok(())
+#[derive(serde::Serialize)]
+struct PrefetchStarted {
+    batch_id: String,
+    /// Distinct repos that will be reported.
+    repos: usize,
 }
 
+/// Warms the metadata cache for a gallery of repos in the background, at most
+/// `PREFETCH_CONCURRENCY` at a time, and returns right away. Each repo is
+/// reported through `cerebro:model_info_ready` with either `info` or `error`.
 #[tauri::command]
-fn chat_generate(
+fn prefetch_model_info(
     app: tauri::AppHandle,
-    state: State<PythonRuntimeState>,
-    payload: ChatGeneratePayload,
-) -> Result<ChatGenerateStarted, String> {
-    ensure_python_runtime(&app, &state)?;
-
-    // Always load from the previously downloaded local directory.
-    // The UI passes the model as a Hugging Face repo id; we map it to our
-    // app_data_dir/models/<sanitized_repo_id> location.
-    let model_local_dir = compute_model_local_dir(&app, &payload.model)?;
-    let model_local_dir_str = model_local_dir.to_string_lossy().to_string();
+    network: State<NetworkState>,
+    repo_ids: Vec<String>,
+) -> Result<PrefetchStarted, CommandError> {
+    network.ensure_online()?;
+    let mut seen = std::collections::HashSet::new();
+    let queue: VecDeque<String> = repo_ids
+        .iter()
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty() && seen.insert(r.clone()))
+        .collect();
+    let batch_id = generate_id();
+    let repos = queue.len();
 
-    let has_any_files = fs::read_dir(&model_local_dir)
-        .ok()
-        .and_then(|mut it| it.next())
-        .is_some();
-    if !has_any_files {
-        return Err(format!(
-            "Model is not available locally. Download it first. Expected dir: {}",
-            model_local_dir_str
-        ));
+    let queue = Arc::new(Mutex::new(queue));
+    for _ in 0..hub::PREFETCH_CONCURRENCY.min(repos) {
+        let (app, queue, batch_id) = (app.clone(), queue.clone(), batch_id.clone());
+        tauri::async_runtime::spawn(async move {
+            // Popped in a closure so the queue lock isn't held across the fetch.
+            let next = || queue.lock().ok().and_then(|mut q| q.pop_front());
+            while let Some(repo_id) = next() {
+                let (info, error) = match load_model_info(&app, &repo_id).await {
+                    Ok(info) => (Some(info), None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                let _ = app.emit(
+                    "cerebro:model_info_ready",
+                    serde_json::json!({
+                        "batch_id": batch_id,
+                        "repo_id": repo_id,
+                        "info": info,
+                        "error": error,
+                    }),
+                );
+            }
+        });
     }
 
-    let generation_id = generate_id();
-    let msg = serde_json::json!({
-        "type": "generate",
-        "generation_id": generation_id,
-        "model": model_local_dir_str,
-        "prompt": payload.prompt,
-        "max_new_tokens": payload.max_new_tokens.unwrap_or(256),
-        "temperature": payload.temperature.unwrap_or(0.2),
-    });
+    Ok(PrefetchStarted { batch_id, repos })
+}
 
-    let mut inner = state
-        .inner
-        .lock()
-        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
-    let Some(stdin) = inner.stdin.as_mut() else {
-        return Err("Python runtime is not running".to_string());
+/// Metadata `prefetch_model_info` already fetched, if it is still fresh.
+#[tauri::command]
+fn get_cached_model_info(
+    cache: State<ModelInfoCache>,
+    repo_id: String,
+) -> Option<serde_json::Value> {
+    cache.get(repo_id.trim())
+}
+
+/// A few MB LFS file served from the Hub's CDN, used as the speed-test payload.
+const SPEED_TEST_FILE: &str = "Qwen/Qwen2.5-1.5B-Instruct/resolve/main/tokenizer.json";
+const SPEED_TEST_MAX_BYTES: u64 = 8 * 1024 * 1024;
+const SPEED_TEST_MAX_TIME: Duration = Duration::from_secs(10);
+
+/// Maps connection-level failures to `NetworkUnavailable` so the UI can tell
+/// "you're offline" apart from an ordinary HTTP error.
+fn network_error(err: reqwest::Error) -> CommandError {
+    let detail = err.to_string();
+    let reason = if detail.to_ascii_lowercase().contains("proxy") {
+        "proxy"
+    } else if err.is_timeout() {
+        "timeout"
+    } else if err.is_connect() {
+        "offline"
+    } else {
+        return format!("Speed test failed: {detail}").into();
     };
+    CommandError::NetworkUnavailable { reason, detail }
+}
 
-    let line = serde_json::to_string(&msg).map_err(|e| format!("Serialize error: {e}"))?;
-    stdin
-        .write_all(line.as_bytes())
-        .and_then(|_| stdin.write_all(b"\n"))
-        .and_then(|_| stdin.flush())
-        .map_err(|e| format!("Failed to write to python runner: {e}"))?;
+/// Starts the local WebSocket server (or returns the running one) so external
+/// tools can stream generations. Bound to 127.0.0.1; clients need the token.
+#[tauri::command]
+fn start_ws_server(
+    app: tauri::AppHandle,
+    server: State<WsServerState>,
+) -> Result<WsServerInfo, String> {
+    server.start(&app)
+}
 
-    Ok(ChatGenerateStarted { generation_id })
+/// Returns whether a server was running.
+#[tauri::command]
+fn stop_ws_server(server: State<WsServerState>) -> Result<bool, String> {
+    server.stop()
 }
 
+/// Totals per model for generations finished since `since` (unix seconds), or
+/// over the whole log.
 #[tauri::command]
-fn chat_cancel(state: State<PythonRuntimeState>, generation_id: String) -> Result<(), String> {
+async fn get_usage_stats(app: tauri::AppHandle, since: Option<u64>) -> Result<UsageStats, String> {
+    tauri::async_runtime::spawn_blocking(move || usage::stats(&app, since))
+        .await
+        .map_err(|e| format!("Usage stats task failed: {e}"))?
+}
 
-    print!("Requesting cancel for generation_id={generation_id}\n");
+/// Time-to-first-token and tokens-per-second percentiles for `repo_id` over
+/// its recent generations.
+#[tauri::command]
+fn latency_stats(latency: State<LatencyState>, repo_id: String) -> LatencyStats {
+    latency.stats(&repo_id)
+}
 
-    let msg = serde_json::json!({
-        "type": "cancel",
-        "generation_id": generation_id,
-    });
+#[tauri::command]
+fn clear_usage_stats(app: tauri::AppHandle) -> Result<(), String> {
+    usage::clear(&app)
+}
 
-    let mut inner = state
-        .inner
-        .lock()
-        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
-    let Some(stdin) = inner.stdin.as_mut() else {
-        return Ok(());
-    };
+/// Frees space taken by caches, logs and (optionally) old conversations.
+/// Model files are never removed.
+#[tauri::command]
+async fn prune_storage(
+    app: tauri::AppHandle,
+    policy: Option<PrunePolicy>,
+) -> Result<PruneReport, String> {
+    let dirs = storage_dirs(&app)?;
+    let policy = policy.unwrap_or_default();
 
-    let line = serde_json::to_string(&msg).map_err(|e| format!("Serialize error: {e}"))?;
-    stdin
-        .write_all(line.as_bytes())
-        .and_then(|_| stdin.write_all(b"\n"))
-        .and_then(|_| stdin.flush())
-        .map_err(|e| format!("Failed to write cancel to python runner: {e}"))?;
-    Ok(())
+    let handle = app.clone();
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        storage::prune(&dirs, &policy, |category, done, total| {
+            let _ = handle.emit(
+                "cerebro:prune_progress",
+                serde_json::json!({ "category": category, "done": done, "total": total }),
+            );
+        })
+    })
+    .await
+    .map_err(|e| format!("Prune task failed: {e}"))?;
+    app.state::<StorageSummaryCache>().invalidate();
+    Ok(report)
+}
+
+fn storage_dirs(app: &tauri::AppHandle) -> Result<storage::StorageDirs, String> {
+    let path = app.path();
+    let data_dir = path
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+    Ok(storage::StorageDirs {
+        cache: path
+            .app_cache_dir()
+            .map_err(|e| format!("Failed to resolve app_cache_dir: {e}"))?,
+        logs: path
+            .app_log_dir()
+            .map_err(|e| format!("Failed to resolve app_log_dir: {e}"))?,
+        conversations: data_dir.join("conversations"),
+        models: data_dir.join("models"),
+    })
 }
 
+/// Disk usage of the model store (per model), caches and logs, plus the free
+/// space left, for a storage dashboard. The walk is cached until a download
+/// finishes or models are imported or removed; `free_bytes` is always fresh.
 #[tauri::command]
-fn model_download_start(
+async fn storage_summary(
     app: tauri::AppHandle,
-    state: State<PythonRuntimeState>,
-    payload: ModelDownloadPayload,
-) -> Result<ModelDownloadStarted, String> {
-    ensure_python_runtime(&app, &state)?;
+    cache: State<'_, StorageSummaryCache>,
+    index: State<'_, ModelIndexState>,
+) -> Result<StorageSummary, String> {
+    let epoch = match cache.get() {
+        Ok(mut summary) => {
+            summary.free_bytes = storage::free_bytes(&PathBuf::from(&summary.models_dir));
+            return Ok(summary);
+        }
+        Err(epoch) => epoch,
+    };
+    let dirs = storage_dirs(&app)?;
+    let installed = index.installed_models(&dirs.models);
+    let summary = tauri::async_runtime::spawn_blocking(move || storage::summarize(&dirs, installed))
+        .await
+        .map_err(|e| format!("Storage summary task failed: {e}"))?;
+    cache.insert(epoch, summary.clone());
+    Ok(summary)
+}
 
-    let download_id = generate_id();
-    let local_dir = compute_model_local_dir(&app, &payload.repo_id)?;
-    let local_dir_str = local_dir.to_string_lossy().to_string();
+/// Downloads part of a known file from the Hugging Face CDN and reports the
+/// throughput, so the UI can estimate download times. Cached for a minute.
+#[tauri::command]
+async fn measure_download_speed(
+    http: State<'_, HttpClientState>,
+    settings: State<'_, SettingsState>,
+    limiter: State<'_, RateLimiterState>,
+    network: State<'_, NetworkState>,
+) -> Result<DownloadSpeed, CommandError> {
+    network.ensure_online()?;
+    if let Ok(last) = http.last_speed.lock() {
+        if let Some((at, speed)) = last.as_ref() {
+            if at.elapsed() < http_client::SPEED_CACHE_TTL {
+                return Ok(speed.clone());
+            }
+        }
+    }
 
-    let msg = serde_json::json!({
-        "type": "download",
-        "download_id": download_id,
-        "repo_id": payload.repo_id,
-        "revision": payload.revision,
-        "local_dir": local_dir_str,
-        "token": payload.token,
-    });
+    let settings = settings.get();
+    limiter.acquire("measure_download_speed", &settings)?;
+    let endpoint = settings
+        .hf_endpoint
+        .as_deref()
+        .unwrap_or("https://huggingface.co")
+        .trim_end_matches('/');
+    let mut request = http
+        .client
+        .get(format!("{endpoint}/{SPEED_TEST_FILE}"))
+        .header("Accept-Encoding", "identity");
+    for (name, value) in &settings.hf_headers {
+        request = request.header(name, value);
+    }
 
-    let mut inner = state
-        .inner
-        .lock()
-        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
-    let Some(stdin) = inner.stdin.as_mut() else {
-        return Err("Python runtime is not running".to_string());
+    let speed = network
+        .run(async move {
+            let started = Instant::now();
+            let mut res = request.send().await.map_err(network_error)?;
+            if res.status() == reqwest::StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+                return Err(CommandError::NetworkUnavailable {
+                    reason: "proxy",
+                    detail: "Proxy authentication required".to_string(),
+                });
+            }
+            if !res.status().is_success() {
+                return Err(format!("Speed test failed: HTTP {}", res.status()).into());
+            }
+
+            let mut bytes = 0u64;
+            let mut latency = None;
+            while bytes < SPEED_TEST_MAX_BYTES && started.elapsed() < SPEED_TEST_MAX_TIME {
+                let Some(chunk) = res.chunk().await.map_err(network_error)? else {
+                    break;
+                };
+                latency.get_or_insert_with(|| started.elapsed());
+                bytes += chunk.len() as u64;
+            }
+
+            let latency = latency.unwrap_or_else(|| started.elapsed());
+            // Exclude time-to-first-byte so the figure reflects sustained throughput.
+            let transfer_secs = (started.elapsed() - latency).as_secs_f64().max(0.001);
+            Ok(DownloadSpeed {
+                bytes,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+                latency_ms: latency.as_millis() as u64,
+                megabytes_per_sec: bytes as f64 / 1_000_000.0 / transfer_secs,
+            })
+        })
+        .await?;
+    if let Ok(mut last) = http.last_speed.lock() {
+        *last = Some((Instant::now(), speed.clone()));
+    }
+    Ok(speed)
+}
+
+/// Digs a policy rejection out of a reqwest error (raised from the redirect policy).
+fn blocked_by_policy(err: &reqwest::Error) -> Option<CommandError> {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(CommandError::BlockedByPolicy { url, address }) = e.downcast_ref() {
+            return Some(CommandError::BlockedByPolicy {
+                url: url.clone(),
+                address: address.clone(),
+            });
+        }
+        source = e.source();
+    }
+    None
+}
+
+/// Methods `http_request` sends. Anything else is taken for a typo rather
+/// than passed on as a custom method.
+const HTTP_METHODS: &[reqwest::Method] = &[
+    reqwest::Method::GET,
+    reqwest::Method::POST,
+    reqwest::Method::PUT,
+    reqwest::Method::PATCH,
+    reqwest::Method::DELETE,
+    reqwest::Method::HEAD,
+    reqwest::Method::OPTIONS,
+];
+
+/// Trimmed and case-insensitive, so `" post"` works.
+fn parse_http_method(raw: &str) -> Result<reqwest::Method, String> {
+    let name = raw.trim().to_ascii_uppercase();
+    HTTP_METHODS
+        .iter()
+        .find(|m| m.as_str() == name)
+        .cloned()
+        .ok_or_else(|| format!("Unsupported HTTP method: {}", raw.trim()))
+}
+
+/// Timeouts come back as `NetworkUnavailable` so the UI can tell them apart.
+fn http_request_error(err: reqwest::Error, context: &str) -> CommandError {
+    if err.is_timeout() {
+        CommandError::NetworkUnavailable {
+            reason: "timeout",
+            detail: format!("{context}: request timed out"),
+        }
+    } else {
+        format!("{context}: {err}").into()
+    }
+}
+
+#[tauri::command]
+async fn http_request(
+    app: tauri::AppHandle,
+    http: State<'_, HttpClientState>,
+    settings: State<'_, SettingsState>,
+    limiter: State<'_, RateLimiterState>,
+    network: State<'_, NetworkState>,
+    request: HttpRequestPayload,
+) -> Result<HttpResponsePayload, CommandError> {
+    network.ensure_online()?;
+    let method = parse_http_method(&request.method)?;
+    let url = reqwest::Url::parse(&request.url).map_err(|e| format!("Invalid URL: {e}"))?;
+    if request.timeout_ms == Some(0) {
+        return Err("timeout_ms must be greater than 0".to_string().into());
+    }
+    let max_redirects = match (request.follow_redirects, request.max_redirects) {
+        (Some(false), _) => 0,
+        (_, Some(n)) if n > MAX_REDIRECTS_LIMIT => {
+            return Err(format!("max_redirects can be at most {MAX_REDIRECTS_LIMIT}").into());
+        }
+        (_, n) => n.unwrap_or(DEFAULT_MAX_REDIRECTS),
     };
+    let settings = settings.get();
+    limiter.acquire("http_request", &settings)?;
+    http_guard::check_url(&url, &settings)?;
 
-    let line = serde_json::to_string(&msg).map_err(|e| format!("Serialize error: {e}"))?;
-    stdin
-        .write_all(line.as_bytes())
-        .and_then(|_| stdin.write_all(b"\n"))
-        .and_then(|_| stdin.flush())
-        .map_err(|e| format!("Failed to write download to python runner: {e}"))?;
+    let timeout = request
+        .timeout_ms
+        .map_or(HTTP_REQUEST_TIMEOUT, Duration::from_millis);
+    let mut builder = http
+        .proxy_client(&app, max_redirects)?
+        .request(method.clone(), url)
+        .timeout(timeout);
 
-    Ok(ModelDownloadStarted {
-        download_id,
-        local_dir: local_dir_str,
-    })
+    if let Some(headers) = request.headers {
+        for (k, v) in headers {
+            if k.trim().is_empty() {
+                continue;
+            }
+            builder = builder.header(k, v);
+        }
+    }
+
+    // Avoid sending body on GET/HEAD.
+    if method != reqwest::Method::GET && method != reqwest::Method::HEAD {
+        if let Some(body) = request.body {
+            if !body.is_empty() {
+                builder = builder.body(body);
+            }
+        }
+    }
+
+    network
+        .run(async move {
+            let res = builder.send().await.map_err(|e| {
+                blocked_by_policy(&e).unwrap_or_else(|| http_request_error(e, "Request failed"))
+            })?;
+
+            let status = res.status();
+            let final_url = res.url().to_string();
+            let status_text = status
+                .canonical_reason()
+                .unwrap_or("")
+                .to_string();
+            let content_type = res
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            let mut headers: HashMap<String, String> = HashMap::new();
+            for (name, value) in res.headers() {
+                let Ok(value) = value.to_str() else {
+                    continue;
+                };
+                headers
+                    .entry(name.as_str().to_string())
+                    .and_modify(|joined| {
+                        joined.push_str(", ");
+                        joined.push_str(value);
+                    })
+                    .or_insert_with(|| value.to_string());
+            }
+            let (body_text, body_base64) = match request.response_as {
+                HttpResponseAs::Text => {
+                    let text = res
+                        .text()
+                        .await
+                        .map_err(|e| http_request_error(e, "Failed reading response body"))?;
+                    (text, None)
+                }
+                HttpResponseAs::Base64 => {
+                    let bytes = res
+                        .bytes()
+                        .await
+                        .map_err(|e| http_request_error(e, "Failed reading response body"))?;
+                    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+                    (String::new(), Some(encoded))
+                }
+            };
+
+            Ok(HttpResponsePayload {
+                status: status.as_u16(),
+                status_text,
+                final_url,
+                body_text,
+                body_base64,
+                content_type,
+                headers,
+            })
+        })
+        .await
 }
 
+/// Streams `url` to `dest_path` under the app's downloads folder in the
+/// background, reporting through `cerebro:http_download_progress` and ending
+/// with `cerebro:http_download_done`, `cerebro:http_download_error` or
+/// `cerebro:http_download_cancelled`. Returns the download id to pass to
+/// `http_download_cancel`.
 #[tauri::command]
-fn model_download_cancel(
-    state: State<PythonRuntimeState>,
+fn http_download(
+    app: tauri::AppHandle,
+    http: State<HttpClientState>,
+    settings: State<SettingsState>,
+    limiter: State<RateLimiterState>,
+    network: State<NetworkState>,
+    request: HttpDownloadPayload,
+) -> Result<String, CommandError> {
+    let HttpDownloadPayload {
+        url,
+        headers,
+        dest_path,
+        overwrite,
+    } = request;
+    network.ensure_online()?;
+    let url = reqwest::Url::parse(&url).map_err(|e| format!("Invalid URL: {e}"))?;
+    let settings = settings.get();
+    limiter.acquire("http_download", &settings)?;
+    http_guard::check_url(&url, &settings)?;
+
+    let dest = http_download::prepare_dest(&app, &dest_path, overwrite)?;
+
+    let mut builder = http.proxy_client(&app, DEFAULT_MAX_REDIRECTS)?.get(url);
+    for (k, v) in headers.unwrap_or_default() {
+        if !k.trim().is_empty() {
+            builder = builder.header(k, v);
+        }
+    }
+
+    let download_id = generate_id();
+    let cancel = app.state::<HttpDownloadState>().register(&download_id);
+    let id = download_id.clone();
+    tauri::async_runtime::spawn(async move {
+        let work = {
+            let (app, id, dest) = (app.clone(), id.clone(), dest.clone());
+            async move {
+                let res = builder.send().await.map_err(|e| {
+                    blocked_by_policy(&e)
+                        .unwrap_or_else(|| http_request_error(e, "Request failed"))
+                })?;
+                if !res.status().is_success() {
+                    return Err(format!("Download failed: HTTP {}", res.status()).into());
+                }
+                http_download::save_to_file(&app, &id, res, &dest, overwrite, &cancel).await
+            }
+        };
+        let result = app.state::<NetworkState>().run(work).await;
+        app.state::<HttpDownloadState>().finish(&id);
+
+        match result {
+            Ok(Some(bytes)) => {
+                let _ = app.emit(
+                    "cerebro:http_download_done",
+                    serde_json::json!({
+                        "download_id": id,
+                        "path": dest.to_string_lossy(),
+                        "bytes": bytes,
+                    }),
+                );
+            }
+            Ok(None) => {
+                let _ = app.emit(
+                    "cerebro:http_download_cancelled",
+                    serde_json::json!({ "download_id": id }),
+                );
+            }
+            Err(e) => {
+                // Going offline aborts the task mid-write, before it can clean up.
+                let _ = fs::remove_file(http_download::partial_path(&dest));
+                let _ = app.emit(
+                    "cerebro:http_download_error",
+                    serde_json::json!({ "download_id": id, "message": e.to_string() }),
+                );
+            }
+        }
+    });
+
+    Ok(download_id)
+}
+
+/// Stops an `http_download`; it confirms with `cerebro:http_download_cancelled`.
+#[tauri::command]
+fn http_download_cancel(
+    downloads: State<HttpDownloadState>,
     download_id: String,
 ) -> Result<(), String> {
-    let msg = serde_json::json!({
-        "type": "download_cancel",
-        "download_id": download_id,
+    if downloads.cancel(&download_id) {
+        Ok(())
+    } else {
+        Err(format!("No HTTP download with id {download_id}"))
+    }
+}
+
+fn toggle_main_window(app: &tauri::AppHandle) {
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+
+    let is_visible = window.is_visible().unwrap_or(false);
+    if is_visible {
+        let _ = window.hide();
+    } else {
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+    note_main_window_visible(app, !is_visible);
+}
+
+/// Starts a generation requested by a launch flag and shows the window. The
+/// outcome is reported as `cerebro:launch_generation` (with the usual `chat_*`
+/// events following) or `cerebro:launch_error`.
+fn start_launch_generation(app: &tauri::AppHandle, request: launch::LaunchGenerate) {
+    let app = app.clone();
+    // Off the calling thread: starting the runner can take a while.
+    std::thread::spawn(move || {
+        show_main_window_centered(&app);
+        if let Err(e) = run_launch_request(&app, request) {
+            emit_launch_error(&app, e.to_string());
+        }
     });
+}
 
-    let mut inner = state
-        .inner
-        .lock()
-        .map_err(|_| "Python runtime mutex poisoned".to_string())?;
-    let Some(stdin) = inner.stdin.as_mut() else {
-        return Ok(());
+fn run_launch_request(
+    app: &tauri::AppHandle,
+    request: launch::LaunchGenerate,
+) -> Result<ChatGenerateStarted, CommandError> {
+    let settings = app.state::<SettingsState>();
+    let model = request
+        .model
+        .unwrap_or_else(|| settings.get().default_model_repo_id);
+    let payload = ChatGeneratePayload {
+        max_new_tokens: request.max_new_tokens,
+        temperature: request.temperature,
+        seed: request.seed,
+        ..ChatGeneratePayload::new(model.clone(), request.prompt.clone())
     };
+    let runtime = app.state::<PythonRuntimeState>();
+    let started = generate_and_retain(app.clone(), &runtime, &settings, payload)?;
+    let _ = app.emit(
+        "cerebro:launch_generation",
+        serde_json::json!({
+            "generation_id": started.generation_id,
+            "seed": started.seed,
+            "model": model,
+            "prompt": request.prompt,
+        }),
+    );
+    Ok(started)
+}
 
-    let line = serde_json::to_string(&msg).map_err(|e| format!("Serialize error: {e}"))?;
-    stdin
-        .write_all(line.as_bytes())
-        .and_then(|_| stdin.write_all(b"\n"))
-        .and_then(|_| stdin.flush())
-        .map_err(|e| format!("Failed to write download_cancel to python runner: {e}"))?;
-    Ok(())
+/// Shows the window with a deep link's request prefilled, as
+/// `cerebro:launch_request`. Nothing runs until the UI calls
+/// `launch_request_confirm`, since any web page can open such a link.
+fn offer_launch_request(app: &tauri::AppHandle, request: launch::LaunchGenerate) {
+    show_main_window_centered(app);
+    let request_id = generate_id();
+    let model = request
+        .model
+        .clone()
+        .unwrap_or_else(|| app.state::<SettingsState>().get().default_model_repo_id);
+    let _ = app.emit(
+        "cerebro:launch_request",
+        serde_json::json!({
+            "request_id": request_id,
+            "model": model,
+            "prompt": request.prompt,
+            "max_new_tokens": request.max_new_tokens,
+            "temperature": request.temperature,
+            "seed": request.seed,
+        }),
+    );
+    app.state::<launch::PendingLaunchState>().insert(request_id, request);
+}
+
+/// Starts a deep link's request once the user has confirmed it.
+#[tauri::command]
+async fn launch_request_confirm(
+    app: tauri::AppHandle,
+    pending: State<'_, launch::PendingLaunchState>,
+    request_id: String,
+) -> Result<ChatGenerateStarted, CommandError> {
+    let Some(request) = pending.take(&request_id) else {
+        return Err(format!("Unknown launch request: {request_id}").into());
+    };
+    tauri::async_runtime::spawn_blocking(move || run_launch_request(&app, request))
+        .await
+        .map_err(|e| format!("Launch task failed: {e}"))?
 }
 
+/// Drops a deep link's request the user turned down.
 #[tauri::command]
-async fn http_request(request: HttpRequestPayload) -> Result<HttpResponsePayload, String> {
-    let method = reqwest::Method::from_bytes(request.method.as_bytes())
-        .map_err(|_| "Invalid HTTP method".to_string())?;
+fn launch_request_dismiss(pending: State<launch::PendingLaunchState>, request_id: String) {
+    pending.take(&request_id);
+}
 
-    let client = reqwest::Client::new();
-    let mut builder = client.request(method.clone(), request.url);
+fn emit_launch_error(app: &tauri::AppHandle, message: String) {
+    eprintln!("Launch request rejected: {message}");
+    let _ = app.emit("cerebro:launch_error", serde_json::json!({ "message": message }));
+}
 
-    if let Some(headers) = request.headers {
-        for (k, v) in headers {
-            if k.trim().is_empty() {
-                continue;
-            }
-            builder = builder.header(k, v);
-        }
+fn handle_launch_args(app: &tauri::AppHandle, args: &[String]) {
+    match launch::parse_args(args) {
+        Ok(Some(request)) => start_launch_generation(app, request),
+        Ok(None) => {}
+        Err(e) => emit_launch_error(app, e),
     }
+}
 
-    // Avoid sending body on GET/HEAD.
-    if method != reqwest::Method::GET && method != reqwest::Method::HEAD {
-        if let Some(body) = request.body {
-            if !body.is_empty() {
-                builder = builder.body(body);
-            }
+fn handle_deep_links(app: &tauri::AppHandle, urls: &[reqwest::Url]) {
+    for url in urls {
+        match launch::parse_deep_link(url) {
+            Ok(Some(request)) => offer_launch_request(app, request),
+            Ok(None) => {}
+            Err(e) => emit_launch_error(app, e),
         }
     }
-
-    let res = builder
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {e}"))?;
-
-    let status = res.status();
-    let status_text = status
-        .canonical_reason()
-        .unwrap_or("")
-        .to_string();
-    let body_text = res
-        .text()
-        .await
-        .map_err(|e| format!("Failed reading response body: {e}"))?;
-
-    Ok(HttpResponsePayload {
-        status: status.as_u16(),
-        status_text,
-        body_text,
-    })
 }
 
-fn toggle_main_window(app: &tauri::AppHandle) {
+/// Shows the main window in the middle of the screen, e.g. when a second launch
+/// hands over to this instance.
+fn show_main_window_centered(app: &tauri::AppHandle) {
     let Some(window) = app.get_webview_window("main") else {
         return;
     };
+    let _ = window.center();
+    let _ = window.show();
+    let _ = window.set_focus();
+    note_main_window_visible(app, true);
+}
 
-    let is_visible = window.is_visible().unwrap_or(false);
-    if is_visible {
-        let _ = window.hide();
-    } else {
-        let _ = window.show();
-        let _ = window.set_focus();
-    }
+/// Gap kept between the popover and the tray / screen edges, in physical pixels.
+const EDGE_GAP_PX: f64 = 20.0;
+
+/// Where the popover was last anchored, so a resize can re-anchor it.
+#[derive(Default)]
+struct DropdownState {
+    anchor: Mutex<Option<(tauri::Rect, tauri::PhysicalPosition<f64>)>>,
+    /// Bumped by every `set_window_content_height`; only the latest call applies.
+    resize_seq: Mutex<u64>,
 }
 
 fn show_dropdown_at(
@@ -509,54 +6001,113 @@ fn show_dropdown_at(
     let Some(window) = app.get_webview_window("main") else {
         return;
     };
+    // A remembered position wins; the tray is the fallback until there is one.
+    let remembered = app.state::<SettingsState>().get().window_placement
+        == WindowPlacement::Remember
+        && window_geometry::restore(app, &window);
+    if let Ok(mut anchor) = app.state::<DropdownState>().anchor.lock() {
+        *anchor = (!remembered).then_some((rect, click_pos));
+    }
+    if !remembered {
+        position_dropdown(&window, rect, click_pos);
+    }
+    let _ = window.show();
+    let _ = window.set_focus();
+    note_main_window_visible(app, true);
+}
+
+/// The monitor containing `point` (physical pixels), which is where the tray
+/// was clicked. `current_monitor` is wherever the window was last shown, so it
+/// can be a different screen. Falls back to the primary monitor.
+fn monitor_at(
+    window: &tauri::WebviewWindow,
+    point: tauri::PhysicalPosition<f64>,
+) -> Option<tauri::Monitor> {
+    let containing = window
+        .available_monitors()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|monitor| {
+            let (pos, size) = (monitor.position(), monitor.size());
+            let (left, top) = (pos.x as f64, pos.y as f64);
+            (left..left + size.width as f64).contains(&point.x)
+                && (top..top + size.height as f64).contains(&point.y)
+        });
+    containing.or_else(|| window.primary_monitor().ok().flatten())
+}
+
+/// A rectangle in the coordinate space the tray reported the click in.
+#[derive(Clone, Copy)]
+struct Bounds {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+/// Moves a `width` x `height` window at (`x`, `y`) so it sits inside `area`, at
+/// least `gap` from every edge. A window too big to fit keeps its top-left
+/// corner on screen, so the menu bar never covers its title.
+fn clamp_to_work_area(
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    area: Bounds,
+    gap: f64,
+) -> (f64, f64) {
+    let x = x.min(area.x + area.width - gap - width).max(area.x + gap);
+    let y = y.min(area.y + area.height - gap - height).max(area.y + gap);
+    (x, y)
+}
 
-    let window_size = window.outer_size().ok();
-    let monitor = window.current_monitor().ok().flatten();
+/// Places the popover next to the tray icon at `rect`, flipping above it or to
+/// its left depending on which quarter of the clicked monitor it is in, then
+/// keeps it inside that monitor's work area.
+fn position_dropdown(
+    window: &tauri::WebviewWindow,
+    rect: tauri::Rect,
+    click_pos: tauri::PhysicalPosition<f64>,
+) {
+    let monitor = monitor_at(window, click_pos);
+    let scale_factor = monitor.as_ref().map_or(1.0, |monitor| monitor.scale_factor());
 
-    const EDGE_GAP_PX: f64 = 20.0;
+    // Work in the rect's space; monitors and window sizes come in physical pixels.
+    let logical = matches!(rect.position, tauri::Position::Logical(_));
+    let to_rect_space = |px: f64| if logical { px / scale_factor } else { px };
 
     let (rect_x, rect_y) = match rect.position {
         tauri::Position::Physical(p) => (p.x as f64, p.y as f64),
         tauri::Position::Logical(p) => (p.x, p.y),
     };
     let (rect_w, rect_h) = match rect.size {
-        tauri::Size::Physical(s) => (s.width as f64, s.height as f64),
+        tauri::Size::Physical(s) => (to_rect_space(s.width as f64), to_rect_space(s.height as f64)),
         tauri::Size::Logical(s) => (s.width, s.height),
     };
+    let window_size = window
+        .outer_size()
+        .ok()
+        .map(|s| (to_rect_space(s.width as f64), to_rect_space(s.height as f64)));
+    let gap = to_rect_space(EDGE_GAP_PX);
 
     // Default anchor point: below the tray icon.
     let mut x = rect_x;
     let mut y = rect_y + rect_h;
+    let mut show_above = false;
 
-    let (show_above, scale_factor) = if let Some(monitor) = &monitor {
-        let monitor_size = monitor.size();
-        (
-            click_pos.y > (monitor_size.height as f64 / 2.0),
-            monitor.scale_factor(),
-        )
-    } else {
-        (false, 1.0)
-    };
-
-    // Convert the desired pixel gap to the coordinate space we'll use.
-    let gap = match rect.position {
-        tauri::Position::Physical(_) => EDGE_GAP_PX,
-        tauri::Position::Logical(_) => EDGE_GAP_PX / scale_factor,
-    };
-
-    if let (Some(window_size), Some(monitor)) = (window_size, monitor) {
-        let monitor_size = monitor.size();
-        let window_w = window_size.width as f64;
-        let window_h = window_size.height as f64;
-
+    if let Some(monitor) = &monitor {
+        // `click_pos` is physical, so compare it against the monitor as-is.
+        let (position, size) = (monitor.position(), monitor.size());
         // If click is in lower half of the screen, show above (Windows taskbar scenario).
-        if show_above {
-            y = rect_y - window_h;
-        }
-
-        // If click is in right half, align right edge of window with tray rect.
-        if click_pos.x > (monitor_size.width as f64 / 2.0) {
-            x = (rect_x + rect_w - (window_w / 2.0) - 10.0).max(0.0);
+        show_above = click_pos.y - position.y as f64 > size.height as f64 / 2.0;
+        if let Some((window_w, window_h)) = window_size {
+            if show_above {
+                y = rect_y - window_h;
+            }
+            // If click is in right half, align right edge of window with tray rect.
+            if click_pos.x - position.x as f64 > size.width as f64 / 2.0 {
+                x = rect_x + rect_w - (window_w / 2.0) - to_rect_space(10.0);
+            }
         }
     }
 
@@ -567,27 +6118,272 @@ fn show_dropdown_at(
         y += gap;
     }
 
-    let _ = match rect.position {
-        tauri::Position::Physical(_) => {
-            window.set_position(tauri::Position::Physical(tauri::PhysicalPosition {
-                x: x.round() as i32,
-                y: y.round() as i32,
-            }))
-        }
-        tauri::Position::Logical(_) => {
-            window.set_position(tauri::Position::Logical(tauri::LogicalPosition { x, y }))
+    // The work area excludes the macOS menu bar (and the notch region inside it)
+    // and the Windows taskbar.
+    if let Some(monitor) = &monitor {
+        let work_area = monitor.work_area();
+        let area = Bounds {
+            x: to_rect_space(work_area.position.x as f64),
+            y: to_rect_space(work_area.position.y as f64),
+            width: to_rect_space(work_area.size.width as f64),
+            height: to_rect_space(work_area.size.height as f64),
+        };
+        let (window_w, window_h) = window_size.unwrap_or_default();
+        (x, y) = clamp_to_work_area(x, y, window_w, window_h, area, gap);
+    }
+
+    let _ = if logical {
+        window.set_position(tauri::Position::Logical(tauri::LogicalPosition { x, y }))
+    } else {
+        window.set_position(tauri::Position::Physical(tauri::PhysicalPosition {
+            x: x.round() as i32,
+            y: y.round() as i32,
+        }))
+    };
+}
+
+/// Rapid `set_window_content_height` calls within this window collapse into one.
+const CONTENT_RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+/// Smallest content height the popover shrinks to, in logical pixels.
+const MIN_CONTENT_HEIGHT: f64 = 160.0;
+
+/// Resizes the popover to fit `height` logical pixels of content, as measured
+/// by the frontend. The height is clamped to the monitor's work area and the
+/// popover is re-anchored to the tray. Rapid calls are debounced.
+#[tauri::command]
+fn set_window_content_height(
+    app: tauri::AppHandle,
+    dropdown: State<DropdownState>,
+    height: f64,
+) -> Result<(), String> {
+    if !height.is_finite() || height <= 0.0 {
+        return Err("height must be a positive number".to_string());
+    }
+    let seq = {
+        let mut seq = dropdown
+            .resize_seq
+            .lock()
+            .map_err(|_| "Dropdown state mutex poisoned".to_string())?;
+        *seq += 1;
+        *seq
+    };
+    std::thread::spawn(move || {
+        std::thread::sleep(CONTENT_RESIZE_DEBOUNCE);
+        let dropdown = app.state::<DropdownState>();
+        if dropdown.resize_seq.lock().is_ok_and(|latest| *latest == seq) {
+            fit_window_to_content(&app, &dropdown, height);
         }
+    });
+    Ok(())
+}
+
+fn fit_window_to_content(app: &tauri::AppHandle, dropdown: &DropdownState, height: f64) {
+    let Some(window) = app.get_webview_window("main") else {
+        return;
     };
-    let _ = window.show();
-    let _ = window.set_focus();
+    let scale = window.scale_factor().unwrap_or(1.0);
+    let (Ok(inner), Ok(outer)) = (window.inner_size(), window.outer_size()) else {
+        return;
+    };
+    let width = inner.width as f64 / scale;
+
+    let anchor = dropdown.anchor.lock().ok().and_then(|anchor| *anchor);
+    let monitor = match anchor {
+        Some((_, click_pos)) => monitor_at(&window, click_pos),
+        None => window.current_monitor().ok().flatten(),
+    };
+
+    let mut height = height.max(MIN_CONTENT_HEIGHT);
+    if let Some(monitor) = monitor {
+        // Leave room for the window chrome and the edge gap on both sides.
+        let work_height = monitor.work_area().size.height as f64;
+        let chrome = outer.height.saturating_sub(inner.height) as f64;
+        let available = (work_height - chrome - 2.0 * EDGE_GAP_PX) / scale;
+        height = height.min(available.max(MIN_CONTENT_HEIGHT));
+    }
+    let _ = window.set_size(tauri::Size::Logical(tauri::LogicalSize { width, height }));
+
+    if let Some((rect, click_pos)) = anchor {
+        position_dropdown(&window, rect, click_pos);
+    }
+}
+
+/// The tray icon, kept so its artwork can be swapped after startup.
+struct TrayHandle(TrayIcon);
+
+const TRAY_ICON_COLOR: &[u8] = include_bytes!("../icons/128x128.png");
+/// Black glyph on transparency; doubles as the macOS template image.
+const TRAY_ICON_LIGHT: &[u8] = include_bytes!("../icons/tray-template.png");
+const TRAY_ICON_DARK: &[u8] = include_bytes!("../icons/tray-dark.png");
+
+/// The theme to use when none is configured: a template image on macOS,
+/// otherwise whatever contrasts with the system appearance.
+fn system_tray_theme(app: &tauri::AppHandle) -> TrayIconTheme {
+    if cfg!(target_os = "macos") {
+        return TrayIconTheme::Template;
+    }
+    let theme = app
+        .get_webview_window("main")
+        .and_then(|window| window.theme().ok());
+    match theme {
+        Some(tauri::Theme::Dark) => TrayIconTheme::Dark,
+        _ => TrayIconTheme::Light,
+    }
+}
+
+fn tray_icon_image(theme: TrayIconTheme) -> tauri::Result<tauri::image::Image<'static>> {
+    let bytes = match theme {
+        TrayIconTheme::Light | TrayIconTheme::Template => TRAY_ICON_LIGHT,
+        TrayIconTheme::Dark => TRAY_ICON_DARK,
+        TrayIconTheme::Color => TRAY_ICON_COLOR,
+    };
+    tauri::image::Image::from_bytes(bytes)
+}
+
+fn apply_tray_theme(tray: &TrayIcon, theme: TrayIconTheme) -> tauri::Result<()> {
+    tray.set_icon(Some(tray_icon_image(theme)?))?;
+    tray.set_icon_as_template(theme == TrayIconTheme::Template)
+}
+
+/// Ids the tray menu always has; custom items can't reuse them.
+const TRAY_BUILTIN_IDS: [&str; 2] = ["show_hide", "quit"];
+
+/// A custom tray menu entry. Clicks are reported as `cerebro:tray_menu_clicked`.
+#[derive(serde::Deserialize)]
+struct TrayMenuItem {
+    id: String,
+    label: String,
+    enabled: Option<bool>,
+}
+
+/// `custom` first, then the built-in Show/Hide and Quit.
+fn build_tray_menu<M: Manager<tauri::Wry>>(
+    manager: &M,
+    custom: &[TrayMenuItem],
+) -> tauri::Result<Menu<tauri::Wry>> {
+    let menu = Menu::new(manager)?;
+    for item in custom {
+        let enabled = item.enabled.unwrap_or(true);
+        menu.append(&MenuItem::with_id(
+            manager,
+            item.id.as_str(),
+            &item.label,
+            enabled,
+            None::<&str>,
+        )?)?;
+    }
+    if !custom.is_empty() {
+        menu.append(&PredefinedMenuItem::separator(manager)?)?;
+    }
+    menu.append(&MenuItem::with_id(manager, "show_hide", "Show/Hide", true, None::<&str>)?)?;
+    menu.append(&MenuItem::with_id(manager, "quit", "Quit", true, None::<&str>)?)?;
+    Ok(menu)
+}
+
+fn handle_tray_menu_event(app: &tauri::AppHandle, event: tauri::menu::MenuEvent) {
+    match event.id().as_ref() {
+        "show_hide" => toggle_main_window(app),
+        "quit" => app.exit(0),
+        id => {
+            let _ = app.emit("cerebro:tray_menu_clicked", serde_json::json!({ "id": id }));
+        }
+    }
+}
+
+/// Replaces the custom part of the tray menu, e.g. with recently used models.
+/// An empty list leaves only the built-in items.
+#[tauri::command]
+fn set_tray_menu(
+    app: tauri::AppHandle,
+    tray: State<TrayHandle>,
+    items: Vec<TrayMenuItem>,
+) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for item in &items {
+        if item.id.is_empty() || TRAY_BUILTIN_IDS.contains(&item.id.as_str()) {
+            return Err(format!("Invalid tray menu item id: {:?}", item.id));
+        }
+        if !seen.insert(item.id.as_str()) {
+            return Err(format!("Duplicate tray menu item id: {}", item.id));
+        }
+    }
+    let menu =
+        build_tray_menu(&app, &items).map_err(|e| format!("Failed to build tray menu: {e}"))?;
+    tray.0
+        .set_menu(Some(menu))
+        .map_err(|e| format!("Failed to set tray menu: {e}"))
+}
+
+/// Swaps the tray icon artwork. `None` goes back to following the system.
+#[tauri::command]
+fn set_tray_icon_theme(
+    app: tauri::AppHandle,
+    settings: State<SettingsState>,
+    tray: State<TrayHandle>,
+    theme: Option<TrayIconTheme>,
+) -> Result<TrayIconTheme, String> {
+    settings.update(&app, |s| s.tray_icon_theme = theme)?;
+    let effective = theme.unwrap_or_else(|| system_tray_theme(&app));
+    apply_tray_theme(&tray.0, effective).map_err(|e| format!("Failed to set tray icon: {e}"))?;
+    Ok(effective)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tauri::Builder::default()
+    let mut builder = tauri::Builder::default();
+    // Must be the first plugin: a second launch exits here after handing its
+    // arguments to the running instance instead of starting another runner.
+    #[cfg(desktop)]
+    {
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            show_main_window_centered(app);
+            // Deep links in `args` are delivered separately through `on_open_url`.
+            handle_launch_args(app, &args);
+            let _ = app.emit(
+                "cerebro:second_instance",
+                serde_json::json!({
+                    // The first entry is the executable path.
+                    "args": args.iter().skip(1).collect::<Vec<_>>(),
+                    "cwd": cwd,
+                }),
+            );
+        }));
+    }
+
+    builder
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_opener::init())
         .manage(PythonRuntimeState::default())
+        .manage(HttpClientState::default())
+        .manage(HttpDownloadState::default())
+        .manage(NetworkState::default())
+        .manage(ModelInfoCache::default())
+        .manage(DropdownState::default())
+        .manage(StorageSummaryCache::default())
+        .manage(LatencyState::default())
+        .manage(WsServerState::default())
+        .manage(RateLimiterState::default())
+        .manage(launch::PendingLaunchState::default())
         .setup(|app| {
+            app.manage(SettingsState::load(app.handle()));
+            app.manage(ModelIndexState::load(app.handle()));
+            if let Ok(mut inner) = app.state::<PythonRuntimeState>().inner.lock() {
+                inner.downloads = downloads::load_persisted(app.handle());
+            }
+            spawn_progress_interpolator(app.handle().clone());
+            #[cfg(desktop)]
+            {
+                app.handle().plugin(global_shortcut::plugin())?;
+                let shortcut = app.state::<SettingsState>().get().global_shortcut;
+                if let Err(e) = global_shortcut::rebind(app.handle(), None, shortcut.as_deref()) {
+                    eprintln!("{e}");
+                }
+            }
+            if app.state::<SettingsState>().get().persist_latency_stats {
+                let handle = app.handle().clone();
+                std::thread::spawn(move || load_latency_samples(&handle));
+            }
+
             #[cfg(target_os = "macos")]
             {
                 let _ = app.set_activation_policy(tauri::ActivationPolicy::Accessory);
@@ -605,28 +6401,30 @@ pub fn run() {
                     );
                 }
                 let _ = window.set_skip_taskbar(true);
+                if app.state::<SettingsState>().get().window_placement
+                    == WindowPlacement::Remember
+                {
+                    window_geometry::restore(app.handle(), &window);
+                }
                 let _ = window.hide();
+                note_main_window_visible(app.handle(), false);
             }
 
-            let show_hide =
-                MenuItem::with_id(app, "show_hide", "Show/Hide", true, None::<&str>)?;
-            let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
-            let menu = Menu::with_items(app, &[&show_hide, &quit])?;
+            let menu = build_tray_menu(app, &[])?;
 
-            let tray_image = tauri::image::Image::from_bytes(include_bytes!("../icons/128x128.png"))?;
+            let tray_theme = app
+                .state::<SettingsState>()
+                .get()
+                .tray_icon_theme
+                .unwrap_or_else(|| system_tray_theme(app.handle()));
 
-            TrayIconBuilder::new()
+            let tray = TrayIconBuilder::new()
                 .menu(&menu)
-                .icon(tray_image)
+                .icon(tray_icon_image(tray_theme)?)
+                .icon_as_template(tray_theme == TrayIconTheme::Template)
                 // Queremos clique-esquerdo abrir o dropdown (não o menu).
                 .show_menu_on_left_click(false)
-                .on_menu_event(
-                    |app, event: tauri::menu::MenuEvent| match event.id().as_ref() {
-                        "show_hide" => toggle_main_window(app),
-                        "quit" => app.exit(0),
-                        _ => {}
-                    },
-                )
+                .on_menu_event(handle_tray_menu_event)
                 .on_tray_icon_event(|tray: &TrayIcon, event: TrayIconEvent| {
                     if let TrayIconEvent::Click {
                         button: MouseButton::Left,
@@ -646,6 +6444,7 @@ pub fn run() {
 
                         if is_visible {
                             let _ = window.hide();
+                            note_main_window_visible(tray.app_handle(), false);
                             return;
                         }
 
@@ -653,31 +6452,609 @@ pub fn run() {
                     }
                 })
                 .build(app)?;
+            app.manage(TrayHandle(tray));
+
+            // Installed bundles register the scheme; dev builds on Linux/Windows need this.
+            #[cfg(all(debug_assertions, any(windows, target_os = "linux")))]
+            {
+                let _ = app.deep_link().register_all();
+            }
+            let handle = app.handle().clone();
+            app.deep_link()
+                .on_open_url(move |event| handle_deep_links(&handle, &event.urls()));
+            if let Ok(Some(urls)) = app.deep_link().get_current() {
+                handle_deep_links(app.handle(), &urls);
+            }
+            handle_launch_args(app.handle(), &std::env::args().collect::<Vec<_>>());
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             greet,
+            get_settings,
+            set_generation_limits,
+            set_hf_endpoint,
+            set_hf_headers,
+            get_hf_header_names,
+            set_hf_token,
+            clear_hf_token,
+            set_http_allowlist,
+            set_http_guard,
+            set_runner_env,
+            set_runner_cwd,
+            set_runner_script_path,
+            python_runtime_set_interpreter,
+            python_runtime_status,
+            set_auto_restart_runner,
+            set_resource_limits,
+            set_hide_during_generation,
+            set_max_concurrent_downloads,
+            set_emit_when_hidden,
+            set_chunk_mode,
+            set_close_behavior,
+            set_window_placement,
+            set_global_shortcut,
+            set_window_content_height,
+            count_tokens,
+            model_context_length,
+            set_enforce_context,
+            set_unload_on_oom,
+            set_allow_empty_prompt,
+            set_download_progress_interpolation,
+            quit_app,
+            set_rate_limit,
+            set_tray_icon_theme,
+            set_tray_menu,
             http_request,
+            http_download,
+            http_download_cancel,
+            measure_download_speed,
+            go_offline,
+            go_online,
+            prefetch_model_info,
+            get_cached_model_info,
+            prune_storage,
+            get_usage_stats,
+            latency_stats,
+            set_persist_latency_stats,
+            clear_usage_stats,
+            start_ws_server,
+            stop_ws_server,
             python_runtime_start,
+            check_python,
+            runtime_versions,
             python_runtime_stop,
+            runtime_drain,
             chat_generate,
             chat_cancel,
+            chat_cancel_latest,
+            chat_regenerate,
+            chat_replace,
+            supported_architectures,
+            storage_summary,
+            chat_compare,
+            set_mock_mode,
+            send_raw_runner_message,
+            get_raw_runner_log,
+            get_loaded_model,
+            resync_state,
+            switch_model,
+            validate_model_loads,
             model_download_start,
-            model_download_cancel
+            model_download_resume,
+            setup_default_model,
+            model_download_cancel,
+            pause_download,
+            resume_download,
+            list_active_downloads,
+            chat_list_active,
+            get_download_log,
+            detect_model_format,
+            estimate_model_memory,
+            get_chat_template,
+            read_model_file,
+            list_models,
+            model_list_downloaded,
+            model_delete,
+            list_model_revisions,
+            set_model_tags,
+            get_model_tags,
+            set_model_favorite,
+            import_model,
+            export_model,
+            launch_request_confirm,
+            launch_request_dismiss,
         ])
         .on_window_event(|window, event| {
+            if let tauri::WindowEvent::ThemeChanged(_) = event {
+                let app = window.app_handle();
+                if app.state::<SettingsState>().get().tray_icon_theme.is_none() {
+                    if let Some(tray) = app.try_state::<TrayHandle>() {
+                        let _ = apply_tray_theme(&tray.0, system_tray_theme(app));
+                    }
+                }
+            }
+
             if let tauri::WindowEvent::CloseRequested { api, .. } = event {
                 api.prevent_close();
-                let _ = window.hide();
+                let app = window.app_handle();
+                remember_window_geometry(app);
+                match app.state::<SettingsState>().get().close_behavior {
+                    CloseBehavior::Hide => {
+                        let _ = window.hide();
+                        note_main_window_visible(app, false);
+                    }
+                    CloseBehavior::Quit => {
+                        let _ = window.hide();
+                        quit_after_drain(app);
+                    }
+                    CloseBehavior::Ask => {
+                        let _ = app.emit("cerebro:close_requested", serde_json::json!({}));
+                    }
+                }
             }
 
             // Comportamento de "dropdown": clicou fora/perdeu foco, esconde.
             //Comentar para debugging
             if let tauri::WindowEvent::Focused(false) = event {
-                let _ = window.hide();
+                remember_window_geometry(window.app_handle());
+                if !suppress_hide(window.app_handle()) {
+                    let _ = window.hide();
+                    note_main_window_visible(window.app_handle(), false);
+                }
             }
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_id_is_unique() {
+        let ids: HashSet<String> = (0..10_000).map(|_| generate_id()).collect();
+        assert_eq!(ids.len(), 10_000);
+    }
+
+    /// Feeds terminal runner messages for one generation through the reader's
+    /// first-wins logic, returning the events that would be emitted.
+    fn terminal_events(
+        mut generation: Option<ActiveGeneration>,
+        messages: &[(&str, Option<&str>)],
+    ) -> Vec<TerminalEvent> {
+        messages
+            .iter()
+            .filter_map(|(msg_type, reason)| {
+                terminal_event(msg_type, *reason, generation.take().as_ref())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stop_sequence_beats_a_max_duration_stop_it_raced() {
+        let mut g = ActiveGeneration::new();
+        // The watchdog fires and asks the runner to cancel, but the runner had
+        // already matched a stop sequence; its late cancel ack is dropped.
+        assert!(g.claim_stop("max_duration"));
+        let messages = [("done", Some("stop_sequence")), ("cancelled", None)];
+        let events = terminal_events(Some(g), &messages);
+        assert_eq!(events, [TerminalEvent::Done(Some("stop_sequence".into()))]);
+    }
+
+    #[test]
+    fn max_duration_is_reported_as_done_when_the_cancel_lands_first() {
+        let mut g = ActiveGeneration::new();
+        assert!(g.claim_stop("max_duration"));
+        let messages = [("cancelled", None), ("done", Some("stop_sequence"))];
+        let events = terminal_events(Some(g), &messages);
+        assert_eq!(events, [TerminalEvent::Done(Some("max_duration".into()))]);
+    }
+
+    #[test]
+    fn a_user_cancel_keeps_later_stops_from_claiming() {
+        let mut g = ActiveGeneration::new();
+        g.cancel_requested_at = Some(Instant::now());
+        assert!(!g.claim_stop("max_duration"));
+        let events = terminal_events(Some(g), &[("cancelled", None), ("error", None)]);
+        assert_eq!(events, [TerminalEvent::Cancelled]);
+    }
+
+    fn limits(ceiling: u32, clamp: bool) -> Settings {
+        Settings {
+            max_new_tokens_ceiling: ceiling,
+            clamp_generation_params: clamp,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn max_new_tokens_boundaries_are_rejected_by_default() {
+        let settings = limits(1024, false);
+        assert!(normalize_generation_params(None, Some(0), &settings).is_err());
+        assert_eq!(normalize_generation_params(None, Some(1), &settings).unwrap().1, 1);
+        assert_eq!(normalize_generation_params(None, Some(1024), &settings).unwrap().1, 1024);
+        let err = normalize_generation_params(None, Some(1025), &settings).unwrap_err();
+        assert!(err.contains("at most 1024"), "{err}");
+    }
+
+    #[test]
+    fn max_new_tokens_above_the_ceiling_is_clamped_when_enabled() {
+        let settings = limits(1024, true);
+        assert_eq!(normalize_generation_params(None, Some(1025), &settings).unwrap().1, 1024);
+        // Zero is never meaningful, so it stays an error.
+        assert!(normalize_generation_params(None, Some(0), &settings).is_err());
+    }
+
+    #[test]
+    fn temperature_boundaries_are_rejected_by_default() {
+        let settings = limits(1024, false);
+        assert_eq!(normalize_generation_params(Some(0.0), None, &settings).unwrap().0, 0.0);
+        assert_eq!(normalize_generation_params(Some(2.0), None, &settings).unwrap().0, 2.0);
+        assert!(normalize_generation_params(Some(-0.1), None, &settings).is_err());
+        assert!(normalize_generation_params(Some(2.1), None, &settings).is_err());
+        assert!(normalize_generation_params(Some(f32::NAN), None, &settings).is_err());
+    }
+
+    #[test]
+    fn temperature_is_clamped_when_enabled() {
+        let settings = limits(1024, true);
+        assert_eq!(normalize_generation_params(Some(-5.0), None, &settings).unwrap().0, 0.0);
+        assert_eq!(normalize_generation_params(Some(9.0), None, &settings).unwrap().0, 2.0);
+        // Not a number can't be clamped to anything sensible.
+        assert!(normalize_generation_params(Some(f32::NAN), None, &settings).is_err());
+        assert!(normalize_generation_params(Some(f32::INFINITY), None, &settings).is_err());
+    }
+
+    #[test]
+    fn unset_params_get_defaults_within_the_ceiling() {
+        assert_eq!(normalize_generation_params(None, None, &limits(1024, false)), Ok((0.2, 256)));
+        assert_eq!(normalize_generation_params(None, None, &limits(100, false)), Ok((0.2, 100)));
+    }
+
+    #[test]
+    fn an_explicit_seed_is_used_as_is() {
+        for seed in [0, 42, MAX_SEED] {
+            assert_eq!(resolve_seed(Some(seed)), Ok(seed));
+        }
+    }
+
+    #[test]
+    fn seeds_outside_the_runner_range_are_rejected() {
+        assert!(resolve_seed(Some(MAX_SEED + 1)).is_err());
+        assert!(resolve_seed(Some(u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn a_missing_seed_is_replaced_by_one_the_runner_accepts() {
+        for _ in 0..100 {
+            assert!(resolve_seed(None).unwrap() <= MAX_SEED);
+        }
+    }
+
+    #[test]
+    fn blank_prompts_are_rejected() {
+        for prompt in ["", " ", "\n\t  "] {
+            assert!(matches!(check_prompt(prompt, false, false), Err(CommandError::EmptyPrompt)));
+        }
+        assert!(check_prompt(" hi ", false, false).is_ok());
+    }
+
+    #[test]
+    fn a_prefill_or_messages_stand_in_for_the_prompt() {
+        assert!(check_prompt("", true, false).is_ok());
+    }
+
+    #[test]
+    fn allow_empty_prompt_permits_unconditioned_generation() {
+        assert!(check_prompt("  ", false, true).is_ok());
+    }
+
+    fn runtime_with_generation(id: &str) -> Arc<Mutex<PythonRuntimeInner>> {
+        let mut inner = PythonRuntimeInner::default();
+        inner.generations.insert(id.to_string(), ActiveGeneration::new());
+        Arc::new(Mutex::new(inner))
+    }
+
+    /// `cat` stands in for the runner: whatever the watchdog sends comes back
+    /// on stdout.
+    #[cfg(unix)]
+    #[test]
+    fn max_duration_cancels_a_generation_that_runs_too_long() {
+        let mut child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let runtime = runtime_with_generation("gen-slow");
+        runtime.lock().unwrap().stdin = child.stdin.take();
+
+        let started = Instant::now();
+        let limit = Duration::from_millis(50);
+        let watchdog = spawn_max_duration_watchdog(runtime.clone(), "gen-slow".into(), limit);
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        watchdog.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let msg: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(msg["type"], "cancel");
+        assert_eq!(msg["generation_id"], "gen-slow");
+        // The runner's cancel ack then finishes it as `done` / `max_duration`.
+        let g = runtime.lock().unwrap().generations.remove("gen-slow");
+        assert_eq!(
+            terminal_event("cancelled", None, g.as_ref()),
+            Some(TerminalEvent::Done(Some("max_duration".into())))
+        );
+
+        runtime.lock().unwrap().stdin = None;
+        let _ = child.wait();
+    }
+
+    #[test]
+    fn max_duration_watchdog_exits_once_the_generation_is_done() {
+        let runtime = runtime_with_generation("gen-quick");
+        let limit = Duration::from_secs(3600);
+        let watchdog = spawn_max_duration_watchdog(runtime.clone(), "gen-quick".into(), limit);
+        runtime.lock().unwrap().generations.remove("gen-quick");
+
+        let started = Instant::now();
+        watchdog.join().unwrap();
+        assert!(started.elapsed() < WATCHDOG_POLL * 4);
+    }
+
+    /// A runner that died mid-stream: the next write fails, drops its stdin,
+    /// and reaping leaves a clean slate for `RuntimeNotRunning` or a restart.
+    #[cfg(unix)]
+    #[test]
+    fn a_dead_runner_is_cleaned_up() {
+        let mut child = Command::new("true").stdin(Stdio::piped()).spawn().unwrap();
+        let runtime = runtime_with_generation("gen-1");
+        let mut inner = runtime.lock().unwrap();
+        inner.stdin = child.stdin.take();
+        let _ = child.wait();
+        inner.child = Some(child);
+        inner.ready_features = Some(vec!["prefill".into()]);
+        inner.runner_started_at = Some(Instant::now());
+
+        let msg = serde_json::json!({ "type": "cancel", "generation_id": "gen-1" });
+        assert!(inner.send_to_runner(&msg, "cancel").is_err());
+        assert!(inner.stdin.is_none());
+
+        let exit = inner.reap_runner().expect("a runner to reap");
+        assert_eq!(exit.aborted, ["gen-1"]);
+        assert_eq!(exit.code, Some(0));
+        assert!(inner.child.is_none());
+        assert!(inner.generations.is_empty());
+        assert!(inner.ready_features.is_none() && inner.capabilities.is_none());
+        assert!(inner.send_to_runner(&msg, "cancel").is_err());
+        // Nothing left to reap, so a second exit report is a no-op.
+        assert!(inner.reap_runner().is_none());
+    }
+
+    /// A live runner (`cat`) at epoch 1 that hasn't sent `ready` yet.
+    #[cfg(unix)]
+    fn runtime_awaiting_ready() -> Arc<Mutex<PythonRuntimeInner>> {
+        let child = Command::new("cat").stdin(Stdio::piped()).spawn().unwrap();
+        Arc::new(Mutex::new(PythonRuntimeInner {
+            child: Some(child),
+            runner_epoch: 1,
+            ..Default::default()
+        }))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_bare_ready_line_ends_the_wait() {
+        let runtime = runtime_awaiting_ready();
+        let reader = runtime.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            reader.lock().unwrap().mark_runner_ready();
+        });
+
+        let started = Instant::now();
+        wait_for_runner_ready(&runtime, 1, Duration::from_secs(30)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(runtime.lock().unwrap().ready_features.is_none());
+        runtime.lock().unwrap().reap_runner();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_silent_runner_is_only_waited_for_once() {
+        let runtime = runtime_awaiting_ready();
+        wait_for_runner_ready(&runtime, 1, Duration::from_millis(50)).unwrap();
+
+        let started = Instant::now();
+        wait_for_runner_ready(&runtime, 1, Duration::from_secs(30)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        runtime.lock().unwrap().reap_runner();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_runner_exit_wakes_the_wait() {
+        let runtime = runtime_awaiting_ready();
+        let reader = runtime.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            reader.lock().unwrap().reap_runner();
+        });
+
+        let started = Instant::now();
+        assert!(wait_for_runner_ready(&runtime, 1, Duration::from_secs(30)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn only_the_switched_folder_is_labelled_with_its_revision() {
+        let mut inner = PythonRuntimeInner {
+            pending_switch: Some(PendingSwitch {
+                switch_id: "switch-1".into(),
+                repo_id: "org/model".into(),
+                revision: Some("v2".into()),
+                local_dir: "/models/org_model/revisions/v2".into(),
+            }),
+            ..Default::default()
+        };
+        inner.record_loaded_model("org/model", "/models/org_model");
+        assert_eq!(inner.loaded_revision("org/model"), None);
+
+        inner.record_loaded_model("org/model", "/models/org_model/revisions/v2");
+        assert_eq!(inner.loaded_revision("org/model").as_deref(), Some("v2"));
+        assert_eq!(inner.loaded_revision("org/other"), None);
+    }
+
+    fn compare_batch(ids: &[&str]) -> CompareBatchState {
+        let pending = ids
+            .iter()
+            .map(|id| ChatGeneratePayload {
+                compare: Some(CompareTag {
+                    batch_id: "batch-1".into(),
+                    model: format!("org/{id}"),
+                }),
+                generation_id: Some(id.to_string()),
+                ..ChatGeneratePayload::new(format!("org/{id}"), "hi".into())
+            })
+            .collect();
+        CompareBatchState {
+            expected: ids.len() + 1,
+            results: Vec::new(),
+            pending,
+        }
+    }
+
+    #[test]
+    fn queued_compare_members_can_be_taken_out() {
+        let mut inner = PythonRuntimeInner::default();
+        inner.compare_batches.insert("batch-1".into(), compare_batch(&["gen-b", "gen-c"]));
+
+        let tag = inner.take_pending_compare_member("gen-c").expect("a queued member");
+        assert_eq!((tag.batch_id.as_str(), tag.model.as_str()), ("batch-1", "org/gen-c"));
+        assert!(inner.take_pending_compare_member("gen-c").is_none());
+        assert!(inner.take_pending_compare_member("gen-a").is_none());
+        assert_eq!(inner.compare_batches["batch-1"].pending.len(), 1);
+    }
+
+    #[test]
+    fn a_reset_aborts_queued_compare_members_too() {
+        let mut inner = PythonRuntimeInner::default();
+        inner.generations.insert("gen-a".into(), ActiveGeneration::new());
+        inner.compare_batches.insert("batch-1".into(), compare_batch(&["gen-b", "gen-c"]));
+
+        let mut aborted = inner.reset_runtime_state();
+        aborted.sort();
+        assert_eq!(aborted, ["gen-a", "gen-b", "gen-c"]);
+        assert!(inner.compare_batches.is_empty());
+    }
+
+    /// The exit status may lag behind stdout closing; it still counts as the
+    /// runner's own exit rather than one we forced.
+    #[cfg(unix)]
+    #[test]
+    fn reaping_waits_briefly_for_the_runner_to_exit() {
+        let child = Command::new("sh").args(["-c", "sleep 0.05; exit 3"]).spawn().unwrap();
+        let mut inner = PythonRuntimeInner {
+            child: Some(child),
+            ..Default::default()
+        };
+        let exit = inner.reap_runner().expect("a runner to reap");
+        assert!(exit.exited.is_some());
+        assert_eq!(exit.code, Some(3));
+    }
+
+    #[test]
+    fn held_text_keeps_a_partial_word_until_the_generation_ends() {
+        let mut g = ActiveGeneration {
+            chunker: Some(MarkdownChunker::default()),
+            ..ActiveGeneration::new()
+        };
+        g.pending_text.push_str("Shown while hid");
+        assert_eq!(g.take_held_text(false).as_deref(), Some("Shown while "));
+        assert_eq!(g.take_held_text(false), None);
+        g.pending_text.push_str("den\n```\nfn");
+        assert_eq!(g.take_held_text(false).as_deref(), Some("hidden\n"));
+        assert_eq!(g.take_held_text(true).as_deref(), Some("```\nfn"));
+        assert!(g.pending_text.is_empty());
+
+        let mut raw = ActiveGeneration::new();
+        raw.pending_text.push_str("no chunk");
+        assert_eq!(raw.take_held_text(false).as_deref(), Some("no chunk"));
+    }
+
+    #[test]
+    fn cancel_latest_skips_generations_already_stopping() {
+        let mut inner = PythonRuntimeInner::default();
+        let now = Instant::now();
+        for (i, id) in ["gen-old", "gen-mid", "gen-new"].into_iter().enumerate() {
+            let g = ActiveGeneration {
+                started_at: now + Duration::from_millis(i as u64),
+                ..ActiveGeneration::new()
+            };
+            inner.generations.insert(id.to_string(), g);
+        }
+        inner.mock_mode = true;
+
+        for expected in ["gen-new", "gen-mid", "gen-old"] {
+            let id = inner.latest_cancellable_generation();
+            assert_eq!(id.as_deref(), Some(expected));
+            cancel_generation(&mut inner, expected).unwrap();
+        }
+        assert_eq!(inner.latest_cancellable_generation(), None);
+    }
+
+    #[test]
+    fn cancel_latest_skips_a_generation_cerebro_is_stopping() {
+        let mut inner = PythonRuntimeInner::default();
+        let mut g = ActiveGeneration::new();
+        assert!(g.claim_stop("max_duration"));
+        inner.generations.insert("gen-1".into(), g);
+        assert_eq!(inner.latest_cancellable_generation(), None);
+    }
+
+    const AREA: Bounds = Bounds {
+        x: 0.0,
+        y: 25.0,
+        width: 1000.0,
+        height: 800.0,
+    };
+    const GAP: f64 = 20.0;
+
+    #[test]
+    fn clamp_leaves_a_window_that_fits() {
+        assert_eq!(clamp_to_work_area(300.0, 200.0, 400.0, 300.0, AREA, GAP), (300.0, 200.0));
+    }
+
+    #[test]
+    fn clamp_pulls_in_each_edge_with_the_gap() {
+        // Left and top.
+        assert_eq!(clamp_to_work_area(-50.0, 0.0, 400.0, 300.0, AREA, GAP), (20.0, 45.0));
+        // Right: 1000 - 20 - 400.
+        assert_eq!(clamp_to_work_area(900.0, 200.0, 400.0, 300.0, AREA, GAP).0, 580.0);
+        // Bottom: 25 + 800 - 20 - 300.
+        assert_eq!(clamp_to_work_area(300.0, 700.0, 400.0, 300.0, AREA, GAP).1, 505.0);
+    }
+
+    #[test]
+    fn clamp_keeps_the_gap_at_an_edge_it_just_touches() {
+        let (x, y) = clamp_to_work_area(0.0, 25.0, 400.0, 300.0, AREA, GAP);
+        assert_eq!((x, y), (AREA.x + GAP, AREA.y + GAP));
+    }
+
+    #[test]
+    fn clamp_keeps_the_top_left_of_a_window_too_large_for_the_area() {
+        assert_eq!(clamp_to_work_area(500.0, 500.0, 1200.0, 900.0, AREA, GAP), (20.0, 45.0));
+    }
+
+    #[test]
+    fn clamp_works_on_a_monitor_left_of_the_primary() {
+        let area = Bounds {
+            x: -1920.0,
+            ..AREA
+        };
+        assert_eq!(clamp_to_work_area(-100.0, 200.0, 400.0, 300.0, area, GAP).0, -1340.0);
+    }
+}
@@ -0,0 +1,219 @@
+// Offline stand-ins for the Python runner, used when mock mode is enabled so the
+// frontend can be developed without downloading a model. Events mirror the
+// runner's message shapes exactly.
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tauri::Emitter;
+
+use crate::PythonRuntimeInner;
+
+const MOCK_RESPONSE: &str = "This is a **mock** response from Cerebro. \
+No model was loaded and the Python runner was not started.\n\n\
+```rust\nfn main() {\n    println!(\"hello from mock mode\");\n}\n```\n\n\
+Disable mock mode to talk to a real local model.";
+
+const MOCK_PREFILL_DELAY: Duration = Duration::from_millis(400);
+const MOCK_TOKEN_DELAY: Duration = Duration::from_millis(35);
+const MOCK_DOWNLOAD_TOTAL_BYTES: u64 = 64 * 1024 * 1024;
+const MOCK_DOWNLOAD_TICKS: u64 = 16;
+const MOCK_DOWNLOAD_TICK_DELAY: Duration = Duration::from_millis(60);
+
+/// Splits text into word-sized chunks, keeping whitespace attached so the
+/// concatenation of chunks is the original text.
+fn mock_tokens(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    for ch in text.chars() {
+        current.push(ch);
+        if ch.is_whitespace() {
+            out.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+/// A canned token stream, played back as if the runner produced it.
+pub(crate) struct Replay {
+    /// Each token with the delay before it.
+    pub tokens: Vec<(Duration, String)>,
+    /// Reported in `chat_done` unless Cerebro stopped the stream itself.
+    pub finish_reason: Option<String>,
+    /// Served from the response cache; flagged as `cached` in `chat_done`.
+    pub cached: bool,
+}
+
+/// Streams `MOCK_RESPONSE` for `generation_id`, like `spawn_replay`.
+pub(crate) fn spawn_mock_generation(
+    app: tauri::AppHandle,
+    runtime: Arc<Mutex<PythonRuntimeInner>>,
+    generation_id: String,
+    seed: u64,
+) {
+    let mut tokens: Vec<(Duration, String)> = mock_tokens(MOCK_RESPONSE)
+        .into_iter()
+        .map(|token| (MOCK_TOKEN_DELAY, token))
+        .collect();
+    if let Some(first) = tokens.first_mut() {
+        first.0 += MOCK_PREFILL_DELAY;
+    }
+    let replay = Replay {
+        tokens,
+        finish_reason: None,
+        cached: false,
+    };
+    spawn_replay(app, runtime, generation_id, seed, replay);
+}
+
+/// Streams `replay` for `generation_id`. The generation must already be
+/// registered in `inner.generations`; a cancel or max-duration stop recorded
+/// there ends the stream early, acknowledged like the runner would.
+pub(crate) fn spawn_replay(
+    app: tauri::AppHandle,
+    runtime: Arc<Mutex<PythonRuntimeInner>>,
+    generation_id: String,
+    seed: u64,
+    replay: Replay,
+) {
+    thread::spawn(move || {
+        for (delay, token) in replay.tokens {
+            thread::sleep(delay);
+            let (chunk, compare) = {
+                let Ok(mut inner) = runtime.lock() else {
+                    return;
+                };
+                let compare = match inner.generations.get(&generation_id) {
+                    Some(g) if g.cancel_requested_at.is_none() => g.compare.clone(),
+                    _ => break,
+                };
+                (inner.accept_token(&app, &generation_id, &token), compare)
+            };
+            if let Some(chunk) = chunk {
+                if let Some(tag) = compare {
+                    tag.emit_token(&app, &generation_id, &chunk);
+                }
+                let _ = app.emit(
+                    "cerebro:chat_token",
+                    serde_json::json!({
+                        "type": "chat_token",
+                        "generation_id": generation_id,
+                        "token": chunk,
+                    }),
+                );
+            }
+        }
+
+        let mut generation = None;
+        if let Ok(mut inner) = runtime.lock() {
+            inner.flush_pending_tokens(&app, &generation_id);
+            let outcome = match inner.generations.get(&generation_id) {
+                Some(g) if g.cancel_requested_at.is_some() && g.finish_reason.is_none() => {
+                    "cancelled"
+                }
+                _ => "done",
+            };
+            generation = inner.finish_generation(&app, &generation_id, outcome);
+        }
+        let finish_reason = generation.as_ref().and_then(|g| g.finish_reason);
+        let cancelled = generation.is_some_and(|g| g.cancel_requested_at.is_some());
+        if cancelled && finish_reason.is_none() {
+            let _ = app.emit(
+                "cerebro:chat_cancelled",
+                serde_json::json!({
+                    "type": "cancelled",
+                    "generation_id": generation_id,
+                    "seed": seed,
+                }),
+            );
+            return;
+        }
+
+        let finish_reason = finish_reason.map(str::to_string).or(replay.finish_reason);
+        let done = done_event(&generation_id, seed, finish_reason, replay.cached);
+        let _ = app.emit("cerebro:chat_done", done);
+    });
+}
+
+/// The runner's `done` message for a replayed stream.
+fn done_event(
+    generation_id: &str,
+    seed: u64,
+    finish_reason: Option<String>,
+    cached: bool,
+) -> serde_json::Value {
+    let mut done = serde_json::json!({
+        "type": "done",
+        "generation_id": generation_id,
+        "seed": seed,
+    });
+    if let Some(reason) = finish_reason {
+        done["finish_reason"] = reason.into();
+    }
+    if cached {
+        done["cached"] = true.into();
+    }
+    done
+}
+
+/// Simulates a quick download with a handful of progress ticks.
+pub(crate) fn spawn_mock_download(
+    app: tauri::AppHandle,
+    download_id: String,
+    repo_id: String,
+    local_dir: String,
+) {
+    thread::spawn(move || {
+        let _ = app.emit(
+            "cerebro:model_download_started",
+            serde_json::json!({
+                "type": "download_started",
+                "download_id": download_id,
+                "repo_id": repo_id,
+                "resumed": false,
+            }),
+        );
+
+        for tick in 0..=MOCK_DOWNLOAD_TICKS {
+            let _ = app.emit(
+                "cerebro:model_download_progress",
+                serde_json::json!({
+                    "type": "download_progress",
+                    "download_id": download_id,
+                    "repo_id": repo_id,
+                    "n": MOCK_DOWNLOAD_TOTAL_BYTES * tick / MOCK_DOWNLOAD_TICKS,
+                    "total": MOCK_DOWNLOAD_TOTAL_BYTES,
+                    "desc": "model.safetensors",
+                }),
+            );
+            thread::sleep(MOCK_DOWNLOAD_TICK_DELAY);
+        }
+
+        let _ = app.emit(
+            "cerebro:model_download_done",
+            serde_json::json!({
+                "type": "download_done",
+                "download_id": download_id,
+                "repo_id": repo_id,
+                "path": local_dir,
+            }),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn done_event_echoes_the_seed() {
+        let seed = crate::resolve_seed(Some(1234)).unwrap();
+        let done = done_event("gen-1", seed, Some("stop".into()), false);
+        assert_eq!(done["seed"], 1234);
+        assert_eq!(done["finish_reason"], "stop");
+        assert!(done.get("cached").is_none());
+    }
+}
@@ -0,0 +1,39 @@
+// Advisory locks on model folders, so a download, import or delete can't write
+// to a model that another operation, or another Cerebro instance, is using.
+// The lock file sits next to the folder rather than inside it, because imports
+// move a whole folder into place.
+use std::fs::{self, File, TryLockError};
+use std::path::Path;
+
+use crate::error::CommandError;
+
+/// Held for as long as the operation runs; dropping it releases the lock.
+pub(crate) struct ModelDirLock {
+    _file: File,
+}
+
+/// Locks `dir`, a model's base folder. Fails with `ModelBusy` instead of
+/// waiting when something else holds it.
+pub(crate) fn acquire(dir: &Path, repo_id: &str) -> Result<ModelDirLock, CommandError> {
+    let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) else {
+        return Err(format!("Invalid model folder: {}", dir.display()).into());
+    };
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create models dir: {e}"))?;
+    // Hidden, so size walks and model listings skip it.
+    let path = parent.join(format!(".{}.lock", name.to_string_lossy()));
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(ModelDirLock { _file: file }),
+        Err(TryLockError::WouldBlock) => Err(CommandError::ModelBusy {
+            repo_id: repo_id.to_string(),
+        }),
+        Err(TryLockError::Error(e)) => {
+            Err(format!("Failed to lock {}: {e}", path.display()).into())
+        }
+    }
+}
@@ -0,0 +1,985 @@
+// Local model store helpers: the persisted model index and format detection.
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use tauri::Manager;
+
+/// Per-repo metadata persisted to app_data_dir/models/index.json.
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct ModelIndexEntry {
+    /// Directory under models/ when it differs from the sanitized repo id.
+    pub dir_name: Option<String>,
+    pub format: Option<CachedFormat>,
+    /// Source folder for models adopted through `import_model`.
+    pub imported_from: Option<String>,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    /// Unix seconds of the last generation that used this model.
+    pub last_used: Option<u64>,
+}
+
+/// An installed model as reported by `list_models`.
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct ModelInfo {
+    /// `None` for folders the index has no record of (e.g. from older versions).
+    pub repo_id: Option<String>,
+    pub local_dir: String,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    pub imported_from: Option<String>,
+    pub last_used: Option<u64>,
+}
+
+/// A model folder as reported by `model_list_downloaded`.
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct DownloadedModel {
+    /// Folder name under models/.
+    pub dir_name: String,
+    /// From the index, which downloads and imports record; `None` for folders
+    /// it has no record of, since the sanitized name can't be mapped back.
+    pub repo_id: Option<String>,
+    pub local_dir: String,
+    /// Every file in the folder, pinned revisions excluded.
+    pub size_bytes: u64,
+}
+
+impl From<ModelInfo> for DownloadedModel {
+    fn from(model: ModelInfo) -> Self {
+        let dir = Path::new(&model.local_dir);
+        Self {
+            dir_name: dir
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            repo_id: model.repo_id,
+            size_bytes: walk_files(dir).iter().map(|(_, len)| len).sum(),
+            local_dir: model.local_dir,
+        }
+    }
+}
+
+/// Orderings `list_models` can apply on top of its default (favorites first).
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ModelSort {
+    Name,
+    /// Largest first.
+    Size,
+    /// Most recent first; never-used models last.
+    LastUsed,
+}
+
+impl ModelSort {
+    pub(crate) fn apply(self, models: &mut [ModelInfo]) {
+        match self {
+            Self::Name => models.sort_by_cached_key(|m| {
+                m.repo_id
+                    .clone()
+                    .unwrap_or_else(|| m.local_dir.clone())
+                    .to_lowercase()
+            }),
+            Self::Size => models.sort_by_cached_key(|m| {
+                let size: u64 = walk_files(Path::new(&m.local_dir))
+                    .iter()
+                    .map(|(_, len)| len)
+                    .sum();
+                std::cmp::Reverse(size)
+            }),
+            Self::LastUsed => models.sort_by_key(|m| std::cmp::Reverse(m.last_used)),
+        }
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct ModelFilter {
+    /// Only models carrying this tag (case-insensitive).
+    pub tag: Option<String>,
+    pub favorite: Option<bool>,
+    /// Case-insensitive substring of the repo id or folder name.
+    pub query: Option<String>,
+}
+
+impl ModelFilter {
+    pub(crate) fn matches(&self, model: &ModelInfo) -> bool {
+        if self.favorite.is_some_and(|f| f != model.favorite) {
+            return false;
+        }
+        if let Some(tag) = &self.tag {
+            if !model.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                return false;
+            }
+        }
+        if let Some(query) = &self.query {
+            let query = query.to_lowercase();
+            let haystack = model.repo_id.as_deref().unwrap_or(&model.local_dir);
+            if !haystack.to_lowercase().contains(&query) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub(crate) fn has_content(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|mut it| it.next().is_some())
+}
+
+/// Joins a repo's directory name and a pinned revision. Sanitized repo ids
+/// never contain it, so any folder name with one is a revision folder.
+const REVISION_SEPARATOR: char = '@';
+
+/// Where `revision` of the repo stored at `base` lives: `base` itself for the
+/// default branch, otherwise a sibling `<name>@<revision>` folder.
+pub(crate) fn revision_dir(base: &Path, revision: Option<&str>) -> PathBuf {
+    let Some(revision) = revision.map(str::trim).filter(|r| !r.is_empty()) else {
+        return base.to_path_buf();
+    };
+    let name = base
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    base.with_file_name(format!(
+        "{name}{REVISION_SEPARATOR}{}",
+        crate::sanitize_dir_component(revision)
+    ))
+}
+
+/// Checks that `dir` is a folder directly inside `models_dir`, so a crafted
+/// repo id or index entry (`..`, an absolute path) can't aim a delete elsewhere.
+pub(crate) fn ensure_model_subdir(models_dir: &Path, dir: &Path) -> Result<(), String> {
+    let inside = dir.strip_prefix(models_dir).is_ok_and(|rel| {
+        let mut parts = rel.components();
+        matches!((parts.next(), parts.next()), (Some(Component::Normal(_)), None))
+    });
+    if inside {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is not a model folder inside {}",
+            dir.display(),
+            models_dir.display()
+        ))
+    }
+}
+
+/// A locally downloaded revision of a repo.
+#[derive(serde::Serialize)]
+pub(crate) struct RevisionInfo {
+    /// `None` for the default branch. Otherwise the sanitized folder suffix,
+    /// so `refs/pr/1` comes back as `refs_pr_1`.
+    pub revision: Option<String>,
+    pub local_dir: String,
+    pub size_bytes: u64,
+    /// Unix seconds the folder was last modified.
+    pub modified_at: Option<u64>,
+}
+
+/// Every non-empty revision folder of the repo stored at `base`, default
+/// branch first and then newest first.
+pub(crate) fn list_revisions(base: &Path) -> Vec<RevisionInfo> {
+    let info = |dir: &Path, revision: Option<String>| RevisionInfo {
+        revision,
+        local_dir: dir.to_string_lossy().to_string(),
+        size_bytes: walk_files(dir).iter().map(|(_, len)| len).sum(),
+        modified_at: fs::metadata(dir)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+    };
+
+    let mut out = Vec::new();
+    if has_content(base) {
+        out.push(info(base, None));
+    }
+    let (Some(parent), Some(name)) = (base.parent(), base.file_name()) else {
+        return out;
+    };
+    let prefix = format!("{}{REVISION_SEPARATOR}", name.to_string_lossy());
+    let mut pinned: Vec<RevisionInfo> = fs::read_dir(parent)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let revision = file_name.strip_prefix(&prefix)?.to_string();
+            let path = entry.path();
+            (path.is_dir() && has_content(&path)).then(|| info(&path, Some(revision)))
+        })
+        .collect();
+    pinned.sort_by_key(|r| std::cmp::Reverse(r.modified_at));
+    out.extend(pinned);
+    out
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct CachedFormat {
+    /// Changes whenever weight files are added, removed or resized.
+    pub fingerprint: u64,
+    pub format: ModelFormat,
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct ModelIndex {
+    pub models: HashMap<String, ModelIndexEntry>,
+}
+
+#[derive(Default)]
+pub(crate) struct ModelIndexState {
+    inner: Mutex<ModelIndex>,
+}
+
+fn index_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let base = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+    Ok(base.join("models").join("index.json"))
+}
+
+impl ModelIndexState {
+    pub(crate) fn load(app: &tauri::AppHandle) -> Self {
+        let index = index_path(app)
+            .ok()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|raw| serde_json::from_str::<ModelIndex>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            inner: Mutex::new(index),
+        }
+    }
+
+    pub(crate) fn entry(&self, repo_id: &str) -> Option<ModelIndexEntry> {
+        self.inner
+            .lock()
+            .ok()
+            .and_then(|index| index.models.get(repo_id).cloned())
+    }
+
+    /// Installed models: everything the index knows about that has files on
+    /// disk, plus untracked folders under `models_dir`. Favorites come first.
+    pub(crate) fn installed_models(&self, models_dir: &Path) -> Vec<ModelInfo> {
+        let index = self
+            .inner
+            .lock()
+            .map(|index| index.clone())
+            .unwrap_or_default();
+
+        let mut seen = std::collections::HashSet::new();
+        let mut out = Vec::new();
+        for (repo_id, entry) in &index.models {
+            let dir_name = entry
+                .dir_name
+                .clone()
+                .unwrap_or_else(|| model_dir_name(models_dir, repo_id).0);
+            let dir = models_dir.join(&dir_name);
+            if !has_content(&dir) {
+                continue;
+            }
+            seen.insert(dir_name);
+            out.push(ModelInfo {
+                repo_id: Some(repo_id.clone()),
+                local_dir: dir.to_string_lossy().to_string(),
+                tags: entry.tags.clone(),
+                favorite: entry.favorite,
+                imported_from: entry.imported_from.clone(),
+                last_used: entry.last_used,
+            });
+        }
+
+        for dir_entry in fs::read_dir(models_dir).into_iter().flatten().flatten() {
+            let name = dir_entry.file_name().to_string_lossy().to_string();
+            let path = dir_entry.path();
+            // Revision folders belong to a model and show up through `list_model_revisions`.
+            let is_revision = name.contains(REVISION_SEPARATOR);
+            if is_revision || seen.contains(&name) || !path.is_dir() || !has_content(&path) {
+                continue;
+            }
+            out.push(ModelInfo {
+                repo_id: None,
+                local_dir: path.to_string_lossy().to_string(),
+                tags: Vec::new(),
+                favorite: false,
+                imported_from: None,
+                last_used: None,
+            });
+        }
+
+        out.sort_by(|a, b| {
+            b.favorite
+                .cmp(&a.favorite)
+                .then_with(|| a.repo_id.cmp(&b.repo_id))
+                .then_with(|| a.local_dir.cmp(&b.local_dir))
+        });
+        out
+    }
+
+    /// Applies `f` to the entry for `repo_id` (creating it if needed) and persists the index.
+    pub(crate) fn update_entry(
+        &self,
+        app: &tauri::AppHandle,
+        repo_id: &str,
+        f: impl FnOnce(&mut ModelIndexEntry),
+    ) -> Result<ModelIndexEntry, String> {
+        let mut index = self
+            .inner
+            .lock()
+            .map_err(|_| "Model index mutex poisoned".to_string())?;
+        let entry = index.models.entry(repo_id.to_string()).or_default();
+        f(entry);
+        let updated = entry.clone();
+
+        let path = index_path(app)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create models dir: {e}"))?;
+        }
+        let raw = serde_json::to_string_pretty(&*index)
+            .map_err(|e| format!("Serialize error: {e}"))?;
+        fs::write(&path, raw).map_err(|e| format!("Failed to write model index: {e}"))?;
+
+        Ok(updated)
+    }
+}
+
+#[cfg(windows)]
+const MAX_MODEL_PATH_LEN: usize = 260;
+#[cfg(not(windows))]
+const MAX_MODEL_PATH_LEN: usize = 4096;
+const MAX_DIR_COMPONENT_LEN: usize = 255;
+/// Room left for files nested inside a model dir (e.g. `onnx/model_quantized.onnx`).
+const MODEL_FILE_PATH_RESERVE: usize = 96;
+
+/// FNV-1a, so hashed directory names stay stable across builds.
+fn stable_hash(input: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in input.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Directory name for `repo_id` under `models_dir`: the sanitized repo id, or a
+/// short hashed name when that would exceed the platform's path limits.
+pub(crate) fn model_dir_name(models_dir: &Path, repo_id: &str) -> (String, bool) {
+    let sanitized = crate::sanitize_dir_component(repo_id);
+    let full_len = models_dir.join(&sanitized).as_os_str().len() + MODEL_FILE_PATH_RESERVE;
+    if sanitized.len() <= MAX_DIR_COMPONENT_LEN && full_len <= MAX_MODEL_PATH_LEN {
+        (sanitized, false)
+    } else {
+        (format!("m-{:016x}", stable_hash(repo_id)), true)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct ModelFormat {
+    /// `gguf`, `safetensors`, `pytorch`, `onnx` or `unknown`.
+    pub weight_format: String,
+    /// e.g. `bf16`, `fp16`, `8-bit`, `gptq-4bit`, or a GGUF type like `Q4_K_M`.
+    pub quantization: Option<String>,
+    pub param_count_estimate: Option<u64>,
+    pub weight_bytes: u64,
+    pub confidence: Confidence,
+}
+
+const WEIGHT_EXTENSIONS: &[(&str, &str)] = &[
+    ("gguf", "gguf"),
+    ("safetensors", "safetensors"),
+    ("bin", "pytorch"),
+    ("pt", "pytorch"),
+    ("pth", "pytorch"),
+    ("onnx", "onnx"),
+];
+
+/// Largest safetensors header we are willing to parse.
+const MAX_SAFETENSORS_HEADER: u64 = 100 * 1024 * 1024;
+
+/// Every regular file under `dir`, skipping hidden entries such as `.cache`.
+pub(crate) fn walk_files(dir: &Path) -> Vec<(PathBuf, u64)> {
+    let mut out = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                stack.push(entry.path());
+            } else if meta.is_file() {
+                out.push((entry.path(), meta.len()));
+            }
+        }
+    }
+    out
+}
+
+/// A plausible model directory has a `config.json` or at least one weight file.
+pub(crate) fn looks_like_model_dir(dir: &Path) -> bool {
+    dir.join("config.json").is_file()
+        || walk_files(dir)
+            .iter()
+            .any(|(path, _)| weight_format_for(path).is_some())
+}
+
+/// Copies every non-hidden file from `src` into `dest`, reporting
+/// `(bytes_copied, total_bytes, file)` after each file.
+pub(crate) fn copy_model_files(
+    src: &Path,
+    dest: &Path,
+    mut on_progress: impl FnMut(u64, u64, &Path),
+) -> Result<(), String> {
+    let files = walk_files(src);
+    let total: u64 = files.iter().map(|(_, len)| len).sum();
+    let mut copied = 0;
+    for (path, len) in files {
+        let rel = path.strip_prefix(src).unwrap_or(&path);
+        let target = dest.join(rel);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+        }
+        fs::copy(&path, &target).map_err(|e| format!("Failed to copy {}: {e}", rel.display()))?;
+        copied += len;
+        on_progress(copied, total, rel);
+    }
+    Ok(())
+}
+
+/// Sets `fields` in `dir/meta.json`, keeping any other keys already there so a
+/// file shipped with the model (e.g. by `export_model`) isn't clobbered.
+pub(crate) fn merge_meta(
+    dir: &Path,
+    fields: serde_json::Map<String, serde_json::Value>,
+) -> Result<(), String> {
+    let path = dir.join("meta.json");
+    let mut meta = match fs::read_to_string(&path) {
+        Ok(raw) => match serde_json::from_str(&raw) {
+            Ok(serde_json::Value::Object(existing)) => existing,
+            _ => return Err(format!("{} is not a JSON object", path.display())),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+        Err(e) => return Err(format!("Failed to read meta.json: {e}")),
+    };
+    meta.extend(fields);
+    let raw = serde_json::to_string_pretty(&meta).map_err(|e| format!("Serialize error: {e}"))?;
+    fs::write(&path, raw).map_err(|e| format!("Failed to write meta.json: {e}"))
+}
+
+/// Where `export_model` puts `repo_id` under `dest_dir`: one folder per repo id
+/// segment, so `org/name` lands in `dest_dir/org/name` like a Hub checkout.
+pub(crate) fn export_target(dest_dir: &Path, repo_id: &str) -> Result<PathBuf, String> {
+    let mut target = dest_dir.to_path_buf();
+    for segment in repo_id.split('/') {
+        let valid = !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && !segment.contains(['\\', ':', '\0']);
+        if !valid {
+            return Err(format!("Invalid repo id: {repo_id}"));
+        }
+        target.push(segment);
+    }
+    Ok(target)
+}
+
+/// Cheap signature of the weight files, used to invalidate cached detections.
+/// Persisted in index.json, so it uses `stable_hash` rather than std's hasher,
+/// whose output may change between Rust releases.
+pub(crate) fn weights_fingerprint(dir: &Path) -> u64 {
+    let mut files: Vec<(String, u64)> = walk_files(dir)
+        .into_iter()
+        .filter(|(p, _)| weight_format_for(p).is_some())
+        .map(|(p, len)| (p.to_string_lossy().to_string(), len))
+        .collect();
+    files.sort();
+
+    let signature: String = files
+        .iter()
+        .map(|(path, len)| format!("{path}\0{len}\n"))
+        .collect();
+    stable_hash(&signature)
+}
+
+fn weight_format_for(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    WEIGHT_EXTENSIONS
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, format)| *format)
+}
+
+fn bytes_per_param(quantization: &str) -> Option<f64> {
+    let q = quantization.to_ascii_lowercase();
+    if q == "fp32" || q == "f32" {
+        return Some(4.0);
+    }
+    if q == "fp16" || q == "bf16" || q == "f16" {
+        return Some(2.0);
+    }
+    if q.contains("8-bit") || q.contains("8bit") || q.starts_with("q8") || q == "fp8" {
+        return Some(1.0);
+    }
+    if q.contains("4-bit") || q.contains("4bit") || q.starts_with("q4") || q.starts_with("iq4") {
+        return Some(0.5);
+    }
+    // GGUF k-quants: Q2_K, Q3_K_M, Q5_K_S, Q6_K...
+    let digits: String = q
+        .trim_start_matches('i')
+        .trim_start_matches('q')
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse::<f64>().ok().map(|bits| bits / 8.0)
+}
+
+fn quantization_from_config(config: &serde_json::Value) -> Option<String> {
+    if let Some(qc) = config.get("quantization_config") {
+        let method = qc
+            .get("quant_method")
+            .and_then(|x| x.as_str())
+            .unwrap_or("quantized");
+        let bits = qc
+            .get("bits")
+            .and_then(|x| x.as_u64())
+            .or_else(|| {
+                if qc.get("load_in_4bit").and_then(|x| x.as_bool()) == Some(true) {
+                    Some(4)
+                } else if qc.get("load_in_8bit").and_then(|x| x.as_bool()) == Some(true) {
+                    Some(8)
+                } else {
+                    None
+                }
+            });
+        return Some(match bits {
+            Some(bits) => format!("{method}-{bits}bit"),
+            None => method.to_string(),
+        });
+    }
+
+    let dtype = config
+        .get("torch_dtype")
+        .or_else(|| config.get("dtype"))
+        .and_then(|x| x.as_str())?;
+    match dtype {
+        "float32" => Some("fp32".to_string()),
+        "float16" => Some("fp16".to_string()),
+        "bfloat16" => Some("bf16".to_string()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Bytes the weights take once loaded. The runner loads in bf16, so
+/// full-precision checkpoints count 2 bytes/param; quantized ones keep their width.
+pub(crate) fn loaded_weight_bytes(format: &ModelFormat) -> u64 {
+    if format.weight_format == "gguf" {
+        return format.weight_bytes;
+    }
+    let bytes_per_param = format
+        .quantization
+        .as_deref()
+        .and_then(bytes_per_param)
+        .map_or(2.0, |b| b.min(2.0));
+    match format.param_count_estimate {
+        Some(params) => (params as f64 * bytes_per_param) as u64,
+        None => format.weight_bytes,
+    }
+}
+
+/// The context window declared in config.json, in tokens.
+pub(crate) fn context_length(dir: &Path) -> Option<u64> {
+    let raw = fs::read_to_string(dir.join("config.json")).ok()?;
+    let root: serde_json::Value = serde_json::from_str(&raw).ok()?;
+    let config = root.get("text_config").unwrap_or(&root);
+    ["max_position_embeddings", "n_positions", "max_sequence_length", "seq_length"]
+        .iter()
+        .find_map(|name| config.get(name).and_then(|x| x.as_u64()))
+        .filter(|n| *n > 0)
+}
+
+/// fp16 KV cache size for `context_tokens`, from the attention shape in config.json.
+pub(crate) fn kv_cache_bytes(dir: &Path, context_tokens: u64) -> Option<u64> {
+    let raw = fs::read_to_string(dir.join("config.json")).ok()?;
+    let root: serde_json::Value = serde_json::from_str(&raw).ok()?;
+    // Multimodal configs nest the language model under `text_config`.
+    let config = root.get("text_config").unwrap_or(&root);
+    let field = |name: &str| config.get(name).and_then(|x| x.as_u64());
+
+    let layers = field("num_hidden_layers")?;
+    let heads = field("num_attention_heads")?.max(1);
+    let kv_heads = field("num_key_value_heads").unwrap_or(heads);
+    let head_dim = field("head_dim").or_else(|| Some(field("hidden_size")? / heads))?;
+    // Keys and values, 2 bytes each.
+    Some(2 * layers * kv_heads * head_dim * context_tokens * 2)
+}
+
+/// Pulls a GGUF quant type such as `Q4_K_M` out of a filename.
+fn gguf_quant_from_name(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?.to_ascii_uppercase();
+    for part in stem.split(['.', '-']) {
+        let is_quant = (part.starts_with('Q') || part.starts_with("IQ"))
+            && part
+                .trim_start_matches('I')
+                .trim_start_matches('Q')
+                .starts_with(|c: char| c.is_ascii_digit());
+        if is_quant || matches!(part, "F16" | "F32" | "BF16") {
+            return Some(part.to_string());
+        }
+    }
+    None
+}
+
+/// Sums tensor element counts (and tallies dtypes) from a safetensors header.
+fn safetensors_header_stats(path: &Path, dtypes: &mut HashMap<String, u64>) -> Option<u64> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut len_buf = [0u8; 8];
+    file.read_exact(&mut len_buf).ok()?;
+    let header_len = u64::from_le_bytes(len_buf);
+    if header_len == 0 || header_len > MAX_SAFETENSORS_HEADER {
+        return None;
+    }
+    let mut header = vec![0u8; header_len as usize];
+    file.read_exact(&mut header).ok()?;
+    let header: serde_json::Value = serde_json::from_slice(&header).ok()?;
+
+    let mut params = 0u64;
+    for (name, tensor) in header.as_object()? {
+        if name == "__metadata__" {
+            continue;
+        }
+        let count = tensor
+            .get("shape")?
+            .as_array()?
+            .iter()
+            .try_fold(1u64, |acc, d| d.as_u64().map(|d| acc.saturating_mul(d)))?;
+        params = params.saturating_add(count);
+        if let Some(dtype) = tensor.get("dtype").and_then(|x| x.as_str()) {
+            *dtypes.entry(dtype.to_string()).or_default() += count;
+        }
+    }
+    Some(params)
+}
+
+/// Largest file `read_model_text_file` returns.
+pub(crate) const MODEL_FILE_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Reads a UTF-8 text file from inside `dir`. Paths are canonicalized first, so
+/// `..` segments and symlinks that lead outside the model folder are rejected.
+pub(crate) fn read_model_text_file(dir: &Path, relative_path: &str) -> Result<String, String> {
+    let relative = Path::new(relative_path);
+    if relative_path.is_empty() || relative.is_absolute() {
+        return Err(format!("Invalid model file path: {relative_path}"));
+    }
+    let root = dir
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve model dir: {e}"))?;
+    let path = root
+        .join(relative)
+        .canonicalize()
+        .map_err(|_| format!("No such file in model: {relative_path}"))?;
+    if !path.starts_with(&root) {
+        return Err(format!("{relative_path} is outside the model directory"));
+    }
+
+    let meta = fs::metadata(&path).map_err(|e| format!("Failed to stat {relative_path}: {e}"))?;
+    if !meta.is_file() {
+        return Err(format!("{relative_path} is not a file"));
+    }
+    if meta.len() > MODEL_FILE_MAX_BYTES {
+        return Err(format!(
+            "{relative_path} is too large to display ({} bytes, limit {MODEL_FILE_MAX_BYTES})",
+            meta.len()
+        ));
+    }
+    let bytes = fs::read(&path).map_err(|e| format!("Failed to read {relative_path}: {e}"))?;
+    if bytes.contains(&0) {
+        return Err(format!("{relative_path} is a binary file"));
+    }
+    String::from_utf8(bytes).map_err(|_| format!("{relative_path} is not UTF-8 text"))
+}
+
+/// The Jinja chat template, looked up where transformers does: a standalone
+/// `chat_template.jinja`, then `chat_template.json`, then `tokenizer_config.json`.
+/// Of a list of named templates, the one called `default` wins.
+pub(crate) fn chat_template(dir: &Path) -> Option<String> {
+    let from_json = |file: &str| -> Option<String> {
+        let raw = fs::read_to_string(dir.join(file)).ok()?;
+        let value: serde_json::Value = serde_json::from_str(&raw).ok()?;
+        match value.get("chat_template")? {
+            serde_json::Value::String(template) => Some(template.clone()),
+            serde_json::Value::Array(named) => named
+                .iter()
+                .find(|t| t.get("name").and_then(|n| n.as_str()) == Some("default"))
+                .or_else(|| named.first())
+                .and_then(|t| t.get("template"))
+                .and_then(|t| t.as_str())
+                .map(str::to_string),
+            _ => None,
+        }
+    };
+    fs::read_to_string(dir.join("chat_template.jinja"))
+        .ok()
+        .or_else(|| from_json("chat_template.json"))
+        .or_else(|| from_json("tokenizer_config.json"))
+        .filter(|template| !template.trim().is_empty())
+}
+
+/// Best-effort inspection of a downloaded model directory. Never fails on
+/// unknown layouts; uncertain results are reported with a lower confidence.
+pub(crate) fn detect_model_format(dir: &Path) -> ModelFormat {
+    let files = walk_files(dir);
+
+    let mut bytes_by_format: HashMap<&'static str, u64> = HashMap::new();
+    for (path, len) in &files {
+        if let Some(format) = weight_format_for(path) {
+            *bytes_by_format.entry(format).or_default() += len;
+        }
+    }
+    let Some((weight_format, weight_bytes)) = bytes_by_format
+        .iter()
+        .max_by_key(|(_, bytes)| **bytes)
+        .map(|(f, b)| (*f, *b))
+    else {
+        return ModelFormat {
+            weight_format: "unknown".to_string(),
+            quantization: None,
+            param_count_estimate: None,
+            weight_bytes: 0,
+            confidence: Confidence::Low,
+        };
+    };
+
+    let weight_files: Vec<&PathBuf> = files
+        .iter()
+        .filter(|(p, _)| weight_format_for(p) == Some(weight_format))
+        .map(|(p, _)| p)
+        .collect();
+
+    let config: Option<serde_json::Value> = fs::read_to_string(dir.join("config.json"))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok());
+
+    let mut quantization = match weight_format {
+        "gguf" => weight_files.iter().find_map(|p| gguf_quant_from_name(p)),
+        _ => config.as_ref().and_then(quantization_from_config),
+    };
+
+    let mut exact_params = None;
+    if weight_format == "safetensors" {
+        let mut dtypes = HashMap::new();
+        let counts: Option<Vec<u64>> = weight_files
+            .iter()
+            .map(|p| safetensors_header_stats(p, &mut dtypes))
+            .collect();
+        exact_params = counts.map(|c| c.iter().sum::<u64>()).filter(|n| *n > 0);
+
+        if quantization.is_none() {
+            quantization = dtypes
+                .into_iter()
+                .max_by_key(|(_, n)| *n)
+                .map(|(dtype, _)| match dtype.as_str() {
+                    "F32" => "fp32".to_string(),
+                    "F16" => "fp16".to_string(),
+                    "BF16" => "bf16".to_string(),
+                    other => other.to_ascii_lowercase(),
+                });
+        }
+    }
+
+    let estimated_params = quantization
+        .as_deref()
+        .and_then(bytes_per_param)
+        .map(|bpp| (weight_bytes as f64 / bpp) as u64);
+
+    let confidence = match (exact_params, &quantization, estimated_params) {
+        (Some(_), Some(_), _) => Confidence::High,
+        (Some(_), None, _) | (None, Some(_), Some(_)) => Confidence::Medium,
+        _ => Confidence::Low,
+    };
+
+    ModelFormat {
+        weight_format: weight_format.to_string(),
+        quantization,
+        param_count_estimate: exact_params.or(estimated_params),
+        weight_bytes,
+        confidence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ensure_model_subdir_rejects_paths_outside_models_dir() {
+        let models_dir = Path::new("/data/cerebro/models");
+        for bad in ["../../etc", "/etc", "org/model", "", "."] {
+            assert!(
+                ensure_model_subdir(models_dir, &models_dir.join(bad)).is_err(),
+                "{bad:?} should be rejected"
+            );
+        }
+        assert!(ensure_model_subdir(models_dir, Path::new("/data/cerebro/other")).is_err());
+    }
+
+    #[test]
+    fn ensure_model_subdir_accepts_a_direct_child() {
+        let models_dir = Path::new("/data/cerebro/models");
+        let dir = models_dir.join(crate::sanitize_dir_component("org/model"));
+        assert!(ensure_model_subdir(models_dir, &dir).is_ok());
+    }
+
+    #[test]
+    fn model_dir_name_hashes_repo_ids_that_would_overflow_the_path_limit() {
+        // Leaves room for a hashed name (`m-` and 16 hex digits), not the full one.
+        let target = MAX_MODEL_PATH_LEN - MODEL_FILE_PATH_RESERVE - 25;
+        let mut base = PathBuf::from("/data");
+        while base.as_os_str().len() < target {
+            let left = target - base.as_os_str().len() - 1;
+            base.push("x".repeat(left.clamp(1, 100)));
+        }
+
+        let (name, hashed) = model_dir_name(&base, "some-org/a-rather-long-model-name-GGUF");
+        assert!(hashed);
+        assert!(base.join(&name).as_os_str().len() + MODEL_FILE_PATH_RESERVE <= MAX_MODEL_PATH_LEN);
+        // Stable for the same repo, distinct for another.
+        let again = model_dir_name(&base, "some-org/a-rather-long-model-name-GGUF").0;
+        let other = model_dir_name(&base, "some-org/a-rather-long-model-name-GGML").0;
+        assert_eq!(name, again);
+        assert_ne!(name, other);
+    }
+
+    #[test]
+    fn model_dir_name_keeps_readable_names_under_a_short_base() {
+        let (name, hashed) = model_dir_name(Path::new("/data/models"), "org/model");
+        assert!(!hashed);
+        assert_eq!(name, crate::sanitize_dir_component("org/model"));
+    }
+
+    #[test]
+    fn model_dir_name_hashes_components_too_long_for_the_filesystem() {
+        let repo_id = format!("org/{}", "m".repeat(MAX_DIR_COMPONENT_LEN));
+        let (name, hashed) = model_dir_name(Path::new("/data/models"), &repo_id);
+        assert!(hashed);
+        assert!(name.len() <= MAX_DIR_COMPONENT_LEN);
+    }
+
+    #[test]
+    fn weights_fingerprint_tracks_weight_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("model.safetensors"), [0u8; 8]).unwrap();
+        let before = weights_fingerprint(dir.path());
+        assert_eq!(weights_fingerprint(dir.path()), before);
+
+        fs::write(dir.path().join("README.md"), "notes").unwrap();
+        assert_eq!(weights_fingerprint(dir.path()), before);
+
+        fs::write(dir.path().join("model.safetensors"), [0u8; 16]).unwrap();
+        assert_ne!(weights_fingerprint(dir.path()), before);
+    }
+
+    #[test]
+    fn stable_hash_is_fnv1a() {
+        // Reference values for 64-bit FNV-1a; persisted hashes depend on them.
+        assert_eq!(stable_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    /// A model folder next to a `secret.txt` that must stay unreachable.
+    fn model_fixture() -> (tempfile::TempDir, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        let model = root.path().join("models").join("org_model");
+        fs::create_dir_all(&model).unwrap();
+        fs::write(model.join("config.json"), "{}").unwrap();
+        fs::write(root.path().join("secret.txt"), "secret").unwrap();
+        (root, model)
+    }
+
+    #[test]
+    fn read_model_text_file_reads_files_inside_the_model() {
+        let (_root, model) = model_fixture();
+        assert_eq!(read_model_text_file(&model, "config.json").unwrap(), "{}");
+    }
+
+    #[test]
+    fn read_model_text_file_rejects_traversal() {
+        let (_root, model) = model_fixture();
+        assert!(read_model_text_file(&model, "../../secret.txt").is_err());
+        assert!(read_model_text_file(&model, "../../etc/passwd").is_err());
+        assert!(read_model_text_file(&model, "").is_err());
+    }
+
+    #[test]
+    fn read_model_text_file_rejects_absolute_paths() {
+        let (root, model) = model_fixture();
+        let secret = root.path().join("secret.txt");
+        assert!(read_model_text_file(&model, &secret.to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn merge_meta_keeps_existing_keys() {
+        let (_root, model) = model_fixture();
+        let fields = |v: serde_json::Value| v.as_object().unwrap().clone();
+        merge_meta(&model, fields(serde_json::json!({ "imported_from": "/a" }))).unwrap();
+        let raw = fs::read_to_string(model.join("meta.json")).unwrap();
+        let meta: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(meta, serde_json::json!({ "imported_from": "/a" }));
+
+        fs::write(model.join("meta.json"), r#"{"exported_from":"cerebro"}"#).unwrap();
+        merge_meta(&model, fields(serde_json::json!({ "imported_from": "/b" }))).unwrap();
+        let raw = fs::read_to_string(model.join("meta.json")).unwrap();
+        let meta: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(meta["exported_from"], "cerebro");
+        assert_eq!(meta["imported_from"], "/b");
+
+        fs::write(model.join("meta.json"), "[]").unwrap();
+        assert!(merge_meta(&model, serde_json::Map::new()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_model_text_file_rejects_symlinks_out_of_the_model() {
+        let (root, model) = model_fixture();
+        std::os::unix::fs::symlink(root.path().join("secret.txt"), model.join("link.txt"))
+            .unwrap();
+        let err = read_model_text_file(&model, "link.txt").unwrap_err();
+        assert!(err.contains("outside"), "{err}");
+    }
+
+    #[test]
+    fn read_model_text_file_enforces_the_size_cap() {
+        let (_root, model) = model_fixture();
+        let big = "a".repeat(MODEL_FILE_MAX_BYTES as usize + 1);
+        fs::write(model.join("big.txt"), big).unwrap();
+        let err = read_model_text_file(&model, "big.txt").unwrap_err();
+        assert!(err.contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn read_model_text_file_rejects_binary_files() {
+        let (_root, model) = model_fixture();
+        fs::write(model.join("model.bin"), [0x47, 0x47, 0x55, 0x46, 0, 1]).unwrap();
+        let err = read_model_text_file(&model, "model.bin").unwrap_err();
+        assert!(err.contains("binary"), "{err}");
+    }
+}
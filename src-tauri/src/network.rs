@@ -0,0 +1,86 @@
+// Offline switch for everything Cerebro does on the network. In-flight HTTP
+// work runs as an abortable task registered here, so `go_offline` can stop it
+// all at once; new work fails fast with `CommandError::Offline` until
+// `go_online`.
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use tokio::task::AbortHandle;
+
+use crate::error::CommandError;
+
+#[derive(Default)]
+struct Registry {
+    offline: bool,
+    next_id: u64,
+    tasks: HashMap<u64, AbortHandle>,
+}
+
+#[derive(Default)]
+pub(crate) struct NetworkState {
+    inner: Mutex<Registry>,
+}
+
+impl NetworkState {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Registry>, CommandError> {
+        self.inner
+            .lock()
+            .map_err(|_| "Network state mutex poisoned".to_string().into())
+    }
+
+    pub(crate) fn ensure_online(&self) -> Result<(), CommandError> {
+        if self.lock()?.offline {
+            return Err(CommandError::Offline);
+        }
+        Ok(())
+    }
+
+    /// Runs `work` as a task that `go_offline` can abort, in which case this
+    /// returns `Offline`.
+    pub(crate) async fn run<T, F>(&self, work: F) -> Result<T, CommandError>
+    where
+        T: Send + 'static,
+        F: Future<Output = Result<T, CommandError>> + Send + 'static,
+    {
+        let (id, task) = {
+            // Checked under the lock so a concurrent `go_offline` can't miss the task.
+            let mut registry = self.lock()?;
+            if registry.offline {
+                return Err(CommandError::Offline);
+            }
+            let task = tokio::spawn(work);
+            registry.next_id += 1;
+            let id = registry.next_id;
+            registry.tasks.insert(id, task.abort_handle());
+            (id, task)
+        };
+
+        let result = task.await;
+        if let Ok(mut registry) = self.inner.lock() {
+            registry.tasks.remove(&id);
+        }
+        match result {
+            Ok(result) => result,
+            Err(e) if e.is_cancelled() => Err(CommandError::Offline),
+            Err(e) => Err(format!("Network task failed: {e}").into()),
+        }
+    }
+
+    /// Turns the switch off and aborts every in-flight task. Returns how many
+    /// were aborted.
+    pub(crate) fn go_offline(&self) -> Result<usize, CommandError> {
+        let mut registry = self.lock()?;
+        registry.offline = true;
+        let aborted = registry.tasks.len();
+        for (_, task) in registry.tasks.drain() {
+            task.abort();
+        }
+        Ok(aborted)
+    }
+
+    pub(crate) fn go_online(&self) -> Result<(), CommandError> {
+        self.lock()?.offline = false;
+        Ok(())
+    }
+}
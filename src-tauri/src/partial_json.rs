@@ -0,0 +1,180 @@
+// Best-effort parsing of a JSON document that is still streaming, so
+// structured output can be rendered before the model has closed it.
+
+/// Where `prefix` stands after scanning it: which containers are open and
+/// whether it ends inside a string.
+struct ScanState {
+    open: Vec<char>,
+    in_string: bool,
+    /// A trailing backslash whose escape hasn't arrived yet.
+    pending_escape: bool,
+    /// Byte offset of the backslash starting the last escape in the open string.
+    last_escape: Option<usize>,
+    /// Byte offsets to fall back to when the tail can't be completed: just
+    /// before each comma and just after each opening brace or bracket.
+    cuts: Vec<usize>,
+}
+
+fn scan(text: &str) -> ScanState {
+    let mut state = ScanState {
+        open: Vec::new(),
+        in_string: false,
+        pending_escape: false,
+        last_escape: None,
+        cuts: Vec::new(),
+    };
+    for (i, ch) in text.char_indices() {
+        if state.in_string {
+            if state.pending_escape {
+                state.pending_escape = false;
+            } else if ch == '\\' {
+                state.pending_escape = true;
+                state.last_escape = Some(i);
+            } else if ch == '"' {
+                state.in_string = false;
+                state.last_escape = None;
+            }
+            continue;
+        }
+        match ch {
+            '"' => state.in_string = true,
+            '{' | '[' => {
+                state.open.push(ch);
+                state.cuts.push(i + 1);
+            }
+            '}' | ']' => {
+                state.open.pop();
+            }
+            ',' => state.cuts.push(i),
+            _ => {}
+        }
+    }
+    state
+}
+
+/// Closes whatever `prefix` left open. The result may still be invalid, e.g.
+/// when it ends in a key with no value.
+fn close(prefix: &str) -> String {
+    let state = scan(prefix);
+    let mut out = prefix.to_string();
+    if state.in_string {
+        // A dangling `\` or a `\u` still short of its four hex digits.
+        if let Some(at) = state
+            .last_escape
+            .filter(|&at| unfinished_escape(&prefix[at..]))
+        {
+            out.truncate(at);
+        }
+        out.push('"');
+    } else {
+        let trimmed = out.trim_end().trim_end_matches(',').len();
+        out.truncate(trimmed);
+    }
+    for opener in state.open.iter().rev() {
+        out.push(if *opener == '{' { '}' } else { ']' });
+    }
+    out
+}
+
+fn unfinished_escape(tail: &str) -> bool {
+    match tail.strip_prefix("\\u") {
+        Some(hex) => hex.len() < 4 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => tail == "\\",
+    }
+}
+
+/// How many earlier cut points to try before giving up on a chunk.
+const MAX_FALLBACKS: usize = 4;
+
+/// Parses `text` as if the model stopped right now: open strings and
+/// containers are closed, and an incomplete trailing member is dropped.
+/// Returns `None` until the output has started an object or array.
+pub(crate) fn repair(text: &str) -> Option<serde_json::Value> {
+    let start = text.find(['{', '['])?;
+    let text = &text[start..];
+
+    if let Ok(value) = serde_json::from_str(&close(text)) {
+        return Some(value);
+    }
+    scan(text)
+        .cuts
+        .iter()
+        .rev()
+        .take(MAX_FALLBACKS)
+        .find_map(|&cut| serde_json::from_str(&close(&text[..cut])).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn nothing_until_a_container_opens() {
+        assert_eq!(repair(""), None);
+        assert_eq!(repair("Sure, here it is: "), None);
+        assert_eq!(repair("Sure: {"), Some(json!({})));
+    }
+
+    #[test]
+    fn open_strings_are_closed() {
+        assert_eq!(
+            repair(r#"{"name": "Ada Lov"#),
+            Some(json!({ "name": "Ada Lov" }))
+        );
+        assert_eq!(repair(r#"["one", "tw"#), Some(json!(["one", "tw"])));
+        assert_eq!(repair(r#"{"na"#), Some(json!({})));
+    }
+
+    #[test]
+    fn a_trailing_escape_is_dropped() {
+        assert_eq!(repair(r#"{"path": "C:\"#), Some(json!({ "path": "C:" })));
+        assert_eq!(repair(r#"{"q": "say \""#), Some(json!({ "q": "say \"" })));
+        assert_eq!(repair(r#"{"p": "a\\"#), Some(json!({ "p": "a\\" })));
+    }
+
+    #[test]
+    fn a_partial_unicode_escape_is_dropped() {
+        for partial in [r#"{"s": "caf\u"#, r#"{"s": "caf\u0"#, r#"{"s": "caf\u00e"#] {
+            assert_eq!(repair(partial), Some(json!({ "s": "caf" })), "{partial}");
+        }
+        assert_eq!(repair(r#"{"s": "caf\u00e9"#), Some(json!({ "s": "café" })));
+        // Only an escape counts: an escaped backslash followed by `u` is text.
+        assert_eq!(repair(r#"{"s": "a\\u"#), Some(json!({ "s": "a\\u" })));
+    }
+
+    #[test]
+    fn a_trailing_comma_is_dropped() {
+        assert_eq!(repair(r#"{"a": 1,"#), Some(json!({ "a": 1 })));
+        assert_eq!(repair("[1, 2, "), Some(json!([1, 2])));
+    }
+
+    #[test]
+    fn a_key_without_a_value_is_dropped() {
+        assert_eq!(repair(r#"{"a": 1, "b":"#), Some(json!({ "a": 1 })));
+        assert_eq!(repair(r#"{"a": 1, "b""#), Some(json!({ "a": 1 })));
+        assert_eq!(repair(r#"{"a": tr"#), Some(json!({})));
+    }
+
+    #[test]
+    fn nested_containers_are_closed_in_order() {
+        assert_eq!(repair("[[1, [2, 3"), Some(json!([[1, [2, 3]]])));
+        assert_eq!(
+            repair(r#"{"rows": [[1, 2], [3, "fo"#),
+            Some(json!({ "rows": [[1, 2], [3, "fo"]] }))
+        );
+        assert_eq!(
+            repair(r#"{"a": [{"b": "x,y", "c": ["#),
+            Some(json!({ "a": [{ "b": "x,y", "c": [] }] }))
+        );
+    }
+
+    #[test]
+    fn complete_documents_parse_as_is() {
+        let text = r#"{"a": [1, {"b": null}], "c": "}]"}"#;
+        assert_eq!(
+            repair(text),
+            Some(json!({ "a": [1, { "b": null }], "c": "}]" }))
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// Token buckets for the network-heavy commands, so a misbehaving frontend can't
+// hammer the Hugging Face API and get the user throttled or banned.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::CommandError;
+use crate::settings::Settings;
+
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub(crate) struct RateLimit {
+    /// Calls allowed back to back before the limit kicks in.
+    pub burst: u32,
+    /// Sustained rate the bucket refills at.
+    pub per_minute: u32,
+}
+
+/// Limits used unless `Settings::rate_limits` overrides them.
+const DEFAULT_LIMITS: &[(&str, RateLimit)] = &[
+    (
+        "model_download_start",
+        RateLimit {
+            burst: 5,
+            per_minute: 10,
+        },
+    ),
+    (
+        "http_request",
+        RateLimit {
+            burst: 20,
+            per_minute: 120,
+        },
+    ),
+    (
+        "http_download",
+        RateLimit {
+            burst: 5,
+            per_minute: 20,
+        },
+    ),
+    (
+        "measure_download_speed",
+        RateLimit {
+            burst: 2,
+            per_minute: 4,
+        },
+    ),
+    // Applies per repo fetched, not per call.
+    (
+        "prefetch_model_info",
+        RateLimit {
+            burst: 8,
+            per_minute: 60,
+        },
+    ),
+];
+
+pub(crate) fn is_limited_command(command: &str) -> bool {
+    DEFAULT_LIMITS.iter().any(|(name, _)| *name == command)
+}
+
+fn limit_for(command: &str, settings: &Settings) -> Option<RateLimit> {
+    settings.rate_limits.get(command).copied().or_else(|| {
+        DEFAULT_LIMITS
+            .iter()
+            .find(|(name, _)| *name == command)
+            .map(|(_, limit)| *limit)
+    })
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct RateLimiterState {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiterState {
+    /// Takes one token from `command`'s bucket, or fails with `RateLimited`
+    /// and how long until the next token is available.
+    pub(crate) fn acquire(&self, command: &str, settings: &Settings) -> Result<(), CommandError> {
+        let Some(limit) = limit_for(command, settings) else {
+            return Ok(());
+        };
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| "Rate limiter mutex poisoned".to_string())?;
+        let burst = f64::from(limit.burst.max(1));
+        let per_sec = f64::from(limit.per_minute.max(1)) / 60.0;
+
+        let now = Instant::now();
+        let bucket = buckets.entry(command.to_string()).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_sec).min(burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec);
+        Err(CommandError::RateLimited {
+            command: command.to_string(),
+            retry_after_ms: wait.as_millis() as u64 + 1,
+        })
+    }
+
+    /// Forgets `command`'s bucket so a changed limit applies from a full bucket.
+    pub(crate) fn reset(&self, command: &str) {
+        if let Ok(mut buckets) = self.buckets.lock() {
+            buckets.remove(command);
+        }
+    }
+}
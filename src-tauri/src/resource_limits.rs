@@ -0,0 +1,79 @@
+// Optional OS resource limits for the runner process, so a runaway model
+// can't take the whole machine down with it. On Unix they are applied with
+// setrlimit in the child between fork and exec; on Windows they are a no-op.
+use std::process::{Command, ExitStatus};
+
+#[derive(Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct ResourceLimits {
+    /// Address-space cap (`RLIMIT_AS`). This counts virtual memory, which
+    /// CUDA reserves generously, so leave it unset on GPU machines. macOS
+    /// largely ignores it.
+    pub max_memory_bytes: Option<u64>,
+    /// CPU time cap (`RLIMIT_CPU`); the runner gets `SIGXCPU` when it's hit.
+    pub max_cpu_seconds: Option<u64>,
+}
+
+/// Grace between the soft CPU limit (`SIGXCPU`) and the hard one (`SIGKILL`).
+#[cfg(unix)]
+const CPU_HARD_LIMIT_GRACE_SECS: u64 = 5;
+
+#[cfg(unix)]
+pub(crate) fn apply(cmd: &mut Command, limits: &ResourceLimits) {
+    use std::os::unix::process::CommandExt;
+
+    let limits = limits.clone();
+    // SAFETY: the hook runs in the forked child before exec and only calls
+    // setrlimit, which is async-signal-safe; it allocates nothing.
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(bytes) = limits.max_memory_bytes {
+                let limit = libc::rlimit {
+                    rlim_cur: bytes as libc::rlim_t,
+                    rlim_max: bytes as libc::rlim_t,
+                };
+                if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if let Some(secs) = limits.max_cpu_seconds {
+                let limit = libc::rlimit {
+                    rlim_cur: secs as libc::rlim_t,
+                    rlim_max: secs.saturating_add(CPU_HARD_LIMIT_GRACE_SECS) as libc::rlim_t,
+                };
+                if libc::setrlimit(libc::RLIMIT_CPU, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn apply(_cmd: &mut Command, _limits: &ResourceLimits) {}
+
+/// The limit (`memory` or `cpu`) that most likely ended a runner which exited
+/// with `status` on its own, judged from the signal that killed it.
+#[cfg(unix)]
+pub(crate) fn exceeded_limit(limits: &ResourceLimits, status: ExitStatus) -> Option<&'static str> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let cpu = limits.max_cpu_seconds.is_some();
+    let memory = limits.max_memory_bytes.is_some();
+    match status.signal()? {
+        libc::SIGXCPU if cpu => Some("cpu"),
+        // Allocation failures under RLIMIT_AS tend to surface as aborts or crashes.
+        libc::SIGKILL | libc::SIGABRT | libc::SIGSEGV if memory => Some("memory"),
+        libc::SIGKILL if cpu => Some("cpu"),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn exceeded_limit(
+    _limits: &ResourceLimits,
+    _status: ExitStatus,
+) -> Option<&'static str> {
+    None
+}
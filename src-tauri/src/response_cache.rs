@@ -0,0 +1,123 @@
+// Response cache for `chat_generate` requests that opt in with `cache: true`.
+// Finished generations are stored under app_cache_dir/generations, where
+// storage pruning sees them, keyed by a hash of the normalized request and the
+// model's weights, and replayed as `chat_token` events on a hit. Only
+// deterministic requests (temperature 0 or a caller-fixed seed) sent as
+// `messages` are cached, since those carry their whole context in the key.
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tauri::Manager;
+
+/// Bumped whenever the key's inputs or the entry format change.
+const KEY_VERSION: u32 = 2;
+
+/// How a cache hit is streamed back.
+#[derive(Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CacheReplay {
+    /// Every token right away.
+    #[default]
+    Instant,
+    /// With the gaps between tokens the original generation had.
+    Timed,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct CachedToken {
+    /// Since the generation started.
+    pub at_ms: u64,
+    pub text: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct CachedGeneration {
+    pub tokens: Vec<CachedToken>,
+    pub finish_reason: Option<String>,
+}
+
+impl CachedGeneration {
+    /// The tokens with the delay before each one. Timed replays start at the
+    /// first token, so a cold model load isn't replayed.
+    pub(crate) fn replay_tokens(self, mode: CacheReplay) -> Vec<(Duration, String)> {
+        let mut last = self.tokens.first().map_or(0, |t| t.at_ms);
+        self.tokens
+            .into_iter()
+            .map(|token| {
+                let delay = match mode {
+                    CacheReplay::Instant => Duration::ZERO,
+                    CacheReplay::Timed => Duration::from_millis(token.at_ms.saturating_sub(last)),
+                };
+                last = last.max(token.at_ms);
+                (delay, token.text)
+            })
+            .collect()
+    }
+}
+
+/// Whether a request's output is reproducible enough to cache.
+pub(crate) fn is_deterministic(temperature: f32, seed: Option<u64>) -> bool {
+    temperature == 0.0 || seed.is_some()
+}
+
+/// SHA-256 over the normalized request. The seed only counts when sampling,
+/// since greedy decoding ignores it.
+pub(crate) fn cache_key(
+    request: &serde_json::Value,
+    temperature: f32,
+    seed: Option<u64>,
+) -> String {
+    let normalized = serde_json::json!({
+        "v": KEY_VERSION,
+        "request": request,
+        "temperature": temperature,
+        "seed": if temperature == 0.0 { None } else { seed },
+    });
+    let digest = ring::digest::digest(&ring::digest::SHA256, normalized.to_string().as_bytes());
+    digest.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn entry_path(app: &tauri::AppHandle, key: &str) -> Result<PathBuf, String> {
+    let base = app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("Failed to resolve app_cache_dir: {e}"))?;
+    Ok(base.join("generations").join(format!("{key}.json")))
+}
+
+/// The stored generation for `key`. Unreadable entries count as a miss.
+pub(crate) fn load(app: &tauri::AppHandle, key: &str) -> Option<CachedGeneration> {
+    let raw = fs::read_to_string(entry_path(app, key).ok()?).ok()?;
+    serde_json::from_str(&raw).ok()
+}
+
+pub(crate) fn store(
+    app: &tauri::AppHandle,
+    key: &str,
+    entry: &CachedGeneration,
+) -> Result<(), String> {
+    let path = entry_path(app, key)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create cache dir: {e}"))?;
+    }
+    let raw = serde_json::to_string(entry).map_err(|e| format!("Serialize error: {e}"))?;
+    // Written aside and renamed, so a concurrent `load` never sees half an entry.
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, raw)
+        .and_then(|()| fs::rename(&tmp, &path))
+        .map_err(|e| format!("Failed to write cached generation: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_key_covers_the_weights_and_only_a_sampling_seed() {
+        let request = |weights: u64| serde_json::json!({ "model": "m", "weights": weights });
+        assert_ne!(cache_key(&request(1), 0.0, None), cache_key(&request(2), 0.0, None));
+        assert_eq!(cache_key(&request(1), 0.0, Some(7)), cache_key(&request(1), 0.0, None));
+        assert_ne!(cache_key(&request(1), 0.7, Some(7)), cache_key(&request(1), 0.7, Some(8)));
+    }
+}
@@ -0,0 +1,226 @@
+// User-tunable settings persisted to app_data_dir/settings.json.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::{fs, path::PathBuf};
+
+use tauri::Manager;
+
+use crate::rate_limit::RateLimit;
+use crate::resource_limits::ResourceLimits;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    /// Upper bound accepted for `max_new_tokens` in `chat_generate`.
+    pub max_new_tokens_ceiling: u32,
+    /// Clamp out-of-range generation params instead of rejecting the request.
+    pub clamp_generation_params: bool,
+    /// Hugging Face Hub base URL override (mirror / enterprise endpoint).
+    pub hf_endpoint: Option<String>,
+    /// Extra headers sent with every Hugging Face request. The values are often
+    /// credentials, so they are kept in the OS credential store: never written
+    /// to settings.json or returned by `get_settings`.
+    #[serde(skip_serializing)]
+    pub hf_headers: HashMap<String, String>,
+    /// Model downloaded by `setup_default_model` on first run.
+    pub default_model_repo_id: String,
+    /// Let the dropdown auto-hide on focus loss even while a generation is streaming.
+    pub hide_during_generation: bool,
+    /// Downloads beyond this many wait in a queue until one finishes.
+    pub max_concurrent_downloads: usize,
+    /// Refuse `http_request` targets that resolve to private/loopback/link-local addresses.
+    pub http_block_private: bool,
+    /// Exempt loopback from `http_block_private` so local backends keep working.
+    pub http_allow_localhost: bool,
+    /// Hosts or IPs `http_request` may reach even when they are internal.
+    pub http_allowlist: Vec<String>,
+    /// Keep streaming token/progress events to the main window while it is hidden.
+    pub emit_when_hidden: bool,
+    /// Extra environment for the runner process (`HF_HOME`, `CUDA_VISIBLE_DEVICES`, ...).
+    pub runner_env: HashMap<String, String>,
+    /// Working directory for the runner; inherited from the app when unset.
+    pub runner_cwd: Option<String>,
+    /// Runner script to use instead of searching the default locations.
+    pub runner_script_path: Option<String>,
+    /// Python interpreter (a path, or a name looked up on PATH) to run the
+    /// script with instead of searching for `python3`/`python`.
+    pub python_interpreter: Option<String>,
+    /// Respawn the runner after it crashes, up to `RUNNER_RESTART_ATTEMPTS` times in a row.
+    pub auto_restart_runner: bool,
+    /// OS limits the runner is started under (Unix only).
+    pub resource_limits: Option<ResourceLimits>,
+    /// Tray icon artwork; follows the system appearance when unset.
+    pub tray_icon_theme: Option<TrayIconTheme>,
+    /// Per-command overrides of the built-in rate limits.
+    pub rate_limits: HashMap<String, RateLimit>,
+    /// How streamed text is split into `chat_token` events.
+    pub chunk_mode: ChunkMode,
+    /// What closing the main window does.
+    pub close_behavior: CloseBehavior,
+    /// Where the main window opens.
+    pub window_placement: WindowPlacement,
+    /// System-wide shortcut that shows or hides the main window; `None` turns it off.
+    pub global_shortcut: Option<String>,
+    /// Refuse generations whose prompt plus `max_new_tokens` exceeds the
+    /// model's context length, instead of leaving it to the runner.
+    pub enforce_context: bool,
+    /// Unload the model after a generation runs out of memory, so the next
+    /// attempt starts from a clean slate.
+    pub unload_on_oom: bool,
+    /// Let empty prompts through for unconditioned generation.
+    pub allow_empty_prompt: bool,
+    /// Fill gaps between the runner's download progress events with estimates.
+    pub interpolate_download_progress: bool,
+    /// Seed `latency_stats` from the usage log at startup instead of starting empty.
+    pub persist_latency_stats: bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CloseBehavior {
+    /// Hide to the tray and keep running.
+    #[default]
+    Hide,
+    /// Drain the runner and exit.
+    Quit,
+    /// Keep the window and emit `cerebro:close_requested` for the UI to decide.
+    Ask,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WindowPlacement {
+    /// Next to the tray icon every time it opens.
+    #[default]
+    Tray,
+    /// Where it was last moved and sized to, across restarts.
+    Remember,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ChunkMode {
+    /// Every token as the runner produced it.
+    #[default]
+    Raw,
+    /// Only at word boundaries and outside unfinished code fences.
+    Markdown,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TrayIconTheme {
+    /// Dark glyph for light menu bars.
+    Light,
+    /// Light glyph for dark menu bars.
+    Dark,
+    /// macOS template image, recolored by the system to match the menu bar.
+    Template,
+    /// The full-color app icon.
+    Color,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_new_tokens_ceiling: 4096,
+            clamp_generation_params: false,
+            hf_endpoint: None,
+            hf_headers: HashMap::new(),
+            default_model_repo_id: "Qwen/Qwen2.5-1.5B-Instruct".to_string(),
+            hide_during_generation: false,
+            max_concurrent_downloads: 2,
+            http_block_private: true,
+            http_allow_localhost: true,
+            http_allowlist: Vec::new(),
+            emit_when_hidden: false,
+            runner_env: HashMap::new(),
+            runner_cwd: None,
+            runner_script_path: None,
+            python_interpreter: None,
+            auto_restart_runner: true,
+            resource_limits: None,
+            tray_icon_theme: None,
+            rate_limits: HashMap::new(),
+            chunk_mode: ChunkMode::Raw,
+            close_behavior: CloseBehavior::Hide,
+            window_placement: WindowPlacement::Tray,
+            global_shortcut: Some("CommandOrControl+Shift+Space".to_string()),
+            enforce_context: false,
+            unload_on_oom: false,
+            allow_empty_prompt: false,
+            interpolate_download_progress: false,
+            persist_latency_stats: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SettingsState {
+    inner: Mutex<Settings>,
+}
+
+fn settings_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let base = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+    Ok(base.join("settings.json"))
+}
+
+impl SettingsState {
+    /// Reads settings from disk, falling back to defaults when missing or unreadable.
+    pub(crate) fn load(app: &tauri::AppHandle) -> Self {
+        let mut settings = settings_path(app)
+            .ok()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|raw| serde_json::from_str::<Settings>(&raw).ok())
+            .unwrap_or_default();
+        if settings.hf_headers.is_empty() {
+            settings.hf_headers = crate::hf_token::load_headers();
+        } else {
+            // Written in plain text by an older version: move them to the
+            // credential store and scrub them from settings.json.
+            let moved = crate::hf_token::store_headers(&settings.hf_headers)
+                .and_then(|()| write(app, &settings));
+            if let Err(e) = moved {
+                eprintln!("{e}");
+            }
+        }
+        Self {
+            inner: Mutex::new(settings),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Settings {
+        self.inner
+            .lock()
+            .map(|s| s.clone())
+            .unwrap_or_default()
+    }
+
+    /// Applies `f` and writes the result to disk, returning the updated settings.
+    pub(crate) fn update(
+        &self,
+        app: &tauri::AppHandle,
+        f: impl FnOnce(&mut Settings),
+    ) -> Result<Settings, String> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| "Settings mutex poisoned".to_string())?;
+        f(&mut inner);
+        write(app, &inner)?;
+        Ok(inner.clone())
+    }
+}
+
+fn write(app: &tauri::AppHandle, settings: &Settings) -> Result<(), String> {
+    let path = settings_path(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create settings dir: {e}"))?;
+    }
+    let raw =
+        serde_json::to_string_pretty(settings).map_err(|e| format!("Serialize error: {e}"))?;
+    fs::write(&path, raw).map_err(|e| format!("Failed to write settings: {e}"))
+}
@@ -0,0 +1,218 @@
+// Maintenance pruning for caches, logs and saved conversations. Model folders
+// under app_data_dir/models are never touched, even if a pruned directory
+// happens to contain them.
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::models::{self, ModelInfo};
+
+/// Emit `cerebro:prune_progress` every this many files.
+pub(crate) const PROGRESS_EVERY: usize = 100;
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct PrunePolicy {
+    /// Remove cache files not modified for this many days. `None` skips caches.
+    pub cache_max_age_days: Option<u64>,
+    /// Truncate log files larger than this, keeping the most recent bytes.
+    pub log_max_bytes: Option<u64>,
+    /// Delete saved conversations older than this. Off unless asked for.
+    pub conversation_max_age_days: Option<u64>,
+}
+
+impl Default for PrunePolicy {
+    fn default() -> Self {
+        Self {
+            cache_max_age_days: Some(30),
+            log_max_bytes: Some(10 * 1024 * 1024),
+            conversation_max_age_days: None,
+        }
+    }
+}
+
+#[derive(Default, serde::Serialize)]
+pub(crate) struct PruneReport {
+    pub cache_bytes: u64,
+    pub log_bytes: u64,
+    pub conversation_bytes: u64,
+    pub files_removed: usize,
+}
+
+pub(crate) struct StorageDirs {
+    pub cache: PathBuf,
+    pub logs: PathBuf,
+    pub conversations: PathBuf,
+    pub models: PathBuf,
+}
+
+fn older_than(path: &Path, cutoff: SystemTime) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| modified < cutoff)
+}
+
+fn cutoff(days: u64) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(Duration::from_secs(days * 24 * 60 * 60))
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Keeps the last `cap` bytes of `path`. Returns the bytes freed.
+fn truncate_log(path: &Path, len: u64, cap: u64) -> std::io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(len - cap))?;
+    let mut tail = Vec::with_capacity(cap as usize);
+    file.read_to_end(&mut tail)?;
+    drop(file);
+    fs::write(path, &tail)?;
+    Ok(len.saturating_sub(tail.len() as u64))
+}
+
+/// Applies `policy` to `dirs`. `progress(category, done, total)` is called
+/// every `PROGRESS_EVERY` files and once at the end of each category.
+pub(crate) fn prune(
+    dirs: &StorageDirs,
+    policy: &PrunePolicy,
+    mut progress: impl FnMut(&str, usize, usize),
+) -> PruneReport {
+    let mut report = PruneReport::default();
+    let is_model_file = |path: &Path| path.starts_with(&dirs.models);
+
+    let aged = [
+        ("cache", &dirs.cache, policy.cache_max_age_days),
+        (
+            "conversations",
+            &dirs.conversations,
+            policy.conversation_max_age_days,
+        ),
+    ];
+    for (category, dir, max_age) in aged {
+        let Some(days) = max_age else {
+            continue;
+        };
+        let cutoff = cutoff(days);
+        let files = models::walk_files(dir);
+        let total = files.len();
+        for (i, (path, len)) in files.into_iter().enumerate() {
+            if !is_model_file(&path) && older_than(&path, cutoff) && fs::remove_file(&path).is_ok()
+            {
+                report.files_removed += 1;
+                match category {
+                    "cache" => report.cache_bytes += len,
+                    _ => report.conversation_bytes += len,
+                }
+            }
+            if (i + 1) % PROGRESS_EVERY == 0 {
+                progress(category, i + 1, total);
+            }
+        }
+        progress(category, total, total);
+    }
+
+    if let Some(cap) = policy.log_max_bytes {
+        let files = models::walk_files(&dirs.logs);
+        let total = files.len();
+        for (i, (path, len)) in files.into_iter().enumerate() {
+            if !is_model_file(&path) && len > cap {
+                if let Ok(freed) = truncate_log(&path, len, cap) {
+                    report.log_bytes += freed;
+                }
+            }
+            if (i + 1) % PROGRESS_EVERY == 0 {
+                progress("logs", i + 1, total);
+            }
+        }
+        progress("logs", total, total);
+    }
+
+    report
+}
+
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct ModelBytes {
+    pub repo_id: Option<String>,
+    pub local_dir: String,
+    pub bytes: u64,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct StorageSummary {
+    pub models_dir: String,
+    /// Everything under `models_dir`, pinned revisions and partial downloads included.
+    pub total_models_bytes: u64,
+    /// Largest first.
+    pub per_model: Vec<ModelBytes>,
+    /// Free space on the volume holding `models_dir`, if it could be read.
+    pub free_bytes: Option<u64>,
+    pub cache_bytes: u64,
+    pub logs_bytes: u64,
+}
+
+fn dir_bytes(dir: &Path) -> u64 {
+    models::walk_files(dir).iter().map(|(_, len)| len).sum()
+}
+
+/// Free space on the volume holding `path`, measured at its nearest existing
+/// ancestor so a store that hasn't been created yet still reports.
+pub(crate) fn free_bytes(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    fs4::available_space(existing).ok()
+}
+
+/// Walks the model store, caches and logs. Slow on big stores; run it off the
+/// main thread.
+pub(crate) fn summarize(dirs: &StorageDirs, installed: Vec<ModelInfo>) -> StorageSummary {
+    let mut per_model: Vec<ModelBytes> = installed
+        .into_iter()
+        .map(|m| ModelBytes {
+            bytes: dir_bytes(Path::new(&m.local_dir)),
+            repo_id: m.repo_id,
+            local_dir: m.local_dir,
+        })
+        .collect();
+    per_model.sort_by_key(|m| std::cmp::Reverse(m.bytes));
+
+    StorageSummary {
+        models_dir: dirs.models.to_string_lossy().to_string(),
+        total_models_bytes: dir_bytes(&dirs.models),
+        per_model,
+        free_bytes: free_bytes(&dirs.models),
+        cache_bytes: dir_bytes(&dirs.cache),
+        logs_bytes: dir_bytes(&dirs.logs),
+    }
+}
+
+/// The last `storage_summary`, dropped whenever models are added or removed.
+/// The epoch keeps a walk that raced an invalidation from being cached.
+#[derive(Default)]
+pub(crate) struct StorageSummaryCache {
+    inner: Mutex<(u64, Option<StorageSummary>)>,
+}
+
+impl StorageSummaryCache {
+    /// The cached summary, or the epoch to hand to `insert` after a fresh walk.
+    pub(crate) fn get(&self) -> Result<StorageSummary, u64> {
+        let Ok(inner) = self.inner.lock() else {
+            return Err(0);
+        };
+        inner.1.clone().ok_or(inner.0)
+    }
+
+    pub(crate) fn insert(&self, epoch: u64, summary: StorageSummary) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if inner.0 == epoch {
+                inner.1 = Some(summary);
+            }
+        }
+    }
+
+    pub(crate) fn invalidate(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.0 += 1;
+            inner.1 = None;
+        }
+    }
+}
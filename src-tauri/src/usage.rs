@@ -0,0 +1,110 @@
+// Persistent usage log for finished generations, one JSON object per line in
+// app_data_dir/usage.jsonl, aggregated on demand for the stats view.
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use tauri::Manager;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct UsageEntry {
+    pub model: String,
+    /// Streamed chunks, roughly one per token.
+    pub completion_tokens: u64,
+    pub duration_ms: u64,
+    pub ttft_ms: Option<u64>,
+    /// Unix seconds.
+    pub finished_at: u64,
+}
+
+#[derive(Default, serde::Serialize)]
+pub(crate) struct ModelUsage {
+    pub model: String,
+    pub generations: u64,
+    pub completion_tokens: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Default, serde::Serialize)]
+pub(crate) struct UsageStats {
+    pub since: Option<u64>,
+    pub generations: u64,
+    pub completion_tokens: u64,
+    pub duration_ms: u64,
+    /// Sorted by model id.
+    pub per_model: Vec<ModelUsage>,
+}
+
+fn usage_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let base = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+    Ok(base.join("usage.jsonl"))
+}
+
+pub(crate) fn append(app: &tauri::AppHandle, entry: &UsageEntry) -> Result<(), String> {
+    let path = usage_path(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create app data dir: {e}"))?;
+    }
+    let mut line = serde_json::to_string(entry).map_err(|e| format!("Serialize error: {e}"))?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| format!("Failed to write usage log: {e}"))
+}
+
+/// Every logged entry, oldest first. Lines that don't parse, e.g. one cut
+/// short by a crash, are skipped.
+pub(crate) fn entries(app: &tauri::AppHandle) -> Result<Vec<UsageEntry>, String> {
+    let raw = match fs::read_to_string(usage_path(app)?) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read usage log: {e}")),
+    };
+    Ok(raw
+        .lines()
+        .filter_map(|line| serde_json::from_str::<UsageEntry>(line).ok())
+        .collect())
+}
+
+/// Totals over entries that finished at or after `since` (unix seconds).
+pub(crate) fn stats(app: &tauri::AppHandle, since: Option<u64>) -> Result<UsageStats, String> {
+    let mut stats = UsageStats {
+        since,
+        ..UsageStats::default()
+    };
+    let mut per_model: BTreeMap<String, ModelUsage> = BTreeMap::new();
+    for entry in entries(app)?
+        .into_iter()
+        .filter(|entry| since.is_none_or(|since| entry.finished_at >= since))
+    {
+        stats.generations += 1;
+        stats.completion_tokens += entry.completion_tokens;
+        stats.duration_ms += entry.duration_ms;
+        let model = per_model
+            .entry(entry.model.clone())
+            .or_insert_with(|| ModelUsage {
+                model: entry.model,
+                ..ModelUsage::default()
+            });
+        model.generations += 1;
+        model.completion_tokens += entry.completion_tokens;
+        model.duration_ms += entry.duration_ms;
+    }
+    stats.per_model = per_model.into_values().collect();
+    Ok(stats)
+}
+
+pub(crate) fn clear(app: &tauri::AppHandle) -> Result<(), String> {
+    match fs::remove_file(usage_path(app)?) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to clear usage log: {e}")),
+    }
+}
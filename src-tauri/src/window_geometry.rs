@@ -0,0 +1,92 @@
+// Main window position and size, persisted to app_data_dir/window.json for
+// `WindowPlacement::Remember` so the window reopens where the user left it.
+use std::fs;
+use std::path::PathBuf;
+
+use tauri::Manager;
+
+/// Pixels of the window that must land on some monitor for a saved position
+/// to be restored, so a monitor that went away doesn't strand it off-screen.
+const MIN_VISIBLE_PX: i64 = 50;
+
+/// Physical pixels: the outer position and the inner size.
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub(crate) struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn geometry_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let base = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app_data_dir: {e}"))?;
+    Ok(base.join("window.json"))
+}
+
+pub(crate) fn load(app: &tauri::AppHandle) -> Option<WindowGeometry> {
+    let raw = fs::read_to_string(geometry_path(app).ok()?).ok()?;
+    serde_json::from_str(&raw).ok()
+}
+
+pub(crate) fn capture(window: &tauri::WebviewWindow) -> Option<WindowGeometry> {
+    let position = window.outer_position().ok()?;
+    let size = window.inner_size().ok()?;
+    Some(WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+pub(crate) fn save(app: &tauri::AppHandle, geometry: &WindowGeometry) -> Result<(), String> {
+    let path = geometry_path(app)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create app data dir: {e}"))?;
+    }
+    let raw = serde_json::to_string(geometry).map_err(|e| format!("Serialize error: {e}"))?;
+    fs::write(&path, raw).map_err(|e| format!("Failed to write window geometry: {e}"))
+}
+
+/// Whether enough of `geometry` falls on one of the current monitors.
+pub(crate) fn is_on_screen(window: &tauri::WebviewWindow, geometry: &WindowGeometry) -> bool {
+    let (x, y) = (i64::from(geometry.x), i64::from(geometry.y));
+    let (w, h) = (i64::from(geometry.width), i64::from(geometry.height));
+    window
+        .available_monitors()
+        .unwrap_or_default()
+        .iter()
+        .any(|monitor| {
+            let (mx, my) = (
+                i64::from(monitor.position().x),
+                i64::from(monitor.position().y),
+            );
+            let (mw, mh) = (
+                i64::from(monitor.size().width),
+                i64::from(monitor.size().height),
+            );
+            let overlap_w = (x + w).min(mx + mw) - x.max(mx);
+            let overlap_h = (y + h).min(my + mh) - y.max(my);
+            overlap_w >= MIN_VISIBLE_PX && overlap_h >= MIN_VISIBLE_PX
+        })
+}
+
+/// Moves and resizes `window` to the saved geometry. False when there is none
+/// or it would be off-screen, leaving the window where it was.
+pub(crate) fn restore(app: &tauri::AppHandle, window: &tauri::WebviewWindow) -> bool {
+    let Some(geometry) = load(app).filter(|g| is_on_screen(window, g)) else {
+        return false;
+    };
+    let _ = window.set_size(tauri::Size::Physical(tauri::PhysicalSize {
+        width: geometry.width,
+        height: geometry.height,
+    }));
+    let _ = window.set_position(tauri::Position::Physical(tauri::PhysicalPosition {
+        x: geometry.x,
+        y: geometry.y,
+    }));
+    true
+}
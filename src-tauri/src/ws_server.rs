@@ -0,0 +1,627 @@
+// Optional local WebSocket server so scripts and other tools can use Cerebro's
+// models. It binds 127.0.0.1 on a random port and requires a per-start token.
+//
+// Protocol: JSON text frames. A client sends
+//   {"type":"generate", "request_id"?, ...chat_generate payload}
+//   {"type":"cancel", "generation_id"}
+// and receives {"type":"started"|"rejected", "request_id", ...} replies plus the
+// usual chat_token / done / error / cancelled messages for its own generations.
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use base64::Engine;
+use ring::rand::SecureRandom;
+use tauri::{Listener, Manager};
+
+use crate::settings::SettingsState;
+use crate::{ChatGeneratePayload, PythonRuntimeState};
+
+/// Largest client frame accepted; generate requests are small.
+const MAX_FRAME_BYTES: u64 = 1024 * 1024;
+/// Cap on the upgrade request line plus headers.
+const MAX_HANDSHAKE_BYTES: u64 = 8 * 1024;
+/// How long a new connection gets to send its upgrade request.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Events forwarded to the client that started the generation.
+const FORWARDED_EVENTS: &[&str] = &[
+    "cerebro:chat_token",
+    "cerebro:chat_progress",
+    "cerebro:chat_partial_json",
+    "cerebro:chat_done",
+    "cerebro:chat_error",
+    "cerebro:chat_cancelled",
+];
+
+#[derive(Clone, serde::Serialize)]
+pub(crate) struct WsServerInfo {
+    pub port: u16,
+    /// Pass as `?token=` or `Authorization: Bearer`.
+    pub token: String,
+}
+
+struct RunningServer {
+    info: WsServerInfo,
+    stop: Arc<AtomicBool>,
+    /// Open connections, shut down when the server stops.
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+#[derive(Default)]
+pub(crate) struct WsServerState {
+    inner: Mutex<Option<RunningServer>>,
+}
+
+fn random_token() -> Result<String, String> {
+    let mut bytes = [0u8; 24];
+    ring::rand::SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| "Failed to generate a WebSocket token".to_string())?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+impl WsServerState {
+    /// Starts the server, or returns the running one's details.
+    pub(crate) fn start(&self, app: &tauri::AppHandle) -> Result<WsServerInfo, String> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| "WebSocket server mutex poisoned".to_string())?;
+        if let Some(server) = inner.as_ref() {
+            return Ok(server.info.clone());
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .map_err(|e| format!("Failed to bind WebSocket server: {e}"))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| format!("Failed to configure WebSocket server: {e}"))?;
+        let info = WsServerInfo {
+            port: listener
+                .local_addr()
+                .map_err(|e| format!("Failed to read WebSocket server port: {e}"))?
+                .port(),
+            token: random_token()?,
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let clients: Arc<Mutex<Vec<TcpStream>>> = Arc::default();
+        let (app, token) = (app.clone(), info.token.clone());
+        let (stop_flag, open) = (stop.clone(), clients.clone());
+        thread::spawn(move || {
+            while !stop_flag.load(Ordering::SeqCst) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL);
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+                if stream.set_nonblocking(false).is_err() {
+                    continue;
+                }
+                if let (Ok(clone), Ok(mut open)) = (stream.try_clone(), open.lock()) {
+                    open.retain(|s| s.peer_addr().is_ok());
+                    open.push(clone);
+                }
+                let (app, token) = (app.clone(), token.clone());
+                thread::spawn(move || serve_client(app, stream, &token));
+            }
+        });
+
+        *inner = Some(RunningServer {
+            info: info.clone(),
+            stop,
+            clients,
+        });
+        Ok(info)
+    }
+
+    /// Stops accepting connections and drops the open ones. Their generations
+    /// keep running; only the streams go away.
+    pub(crate) fn stop(&self) -> Result<bool, String> {
+        let server = self
+            .inner
+            .lock()
+            .map_err(|_| "WebSocket server mutex poisoned".to_string())?
+            .take();
+        let Some(server) = server else {
+            return Ok(false);
+        };
+        server.stop.store(true, Ordering::SeqCst);
+        if let Ok(clients) = server.clients.lock() {
+            for client in clients.iter() {
+                let _ = client.shutdown(Shutdown::Both);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Reads the HTTP upgrade request and answers it. Returns false (after
+/// replying with an error status) if the client may not connect, or without a
+/// reply if the request runs past `MAX_HANDSHAKE_BYTES`.
+fn handshake(stream: &mut (impl Read + Write), token: &str) -> bool {
+    let mut reader = BufReader::new((&mut *stream).take(MAX_HANDSHAKE_BYTES));
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return false;
+    }
+    let mut key = None;
+    let mut authorized = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return false;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("sec-websocket-key") {
+            key = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorized |= value
+                .strip_prefix("Bearer ")
+                .is_some_and(|given| tokens_match(given, token));
+        }
+    }
+
+    drop(reader);
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("");
+    authorized |= target
+        .split_once('?')
+        .map(|(_, query)| query.split('&'))
+        .into_iter()
+        .flatten()
+        .filter_map(|pair| pair.strip_prefix("token="))
+        .any(|given| tokens_match(given, token));
+
+    let reply = match (request_line.starts_with("GET "), key, authorized) {
+        (true, Some(key), true) => {
+            let digest = ring::digest::digest(
+                &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+                format!("{key}{HANDSHAKE_GUID}").as_bytes(),
+            );
+            let accept = base64::engine::general_purpose::STANDARD.encode(digest.as_ref());
+            let reply = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+            );
+            return stream.write_all(reply.as_bytes()).is_ok();
+        }
+        (_, _, false) => "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n",
+        _ => "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n",
+    };
+    let _ = stream.write_all(reply.as_bytes());
+    false
+}
+
+enum Frame {
+    Text(String),
+    Ping(Vec<u8>),
+    /// Binary and pong frames carry nothing we use.
+    Ignored,
+    Close,
+}
+
+/// Reads one client frame. Fragmented messages aren't supported and end the
+/// connection, as does anything over `MAX_FRAME_BYTES`.
+fn read_frame(stream: &mut impl Read) -> Option<Frame> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).ok()?;
+    let (fin, opcode) = (head[0] & 0x80 != 0, head[0] & 0x0f);
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut ext = [0u8; 2];
+            stream.read_exact(&mut ext).ok()?;
+            u64::from(u16::from_be_bytes(ext))
+        }
+        127 => {
+            let mut ext = [0u8; 8];
+            stream.read_exact(&mut ext).ok()?;
+            u64::from_be_bytes(ext)
+        }
+        n => u64::from(n),
+    };
+    // Clients must mask their frames (RFC 6455 5.1).
+    if !fin || !masked || len > MAX_FRAME_BYTES {
+        return None;
+    }
+    let mut mask = [0u8; 4];
+    stream.read_exact(&mut mask).ok()?;
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload).ok()?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    match opcode {
+        0x1 => String::from_utf8(payload).ok().map(Frame::Text),
+        0x8 => Some(Frame::Close),
+        0x9 => Some(Frame::Ping(payload)),
+        0x2 | 0xa => Some(Frame::Ignored),
+        _ => None,
+    }
+}
+
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= usize::from(u16::MAX) => {
+            frame.push(126);
+            frame.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            frame.push(127);
+            frame.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// What the writer thread sends: a JSON text message or a raw control frame.
+enum Outgoing {
+    Text(String),
+    Control(u8, Vec<u8>),
+}
+
+fn serve_client(app: tauri::AppHandle, mut stream: TcpStream, token: &str) {
+    // A client that connects and never finishes the upgrade mustn't pin this thread.
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    if !handshake(&mut stream, token) {
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    if stream.set_read_timeout(None).is_err() {
+        return;
+    }
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    let (tx, rx) = mpsc::channel::<Outgoing>();
+    thread::spawn(move || {
+        for message in rx {
+            let result = match message {
+                Outgoing::Text(text) => write_frame(&mut writer, 0x1, text.as_bytes()),
+                Outgoing::Control(opcode, payload) => write_frame(&mut writer, opcode, &payload),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown(Shutdown::Both);
+    });
+
+    // Generations this client started; only their events are forwarded.
+    let owned: Arc<Mutex<HashSet<String>>> = Arc::default();
+    let listeners: Vec<_> = FORWARDED_EVENTS
+        .iter()
+        .map(|event| {
+            let (tx, owned) = (tx.clone(), owned.clone());
+            let finished = !matches!(
+                *event,
+                "cerebro:chat_token" | "cerebro:chat_progress" | "cerebro:chat_partial_json"
+            );
+            app.listen_any(*event, move |event| {
+                let Ok(v) = serde_json::from_str::<serde_json::Value>(event.payload()) else {
+                    return;
+                };
+                let Some(id) = v.get("generation_id").and_then(|x| x.as_str()) else {
+                    return;
+                };
+                let Ok(mut owned) = owned.lock() else {
+                    return;
+                };
+                let ours = if finished {
+                    owned.remove(id)
+                } else {
+                    owned.contains(id)
+                };
+                if ours {
+                    let _ = tx.send(Outgoing::Text(event.payload().to_string()));
+                }
+            })
+        })
+        .collect();
+
+    while let Some(frame) = read_frame(&mut stream) {
+        match frame {
+            Frame::Ignored => {}
+            Frame::Text(text) => {
+                let reply = handle_message(&app, &owned, &text);
+                if tx.send(Outgoing::Text(reply.to_string())).is_err() {
+                    break;
+                }
+            }
+            Frame::Ping(payload) => {
+                let _ = tx.send(Outgoing::Control(0xa, payload));
+            }
+            Frame::Close => {
+                let _ = tx.send(Outgoing::Control(0x8, Vec::new()));
+                break;
+            }
+        }
+    }
+
+    for id in listeners {
+        app.unlisten(id);
+    }
+}
+
+fn handle_message(
+    app: &tauri::AppHandle,
+    owned: &Mutex<HashSet<String>>,
+    text: &str,
+) -> serde_json::Value {
+    let rejected = |request_id: &serde_json::Value, message: String| {
+        serde_json::json!({
+            "type": "rejected",
+            "request_id": request_id,
+            "message": message,
+        })
+    };
+    let Ok(msg) = serde_json::from_str::<serde_json::Value>(text) else {
+        return rejected(&serde_json::Value::Null, "Invalid JSON".to_string());
+    };
+    let request_id = msg.get("request_id").cloned().unwrap_or_default();
+    let runtime = app.state::<PythonRuntimeState>();
+
+    match msg.get("type").and_then(|x| x.as_str()) {
+        Some("generate") => {
+            let mut payload = match serde_json::from_value::<ChatGeneratePayload>(msg.clone()) {
+                Ok(payload) => payload,
+                Err(e) => return rejected(&request_id, format!("Invalid generate request: {e}")),
+            };
+            // The id is claimed before starting so no early event slips past the filter.
+            let generation_id = crate::generate_id();
+            payload.external = true;
+            payload.generation_id = Some(generation_id.clone());
+            if let Ok(mut owned) = owned.lock() {
+                owned.insert(generation_id.clone());
+            }
+            let settings = app.state::<SettingsState>();
+            match crate::generate_and_retain(app.clone(), &runtime, &settings, payload) {
+                Ok(started) => {
+                    serde_json::json!({
+                        "type": "started",
+                        "request_id": request_id,
+                        "generation_id": started.generation_id,
+                        "seed": started.seed,
+                    })
+                }
+                Err(e) => {
+                    if let Ok(mut owned) = owned.lock() {
+                        owned.remove(&generation_id);
+                    }
+                    rejected(&request_id, e.to_string())
+                }
+            }
+        }
+        Some("cancel") => {
+            let Some(id) = msg.get("generation_id").and_then(|x| x.as_str()) else {
+                return rejected(&request_id, "cancel needs a generation_id".to_string());
+            };
+            if !owned.lock().is_ok_and(|owned| owned.contains(id)) {
+                return rejected(&request_id, format!("Unknown generation {id}"));
+            }
+            let result = runtime
+                .inner
+                .lock()
+                .map_err(|_| "Python runtime mutex poisoned".to_string())
+                .and_then(|mut inner| crate::cancel_generation(&mut inner, id));
+            match result {
+                Ok(()) => {
+                    crate::spawn_cancel_ack_watchdog(
+                        app.clone(),
+                        runtime.inner.clone(),
+                        id.to_string(),
+                    );
+                    serde_json::json!({
+                        "type": "cancel_requested",
+                        "request_id": request_id,
+                        "generation_id": id,
+                    })
+                }
+                Err(e) => rejected(&request_id, e),
+            }
+        }
+        _ => rejected(&request_id, "Unknown message type".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const TOKEN: &str = "secret-token";
+
+    /// A client frame: final, and masked unless `masked` is false.
+    fn client_frame(opcode: u8, payload: &[u8], masked: bool) -> Vec<u8> {
+        let mask_bit = if masked { 0x80 } else { 0 };
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            n if n < 126 => frame.push(mask_bit | n as u8),
+            n if n <= usize::from(u16::MAX) => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            n => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+        }
+        if !masked {
+            frame.extend_from_slice(payload);
+            return frame;
+        }
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn frames_of_each_length_encoding_round_trip() {
+        for len in [0, 125, 126, 65535, 65536] {
+            let text = "x".repeat(len);
+            let frame = client_frame(0x1, text.as_bytes(), true);
+            match read_frame(&mut Cursor::new(frame)) {
+                Some(Frame::Text(got)) => assert_eq!(got.len(), len),
+                _ => panic!("no text frame for length {len}"),
+            }
+
+            let mut out = Vec::new();
+            write_frame(&mut out, 0x1, text.as_bytes()).unwrap();
+            assert_eq!(
+                out,
+                client_frame(0x1, text.as_bytes(), false),
+                "length {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn write_frame_picks_the_shortest_length_encoding() {
+        let header = |len: usize| {
+            let mut out = Vec::new();
+            write_frame(&mut out, 0x1, &vec![b'a'; len]).unwrap();
+            out[..out.len() - len].to_vec()
+        };
+        assert_eq!(header(125), [0x81, 125]);
+        assert_eq!(header(126), [0x81, 126, 0, 126]);
+        assert_eq!(header(65536), [0x81, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn unmasked_fragmented_and_oversized_frames_are_refused() {
+        let unmasked = client_frame(0x1, b"hi", false);
+        assert!(read_frame(&mut Cursor::new(unmasked)).is_none());
+
+        let mut fragment = client_frame(0x1, b"hi", true);
+        fragment[0] &= 0x7f;
+        assert!(read_frame(&mut Cursor::new(fragment)).is_none());
+
+        // Refused from the header alone, before any payload is read.
+        let mut oversized = vec![0x81, 0x80 | 127];
+        oversized.extend_from_slice(&(MAX_FRAME_BYTES + 1).to_be_bytes());
+        assert!(read_frame(&mut Cursor::new(oversized)).is_none());
+
+        let truncated = client_frame(0x1, b"hello", true);
+        assert!(read_frame(&mut Cursor::new(&truncated[..truncated.len() - 1])).is_none());
+    }
+
+    #[test]
+    fn control_frames_are_recognised() {
+        let ping = client_frame(0x9, b"p", true);
+        assert!(matches!(read_frame(&mut Cursor::new(ping)), Some(Frame::Ping(p)) if p == b"p"));
+        let close = client_frame(0x8, b"", true);
+        assert!(matches!(
+            read_frame(&mut Cursor::new(close)),
+            Some(Frame::Close)
+        ));
+        let binary = client_frame(0x2, b"\x00", true);
+        assert!(matches!(
+            read_frame(&mut Cursor::new(binary)),
+            Some(Frame::Ignored)
+        ));
+    }
+
+    /// A request fed to `handshake`, with whatever it writes back.
+    struct Exchange {
+        request: Cursor<Vec<u8>>,
+        reply: Vec<u8>,
+    }
+
+    impl Read for Exchange {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.request.read(buf)
+        }
+    }
+
+    impl Write for Exchange {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.reply.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn shake(request: &str) -> (bool, String) {
+        let mut exchange = Exchange {
+            request: Cursor::new(request.as_bytes().to_vec()),
+            reply: Vec::new(),
+        };
+        let accepted = handshake(&mut exchange, TOKEN);
+        (accepted, String::from_utf8(exchange.reply).unwrap())
+    }
+
+    // The sample key from RFC 6455 1.3.
+    const KEY: &str = "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+    #[test]
+    fn handshake_accepts_the_token_in_the_query_or_a_bearer_header() {
+        let (accepted, reply) = shake(&format!("GET /?token={TOKEN} HTTP/1.1\r\n{KEY}\r\n"));
+        assert!(accepted);
+        assert!(reply.starts_with("HTTP/1.1 101 "), "{reply}");
+        assert!(reply.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let bearer = format!("GET / HTTP/1.1\r\nAuthorization: Bearer {TOKEN}\r\n{KEY}\r\n");
+        assert!(shake(&bearer).0);
+    }
+
+    #[test]
+    fn handshake_rejects_a_bad_token_or_a_missing_key() {
+        let (accepted, reply) = shake(&format!("GET /?token=nope HTTP/1.1\r\n{KEY}\r\n"));
+        assert!(!accepted);
+        assert!(reply.starts_with("HTTP/1.1 401 "), "{reply}");
+        assert!(!shake(&format!("GET / HTTP/1.1\r\n{KEY}\r\n")).0);
+
+        let (accepted, reply) = shake(&format!("GET /?token={TOKEN} HTTP/1.1\r\n\r\n"));
+        assert!(!accepted);
+        assert!(reply.starts_with("HTTP/1.1 400 "), "{reply}");
+
+        let post = format!("POST /?token={TOKEN} HTTP/1.1\r\n{KEY}\r\n");
+        assert!(!shake(&post).0);
+    }
+
+    #[test]
+    fn handshake_gives_up_on_oversized_or_unfinished_requests() {
+        let padding = format!("X-Pad: {}\r\n", "a".repeat(MAX_HANDSHAKE_BYTES as usize));
+        let (accepted, reply) = shake(&format!(
+            "GET /?token={TOKEN} HTTP/1.1\r\n{padding}{KEY}\r\n"
+        ));
+        assert!(!accepted);
+        assert!(reply.is_empty());
+
+        assert!(!shake(&format!("GET /?token={TOKEN} HTTP/1.1\r\n{KEY}")).0);
+    }
+}